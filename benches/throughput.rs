@@ -0,0 +1,82 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use redis::{Client, Connection, Pipeline};
+use std::{
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Commands each client sends at a time
+const PIPELINE: usize = 100;
+
+// Runs the server for as long as the benchmark does. It has to listen on the default
+// port, as any other port starts a replica.
+struct Server(Child);
+
+impl Server {
+    fn start() -> Self {
+        let child = Command::new(env!("CARGO_BIN_EXE_tinyredis"))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        Self(child)
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+    }
+}
+
+// Alternating SETs and GETs of keys no other client uses
+fn pipeline(client: usize) -> Pipeline {
+    let mut pipeline = redis::pipe();
+    for i in 0..PIPELINE / 2 {
+        let key = format!("bench:{}:{}", client, i);
+        pipeline.cmd("SET").arg(&key).arg("value").ignore();
+        pipeline.cmd("GET").arg(&key).ignore();
+    }
+    pipeline
+}
+
+// Each client has its own connection and thread and works on keys of its own, so with the
+// keyspace sharded they seldom wait for each other, and commands per second should grow
+// with the number of clients until every core is busy
+fn set_and_get(c: &mut Criterion) {
+    let _server = Server::start();
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+
+    let mut group = c.benchmark_group("set_and_get");
+    let mut clients = 1;
+    while clients <= 2 * cores {
+        let mut connections: Vec<Connection> = (0..clients)
+            .map(|_| client.get_connection().unwrap())
+            .collect();
+        let pipelines: Vec<Pipeline> = (0..clients).map(pipeline).collect();
+
+        group.throughput(Throughput::Elements((clients * PIPELINE) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(clients), &clients, |b, _| {
+            b.iter_custom(|iters| {
+                let start = Instant::now();
+                thread::scope(|scope| {
+                    for (con, pipeline) in connections.iter_mut().zip(&pipelines) {
+                        scope.spawn(move || {
+                            for _ in 0..iters {
+                                pipeline.query::<()>(con).unwrap();
+                            }
+                        });
+                    }
+                });
+                start.elapsed()
+            })
+        });
+        clients *= 2;
+    }
+    group.finish();
+}
+
+criterion_group!(benches, set_and_get);
+criterion_main!(benches);
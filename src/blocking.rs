@@ -0,0 +1,99 @@
+use crate::resp::RESPMessage;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+// What a blocking command that found nothing to serve leaves on the client: the keys it
+// waits on, for how long (forever if None) and what to reply once that time is up
+pub struct Blocked {
+    pub keys: Vec<Vec<u8>>,
+    pub timeout: Option<Duration>,
+    pub reply: RESPMessage,
+    // The command to run once woken instead of the one that blocked, for commands whose
+    // arguments mean something else by then, like the $ ID of XREAD
+    pub command: Option<RESPMessage>,
+}
+
+// How a blocked client is woken when one of its keys may have something for it
+pub struct Waiter {
+    pub id: u64,
+    notify: Notify,
+}
+
+impl Waiter {
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            notify: Notify::new(),
+        }
+    }
+
+    // Returns straight away if the client was woken since it last waited
+    pub async fn wait(&self) {
+        self.notify.notified().await
+    }
+}
+
+// The clients blocked on each key, in the order they blocked, like Redis' blocking_keys.
+// Only the first client in line is woken when something is pushed to a key. It runs its
+// command again and, when that succeeds, passes the key on to the next one, so clients
+// are served in the order they blocked.
+// Clients block while their command still holds the lock on the key's shard, and writers
+// signal the key once they have changed it, so no push can slip in between a client
+// finding the key empty and joining the line.
+#[derive(Default)]
+pub struct BlockedClients {
+    keys: Mutex<HashMap<Vec<u8>, VecDeque<Arc<Waiter>>>>,
+}
+
+impl BlockedClients {
+    // Puts the client at the back of the line for each key, unless it is already in it:
+    // a client that was woken but found nothing left keeps its place
+    pub fn block(&self, keys: &[Vec<u8>], waiter: &Arc<Waiter>) {
+        let mut blocked = self.keys.lock().unwrap();
+        for key in keys {
+            let line = blocked.entry(key.clone()).or_default();
+            if !line.iter().any(|queued| queued.id == waiter.id) {
+                line.push_back(Arc::clone(waiter));
+            }
+        }
+    }
+
+    // Takes the client out of the line for each key, waking whoever is first in line now
+    // in case there is more to serve
+    pub fn unblock(&self, keys: &[Vec<u8>], id: u64) {
+        let mut blocked = self.keys.lock().unwrap();
+        for key in keys {
+            let Some(line) = blocked.get_mut(key) else {
+                continue;
+            };
+            line.retain(|queued| queued.id != id);
+            match line.front() {
+                Some(first) => first.notify.notify_one(),
+                None => {
+                    blocked.remove(key);
+                }
+            }
+        }
+    }
+
+    // Wakes the first client in line for the key, after something was pushed to it
+    pub fn signal(&self, key: &[u8]) {
+        let blocked = self.keys.lock().unwrap();
+        if let Some(first) = blocked.get(key).and_then(VecDeque::front) {
+            first.notify.notify_one();
+        }
+    }
+
+    // Wakes every client in line for the key, after an entry was added to the stream it
+    // holds. Readers don't take entries away from each other, so any of them may be served.
+    pub fn signal_all(&self, key: &[u8]) {
+        let blocked = self.keys.lock().unwrap();
+        for waiter in blocked.get(key).into_iter().flatten() {
+            waiter.notify.notify_one();
+        }
+    }
+}
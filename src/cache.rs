@@ -1,5 +1,12 @@
-use std::cmp::Ordering;
+use crate::{config::Config, value::Value};
+use rand::Rng;
+use slab::Slab;
 use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
 
 use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -10,134 +17,506 @@ pub fn now() -> u128 {
         .as_millis()
 }
 
+// Counts uses across every shard, so the entries' last_used can be compared wherever they are
+static CLOCK: AtomicU64 = AtomicU64::new(0);
+
+fn tick() -> u64 {
+    CLOCK.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+// What a new key's LFU counter starts at, so it is not evicted before it had a chance to be used
+const LFU_INIT_VAL: u8 = 5;
+
+// Unix time in minutes, wrapping around like the 16 bits Redis keeps it in
+fn lfu_minutes() -> u16 {
+    (now() / 60_000) as u16
+}
+
+// Which key to evict when a new one does not fit, named as in redis.conf's maxmemory-policy
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EvictionPolicy {
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl EvictionPolicy {
+    pub const ALL: [Self; 8] = [
+        Self::NoEviction,
+        Self::AllKeysLru,
+        Self::AllKeysLfu,
+        Self::AllKeysRandom,
+        Self::VolatileLru,
+        Self::VolatileLfu,
+        Self::VolatileRandom,
+        Self::VolatileTtl,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileLru => "volatile-lru",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|policy| policy.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_lfu(self) -> bool {
+        matches!(self, Self::AllKeysLfu | Self::VolatileLfu)
+    }
+
+    // Whether only keys with an expiry may be evicted
+    fn is_volatile(self) -> bool {
+        matches!(
+            self,
+            Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom | Self::VolatileTtl
+        )
+    }
+}
+
 #[derive(Debug)]
 struct Entry {
-    value: String,
-    ttl: Option<u64>,
-    insertion_time: u128,
-    frequency: u64,
-    aging: u64,
+    key: Vec<u8>,
+    value: Value,
+    // Unix time in milliseconds after which the entry no longer exists
+    expires_at: Option<u128>,
+    // How often the entry is used, on a logarithmic scale: the higher it is the less
+    // likely it is to grow, so 255 takes about a million uses with the default factor
+    lfu_counter: u8,
+    // lfu_minutes() when lfu_counter was last decayed
+    lfu_decay_start: u16,
+    // Value of CLOCK when the entry was last used
+    last_used: u64,
+    // Neighbours in the recency list: prev was used more recently, next less recently
+    prev: Option<usize>,
+    next: Option<usize>,
+    // Positions of the entry in Cache::all and Cache::volatile
+    all_index: Option<usize>,
+    volatile_index: Option<usize>,
+}
+
+// The lists of entries that keys are sampled from
+#[derive(Clone, Copy)]
+enum Pool {
+    All,
+    Volatile,
+}
+
+// Roughly how many bytes an entry takes up: the key is kept twice, in the entry and in the
+// index, along with the entry itself and the index and pool slots pointing at it
+pub fn entry_size(key: &[u8], value: &Value) -> usize {
+    2 * key.len()
+        + value.size()
+        + size_of::<Entry>()
+        + size_of::<(Vec<u8>, usize)>()
+        + 2 * size_of::<usize>()
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        entry_size(&self.key, &self.value)
+    }
+
+    fn pool_index(&mut self, pool: Pool) -> &mut Option<usize> {
+        match pool {
+            Pool::All => &mut self.all_index,
+            Pool::Volatile => &mut self.volatile_index,
+        }
+    }
 }
 
+// One shard of the keyspace.
+// Entries live in a slab and are linked together from the most to the least recently
+// used, so a lookup only has to unlink its entry and put it back at the front, and the
+// least recently used entry is always at the back. Every operation is O(1), however
+// many keys there are.
 pub struct Cache {
-    cache: HashMap<String, Entry>,
-    maximum: usize,
+    // Where each key's entry is in entries
+    index: HashMap<Vec<u8>, usize>,
+    entries: Slab<Entry>,
+    // Most and least recently used entries
+    head: Option<usize>,
+    tail: Option<usize>,
+    // Every entry, and every entry that has an expiry, so they can be sampled without
+    // scanning the whole cache
+    all: Vec<usize>,
+    volatile: Vec<usize>,
+    // Sum of the entries' sizes in every shard
+    used_memory: Arc<AtomicUsize>,
+    pub policy: EvictionPolicy,
+    // How many keys are compared to pick one to evict, as Redis' maxmemory-samples
+    pub samples: usize,
+    // How slowly LFU counters grow, and how many minutes it takes for one to drop by one
+    pub lfu_log_factor: usize,
+    pub lfu_decay_time: usize,
+    // Keys removed because they expired, whether found by a lookup or the expiry cycle
+    pub expired_keys: u64,
 }
 impl Cache {
-    pub fn new(maximum: usize) -> Self {
+    pub fn new(used_memory: Arc<AtomicUsize>) -> Self {
         Self {
-            maximum: maximum,
-            cache: HashMap::with_capacity(maximum),
+            index: HashMap::new(),
+            entries: Slab::new(),
+            head: None,
+            tail: None,
+            all: vec![],
+            volatile: vec![],
+            used_memory,
+            policy: EvictionPolicy::AllKeysLru,
+            samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            expired_keys: 0,
         }
     }
 
-    pub fn update_aging(&mut self, key: &str) {
-        for mut c in &mut self.cache {
-            if c.0 != key {
-                c.1.aging += 1;
+    // Picks up the settings that affect the cache, at startup and after CONFIG SET
+    pub fn configure(&mut self, config: &Config) {
+        self.policy = config.maxmemory_policy;
+        self.samples = config.maxmemory_samples;
+        self.lfu_log_factor = config.lfu_log_factor;
+        self.lfu_decay_time = config.lfu_decay_time;
+    }
+
+    // The entry's LFU counter after taking off one for every lfu-decay-time minutes since
+    // it was last decayed, so keys that were only used long ago can be evicted eventually
+    fn lfu_decayed(&self, entry: &Entry) -> u8 {
+        if self.lfu_decay_time == 0 {
+            return entry.lfu_counter;
+        }
+        let elapsed = lfu_minutes().wrapping_sub(entry.lfu_decay_start) as usize;
+        let periods = elapsed / self.lfu_decay_time;
+        entry.lfu_counter.saturating_sub(periods.min(255) as u8)
+    }
+
+    // Decays the entry's LFU counter and counts one more use, which only raises the counter
+    // with a probability that falls as it grows
+    fn lfu_use(&mut self, slot: usize) {
+        let mut counter = self.lfu_decayed(&self.entries[slot]);
+        if counter < 255 {
+            let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+            let p = 1.0 / (base * self.lfu_log_factor as f64 + 1.0);
+            if rand::thread_rng().gen::<f64>() < p {
+                counter += 1;
             }
         }
+        let entry = &mut self.entries[slot];
+        entry.lfu_counter = counter;
+        entry.lfu_decay_start = lfu_minutes();
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn volatile_count(&self) -> usize {
+        self.volatile.len()
+    }
+
+    // Takes the entry out of the recency list, leaving it in the slab
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.entries[slot].prev, self.entries[slot].next);
+        match prev {
+            Some(prev) => self.entries[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.entries[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    // Puts the entry at the front of the recency list, as the most recently used
+    fn push_front(&mut self, slot: usize) {
+        self.entries[slot].prev = None;
+        self.entries[slot].next = self.head;
+        match self.head {
+            Some(head) => self.entries[head].prev = Some(slot),
+            None => self.tail = Some(slot),
+        }
+        self.head = Some(slot);
+    }
+
+    fn touch(&mut self, slot: usize) {
+        self.entries[slot].last_used = tick();
+        if self.head != Some(slot) {
+            self.unlink(slot);
+            self.push_front(slot);
+        }
+    }
+
+    fn pool(&mut self, pool: Pool) -> &mut Vec<usize> {
+        match pool {
+            Pool::All => &mut self.all,
+            Pool::Volatile => &mut self.volatile,
+        }
     }
 
-    pub fn get(&mut self, key: &str) -> Option<String> {
-        self.update_aging(key);
+    fn add_to_pool(&mut self, slot: usize, pool: Pool) {
+        let index = self.pool(pool).len();
+        *self.entries[slot].pool_index(pool) = Some(index);
+        self.pool(pool).push(slot);
+    }
+
+    // Moves the last entry of the pool into the removed entry's place
+    fn remove_from_pool(&mut self, slot: usize, pool: Pool) {
+        let Some(index) = self.entries[slot].pool_index(pool).take() else {
+            return;
+        };
+        let entries = self.pool(pool);
+        entries.swap_remove(index);
+        if let Some(&moved) = entries.get(index) {
+            *self.entries[moved].pool_index(pool) = Some(index);
+        }
+    }
+
+    // Every removal goes through here so the index, recency list and pools stay in step
+    fn remove_slot(&mut self, slot: usize) -> Entry {
+        self.unlink(slot);
+        self.remove_from_pool(slot, Pool::All);
+        self.remove_from_pool(slot, Pool::Volatile);
+        let entry = self.entries.remove(slot);
+        self.index.remove(&entry.key);
+        self.used_memory.fetch_sub(entry.size(), Ordering::Relaxed);
+        entry
+    }
+
+    // Every insertion goes through here, putting the entry at the front of the recency list
+    fn insert(&mut self, entry: Entry) -> usize {
+        self.used_memory.fetch_add(entry.size(), Ordering::Relaxed);
+        let (key, volatile) = (entry.key.clone(), entry.expires_at.is_some());
+        let slot = self.entries.insert(entry);
+        self.index.insert(key, slot);
+        self.push_front(slot);
+        self.add_to_pool(slot, Pool::All);
+        if volatile {
+            self.add_to_pool(slot, Pool::Volatile);
+        }
+        slot
+    }
+
+    fn is_expired(&self, slot: usize, now: u128) -> bool {
+        self.entries[slot]
+            .expires_at
+            .is_some_and(|expires_at| now > expires_at)
+    }
+
+    // Finds the entry for key, removing it instead if it has expired so it is never handed out
+    fn find(&mut self, key: &[u8]) -> Option<usize> {
+        let slot = *self.index.get(key)?;
+        if self.is_expired(slot, now()) {
+            self.remove_slot(slot);
+            self.expired_keys += 1;
+            return None;
+        }
+        Some(slot)
+    }
 
-        if let Some(entry) = self.cache.get_mut(key) {
-            if let Some(ttl) = entry.ttl {
-                if now() - entry.insertion_time > ttl.into() {
-                    self.cache.remove(key);
-                    return None;
-                }
+    // Checks up to count random keys that have an expiry and removes the ones that have
+    // expired. Returns how many keys were checked and how many of them had expired.
+    pub fn expire_sample(&mut self, count: usize) -> (usize, usize) {
+        let mut rng = rand::thread_rng();
+        let now = now();
+        let sampled = count.min(self.volatile.len());
+        let mut expired = 0;
+
+        for _ in 0..sampled {
+            if self.volatile.is_empty() {
+                break;
+            }
+            let slot = self.volatile[rng.gen_range(0..self.volatile.len())];
+            if self.is_expired(slot, now) {
+                self.remove_slot(slot);
+                self.expired_keys += 1;
+                expired += 1;
             }
-            entry.frequency += 1;
-            entry.aging = 1;
-            Some(entry.value.clone())
+        }
+        (sampled, expired)
+    }
+
+    pub fn contains(&mut self, key: &[u8]) -> bool {
+        self.find(key).is_some()
+    }
+
+    // When the key expires, as unix time in milliseconds, or None if it has no expiry or does not exist
+    pub fn expires_at(&mut self, key: &[u8]) -> Option<u128> {
+        let slot = self.find(key)?;
+        self.entries[slot].expires_at
+    }
+
+    // How many bytes the key's entry takes up, or None if it does not exist
+    pub fn size_of(&mut self, key: &[u8]) -> Option<usize> {
+        let slot = self.find(key)?;
+        Some(self.entries[slot].size())
+    }
+
+    // The key's LFU counter, without counting this as a use, or None if it does not exist
+    pub fn frequency(&mut self, key: &[u8]) -> Option<u8> {
+        let slot = self.find(key)?;
+        Some(self.lfu_decayed(&self.entries[slot]))
+    }
+
+    // Changes when an existing key expires, returning false if there is no such key
+    pub fn set_expires_at(&mut self, key: &[u8], expires_at: Option<u128>) -> bool {
+        let Some(slot) = self.find(key) else {
+            return false;
+        };
+
+        self.entries[slot].expires_at = expires_at;
+        match (self.entries[slot].volatile_index, expires_at) {
+            (None, Some(_)) => self.add_to_pool(slot, Pool::Volatile),
+            (Some(_), None) => self.remove_from_pool(slot, Pool::Volatile),
+            _ => {}
+        }
+        true
+    }
+
+    pub fn get(&mut self, key: &[u8]) -> Option<&Value> {
+        let slot = self.find(key)?;
+        self.touch(slot);
+        self.lfu_use(slot);
+        Some(&self.entries[slot].value)
+    }
+
+    // The key's value without counting this as a use, for reading several values at once.
+    // An expired key is not returned, but is left for a lookup or the expiry cycle to remove.
+    pub fn peek(&self, key: &[u8]) -> Option<&Value> {
+        let slot = *self.index.get(key)?;
+        (!self.is_expired(slot, now())).then(|| &self.entries[slot].value)
+    }
+
+    // Changes the key's value in place, keeping track of how much memory it takes up.
+    // A collection left empty is removed along with its key.
+    pub fn modify<T>(&mut self, key: &[u8], f: impl FnOnce(&mut Value) -> T) -> Option<T> {
+        let slot = self.find(key)?;
+        self.touch(slot);
+        self.lfu_use(slot);
+
+        // Values keep count of their own size, so only the change is added or taken away
+        let before = self.entries[slot].value.size();
+        let result = f(&mut self.entries[slot].value);
+        let after = self.entries[slot].value.size();
+        if after > before {
+            self.used_memory
+                .fetch_add(after - before, Ordering::Relaxed);
         } else {
-            None
+            self.used_memory
+                .fetch_sub(before - after, Ordering::Relaxed);
+        }
+        if self.entries[slot].value.is_empty_collection() {
+            self.remove_slot(slot);
         }
+        Some(result)
     }
-    pub fn get_key(&mut self) -> (Vec<String>, Vec<String>) {
 
-        let mut keys: Vec<String> = vec![];
-        let mut vals: Vec<String> = vec![];
-        for (k, entry) in self.cache.iter_mut() {
-                keys.push(k.to_string());
-                vals.push(entry.value.to_string());
-                
-                
-            }
-            (keys, vals)
-        }
-
-    pub fn set(&mut self, key: String, value: String, ttl: Option<u64>) -> Option<String> {
-        println!("\n{:?}\n", "Keys Before");
-        for c in &self.cache {
-            println!("{:?}", c);
-        }
-        self.update_aging(&key);
-        let result: Result<(String, String), ()> = match self.cache.keys().len().cmp(&self.maximum)
-        {
-            Ordering::Greater => Err(()),
-            Ordering::Equal => {
-                // LRU
-                let mut maxAging = 0;
-                let mut maxAgingKey = "";
-                for c in &self.cache {
-                    if c.1.aging > maxAging {
-                        maxAging = c.1.aging;
-                        maxAgingKey = c.0;
-                    }
-                }
-                // LFU
-                let mut minFrequency = std::u64::MAX;
-                let mut minFrequencyKey = "";
-                for c in &self.cache {
-                    if c.1.frequency < minFrequency {
-                        minFrequency = c.1.frequency;
-                        minFrequencyKey = c.0;
-                    }
-                }
-                // LRU policy
-                Ok(("Equal".to_string(), maxAgingKey.to_string()))
+    // Every key that hasn't expired with its value, of whatever type, and when it expires,
+    // for SYNC to dump to a replica
+    pub fn get_key(&mut self) -> Vec<(Vec<u8>, Value, Option<u128>)> {
+        let now = now();
+        self.entries
+            .iter()
+            .filter(|(slot, _)| !self.is_expired(*slot, now))
+            .map(|(_, entry)| (entry.key.clone(), entry.value.clone(), entry.expires_at))
+            .collect()
+    }
+
+    // Picks the key this shard would evict under the eviction policy, along with a rank to
+    // compare it with the other shards' picks by: the lowest rank is evicted first.
+    // None if the policy does not allow evicting any of the entries there are.
+    // Apart from allkeys-lru, which simply takes the least recently used entry, a few
+    // random entries are compared like Redis does rather than searching all of them.
+    pub fn eviction_candidate(&self) -> Option<(u128, Vec<u8>)> {
+        let mut rng = rand::thread_rng();
+        let pool = match self.policy {
+            EvictionPolicy::NoEviction => return None,
+            EvictionPolicy::AllKeysLru => {
+                let entry = &self.entries[self.tail?];
+                return Some((entry.last_used.into(), entry.key.clone()));
             }
-            // smaller
-            _ => Ok(("Smaller".to_string(), "".to_string())),
+            policy if policy.is_volatile() => &self.volatile,
+            _ => &self.all,
+        };
+        if pool.is_empty() {
+            return None;
+        }
+
+        // Small pools are compared in full
+        let slots: Vec<usize> = if pool.len() <= self.samples {
+            pool.clone()
+        } else {
+            (0..self.samples.max(1))
+                .map(|_| pool[rng.gen_range(0..pool.len())])
+                .collect()
         };
-        match result {
-            Ok(r) => {
-                if r.0 == "Equal" {
-                    self.remove(&r.1);
-                }
-
-                self.cache.insert(
-                    key,
-                    Entry {
-                        value,
-                        ttl,
-                        insertion_time: now(),
-                        frequency: 0,
-                        aging: 1,
-                    },
-                );
-                println!("\n{:?}\n", "Keys After");
-                for c in &self.cache {
-                    println!("{:?}", c);
-                }
-                Some("OK".to_string())
+        let samples = slots.into_iter().map(|slot| &self.entries[slot]);
+        let (rank, entry) = match self.policy {
+            // A random rank, so every shard is as likely to lose a key
+            EvictionPolicy::AllKeysRandom | EvictionPolicy::VolatileRandom => {
+                samples.map(|entry| (rng.gen(), entry)).next()
             }
-            Err(_) => None,
+            EvictionPolicy::VolatileLru => samples
+                .map(|entry| (entry.last_used.into(), entry))
+                .min_by_key(|(rank, _)| *rank),
+            EvictionPolicy::VolatileTtl => samples
+                .map(|entry| (entry.expires_at.unwrap_or(u128::MAX), entry))
+                .min_by_key(|(rank, _)| *rank),
+            _ => samples
+                .map(|entry| (self.lfu_decayed(entry).into(), entry))
+                .min_by_key(|(rank, _)| *rank),
+        }?;
+        Some((rank, entry.key.clone()))
+    }
+
+    // Stores the value until the given unix time in milliseconds
+    pub fn set_expiring_at(&mut self, key: Vec<u8>, value: Value, expires_at: Option<u128>) {
+        let mut entry = Entry {
+            key,
+            value,
+            expires_at,
+            lfu_counter: LFU_INIT_VAL,
+            lfu_decay_start: lfu_minutes(),
+            last_used: tick(),
+            prev: None,
+            next: None,
+            all_index: None,
+            volatile_index: None,
+        };
+
+        // Overwriting a key is a use of it, so it keeps its LFU counter
+        let old = self.find(&entry.key).map(|slot| self.remove_slot(slot));
+        if let Some(old) = &old {
+            entry.lfu_counter = old.lfu_counter;
+            entry.lfu_decay_start = old.lfu_decay_start;
+        }
+        let slot = self.insert(entry);
+        if old.is_some() {
+            self.lfu_use(slot);
         }
     }
 
-    pub fn remove(&mut self, key: &str) -> Option<String> {
-        let keys = key.trim().split(" ");
-        let mut counter = 0;
-        for k in keys {
-            if (self.cache.contains_key(k)) {
-                counter += 1;
-                self.cache.remove(k);
+    // Removes a single key, returning whether it existed
+    pub fn delete(&mut self, key: &[u8]) -> bool {
+        match self.find(key) {
+            Some(slot) => {
+                self.remove_slot(slot);
+                true
             }
+            None => false,
         }
-        Some(counter.to_string())
     }
 }
@@ -0,0 +1,96 @@
+use crate::resp::{ProtocolError, ProtocolLimits, RESPMessage, RESPVersion};
+use bytes::BytesMut;
+use std::{fmt, io};
+use tokio_util::codec::{Decoder, Encoder};
+
+// Why a framed connection failed: the peer sent something that is not RESP,
+// or the socket itself could not be read from or written to
+#[derive(Debug)]
+pub enum RESPCodecError {
+    Protocol(ProtocolError),
+    Io(io::Error),
+}
+
+impl From<ProtocolError> for RESPCodecError {
+    fn from(e: ProtocolError) -> Self {
+        Self::Protocol(e)
+    }
+}
+
+impl From<io::Error> for RESPCodecError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for RESPCodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Protocol(e) => write!(f, "Protocol error: {}", e),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RESPCodecError {}
+
+// Turns a connection into a stream of RESPMessages (and a sink for them) through
+// tokio_util's Framed. Bytes that have not formed a whole message yet stay in the
+// read buffer until the next read, and bulk strings are sliced out of that buffer
+// rather than copied.
+pub struct RESPCodec {
+    // Whether the other end sends commands (so inline commands are accepted) or replies
+    requests: bool,
+    pub limits: ProtocolLimits,
+    // The protocol version messages are encoded with
+    pub version: RESPVersion,
+}
+
+impl RESPCodec {
+    // For the server side of a connection, which receives commands
+    pub fn for_requests(limits: ProtocolLimits) -> Self {
+        Self {
+            requests: true,
+            limits,
+            version: RESPVersion::RESP2,
+        }
+    }
+
+    // For the client side of a connection, which receives replies
+    pub fn for_replies(limits: ProtocolLimits) -> Self {
+        Self {
+            requests: false,
+            limits,
+            version: RESPVersion::RESP2,
+        }
+    }
+}
+
+impl Decoder for RESPCodec {
+    type Item = RESPMessage;
+    type Error = RESPCodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<RESPMessage>, RESPCodecError> {
+        if !self.requests {
+            return Ok(RESPMessage::deserialize(src, &self.limits)?);
+        }
+
+        // Empty commands (a blank line, *0 or *-1) are skipped without a reply, like Redis does
+        loop {
+            match RESPMessage::deserialize_request(src, &self.limits)? {
+                Some(RESPMessage::NullArray) => {}
+                Some(RESPMessage::Array(elements)) if elements.is_empty() => {}
+                message => return Ok(message),
+            }
+        }
+    }
+}
+
+impl Encoder<RESPMessage> for RESPCodec {
+    type Error = RESPCodecError;
+
+    fn encode(&mut self, item: RESPMessage, dst: &mut BytesMut) -> Result<(), RESPCodecError> {
+        item.write_to(self.version, dst);
+        Ok(())
+    }
+}
@@ -0,0 +1,991 @@
+use crate::{
+    blocking::{Blocked, Waiter},
+    cache::now,
+    config::Config,
+    hash,
+    keyspace::Keyspace,
+    list,
+    resp::{RESPMessage, RESPVersion},
+    set,
+    stream,
+    string,
+    value::{Value, WrongType},
+    zset,
+};
+use anyhow::{Error, Result};
+use bytes::Bytes;
+use std::{
+    process::{self, Command as Process, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+// State kept for each connection, which commands such as HELLO can change
+pub struct Client {
+    pub id: u64,
+    pub name: Option<String>,
+    pub protocol: RESPVersion,
+    pub waiter: Arc<Waiter>,
+    // Set by a blocking command that has to wait before it can reply
+    pub blocked: Option<Blocked>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        Self {
+            id,
+            name: None,
+            protocol: RESPVersion::RESP2,
+            waiter: Arc::new(Waiter::new(id)),
+            blocked: None,
+        }
+    }
+}
+
+// Everything a command handler may read or change
+pub struct Context<'a> {
+    pub keyspace: &'a Keyspace,
+    pub config: &'a RwLock<Config>,
+    pub client: &'a mut Client,
+}
+
+// Handlers get the arguments that follow the command name, already checked against its arity
+type Handler = fn(&mut Context, &[RESPMessage]) -> Result<RESPMessage>;
+
+#[derive(Clone, Copy)]
+pub enum Flag {
+    Write,
+    // Refused when used memory is over maxmemory and no keys can be evicted
+    DenyOom,
+    ReadOnly,
+    Admin,
+    Fast,
+    // May wait for another client to write before replying
+    Blocking,
+}
+
+impl Flag {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Write => "write",
+            Self::DenyOom => "denyoom",
+            Self::ReadOnly => "readonly",
+            Self::Admin => "admin",
+            Self::Fast => "fast",
+            Self::Blocking => "blocking",
+        }
+    }
+
+    fn acl_category(self) -> Option<&'static str> {
+        match self {
+            Self::Write => Some("@write"),
+            Self::DenyOom => None,
+            Self::ReadOnly => Some("@read"),
+            Self::Admin => Some("@admin"),
+            Self::Fast => Some("@fast"),
+            Self::Blocking => Some("@blocking"),
+        }
+    }
+}
+
+use Flag::*;
+
+// Describes a command the same way Redis' command table does
+pub struct Command {
+    pub name: &'static str,
+    // The number of arguments including the command name itself.
+    // A negative arity -n means at least n arguments.
+    pub arity: i64,
+    pub flags: &'static [Flag],
+    // Positions of the keys among the arguments: the first one, the last one (negative
+    // counts from the end, so -1 is the last argument) and the step between them.
+    // All three are 0 for commands that take no keys.
+    pub first_key: i64,
+    pub last_key: i64,
+    pub step: i64,
+    pub group: &'static str,
+    pub summary: &'static str,
+    handler: Handler,
+}
+
+impl Command {
+    #[allow(clippy::too_many_arguments)]
+    const fn new(
+        name: &'static str,
+        arity: i64,
+        flags: &'static [Flag],
+        (first_key, last_key, step): (i64, i64, i64),
+        group: &'static str,
+        summary: &'static str,
+        handler: Handler,
+    ) -> Self {
+        Self {
+            name,
+            arity,
+            flags,
+            first_key,
+            last_key,
+            step,
+            group,
+            summary,
+            handler,
+        }
+    }
+
+    fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as i64;
+        if self.arity < 0 {
+            argc >= -self.arity
+        } else {
+            argc == self.arity
+        }
+    }
+
+    // The entry COMMAND and COMMAND INFO reply with
+    fn info(&self) -> RESPMessage {
+        let status = |s: &str| RESPMessage::SimpleString(s.to_string());
+        let mut categories: Vec<RESPMessage> =
+            self.flags.iter().filter_map(|flag| Some(status(flag.acl_category()?))).collect();
+        match self.group {
+            "generic" => categories.push(status("@keyspace")),
+            "server" => {}
+            group => categories.push(status(&format!("@{}", group))),
+        }
+
+        RESPMessage::Array(vec![
+            RESPMessage::BulkString(Bytes::from_static(self.name.as_bytes())),
+            RESPMessage::Integer(self.arity),
+            RESPMessage::Array(self.flags.iter().map(|flag| status(flag.name())).collect()),
+            RESPMessage::Integer(self.first_key),
+            RESPMessage::Integer(self.last_key),
+            RESPMessage::Integer(self.step),
+            RESPMessage::Array(categories),
+            // Tips, key specifications and subcommands
+            RESPMessage::Array(vec![]),
+            RESPMessage::Array(vec![]),
+            RESPMessage::Array(vec![]),
+        ])
+    }
+
+    // The entry COMMAND DOCS replies with
+    fn docs(&self) -> RESPMessage {
+        let bulk = |s: &'static str| RESPMessage::BulkString(Bytes::from_static(s.as_bytes()));
+        RESPMessage::Map(vec![
+            (bulk("summary"), bulk(self.summary)),
+            (bulk("group"), bulk(self.group)),
+        ])
+    }
+}
+
+#[rustfmt::skip]
+pub static COMMANDS: &[Command] = &[
+    // Connection
+    Command::new("ping", -1, &[Fast], (0, 0, 0), "connection", "Returns the server's liveliness response.", ping),
+    Command::new("echo", 2, &[Fast], (0, 0, 0), "connection", "Returns the given string.", echo),
+    Command::new("hello", -1, &[Fast], (0, 0, 0), "connection", "Handshakes with the Redis server.", hello),
+    // Strings
+    Command::new("get", 2, &[ReadOnly, Fast], (1, 1, 1), "string", "Returns the string value of a key.", get),
+    Command::new("set", -3, &[Write, DenyOom], (1, 1, 1), "string", "Sets the string value of a key.", set),
+    Command::new("append", 3, &[Write, DenyOom, Fast], (1, 1, 1), "string", "Appends a string to the value of a key. Creates the key if it doesn't exist.", string::append),
+    Command::new("strlen", 2, &[ReadOnly, Fast], (1, 1, 1), "string", "Returns the length of a string value.", string::strlen),
+    Command::new("getrange", 4, &[ReadOnly], (1, 1, 1), "string", "Returns a substring of the string stored at a key.", string::getrange),
+    Command::new("setrange", 4, &[Write, DenyOom], (1, 1, 1), "string", "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist.", string::setrange),
+    Command::new("getset", 3, &[Write, DenyOom, Fast], (1, 1, 1), "string", "Returns the previous string value of a key after setting it to a new value.", string::getset),
+    Command::new("getdel", 2, &[Write, Fast], (1, 1, 1), "string", "Returns the string value of a key after deleting the key.", string::getdel),
+    Command::new("getex", -2, &[Write, Fast], (1, 1, 1), "string", "Returns the string value of a key after setting its expiration time.", string::getex),
+    Command::new("mget", -2, &[ReadOnly, Fast], (1, -1, 1), "string", "Atomically returns the string values of one or more keys.", string::mget),
+    Command::new("mset", -3, &[Write, DenyOom], (1, -1, 2), "string", "Atomically creates or modifies the string values of one or more keys.", string::mset),
+    Command::new("msetnx", -3, &[Write, DenyOom], (1, -1, 2), "string", "Atomically modifies the string values of one or more keys only when all keys don't exist.", string::msetnx),
+    Command::new("setnx", 3, &[Write, DenyOom, Fast], (1, 1, 1), "string", "Set the string value of a key only when the key doesn't exist.", string::setnx),
+    Command::new("setex", 4, &[Write, DenyOom], (1, 1, 1), "string", "Sets the string value and expiration time of a key. Creates the key if it doesn't exist.", string::setex),
+    Command::new("psetex", 4, &[Write, DenyOom], (1, 1, 1), "string", "Sets both string value and expiration time in milliseconds of a key. The key is created if it doesn't exist.", string::psetex),
+    // Keyspace
+    Command::new("del", -2, &[Write], (1, -1, 1), "generic", "Deletes one or more keys.", del),
+    Command::new("type", 2, &[ReadOnly, Fast], (1, 1, 1), "generic", "Determines the type of value stored at a key.", type_),
+    Command::new("expire", -3, &[Write, Fast], (1, 1, 1), "generic", "Sets the expiration time of a key in seconds.", expire),
+    Command::new("pexpire", -3, &[Write, Fast], (1, 1, 1), "generic", "Sets the expiration time of a key in milliseconds.", pexpire),
+    Command::new("expireat", -3, &[Write, Fast], (1, 1, 1), "generic", "Sets the expiration time of a key to a Unix timestamp.", expireat),
+    Command::new("pexpireat", -3, &[Write, Fast], (1, 1, 1), "generic", "Sets the expiration time of a key to a Unix milliseconds timestamp.", pexpireat),
+    Command::new("ttl", 2, &[ReadOnly, Fast], (1, 1, 1), "generic", "Returns the expiration time in seconds of a key.", ttl),
+    Command::new("pttl", 2, &[ReadOnly, Fast], (1, 1, 1), "generic", "Returns the expiration time in milliseconds of a key.", pttl),
+    Command::new("expiretime", 2, &[ReadOnly, Fast], (1, 1, 1), "generic", "Returns the expiration time of a key as a Unix timestamp.", expiretime),
+    Command::new("pexpiretime", 2, &[ReadOnly, Fast], (1, 1, 1), "generic", "Returns the expiration time of a key as a Unix milliseconds timestamp.", pexpiretime),
+    Command::new("object", -2, &[ReadOnly], (2, 2, 1), "generic", "Inspects the internals of a key.", object),
+    Command::new("persist", 2, &[Write, Fast], (1, 1, 1), "generic", "Removes the expiration time of a key.", persist),
+    // Lists
+    Command::new("lpush", -3, &[Write, DenyOom, Fast], (1, 1, 1), "list", "Prepends one or more elements to a list. Creates the key if it doesn't exist.", list::lpush),
+    Command::new("rpush", -3, &[Write, DenyOom, Fast], (1, 1, 1), "list", "Appends one or more elements to a list. Creates the key if it doesn't exist.", list::rpush),
+    Command::new("lpop", -2, &[Write, Fast], (1, 1, 1), "list", "Returns the first elements in a list after removing it. Deletes the list if the last element was popped.", list::lpop),
+    Command::new("rpop", -2, &[Write, Fast], (1, 1, 1), "list", "Returns and removes the last elements of a list. Deletes the list if the last element was popped.", list::rpop),
+    Command::new("lrange", 4, &[ReadOnly], (1, 1, 1), "list", "Returns a range of elements from a list.", list::lrange),
+    Command::new("llen", 2, &[ReadOnly, Fast], (1, 1, 1), "list", "Returns the length of a list.", list::llen),
+    Command::new("lindex", 3, &[ReadOnly], (1, 1, 1), "list", "Returns an element from a list by its index.", list::lindex),
+    Command::new("lset", 4, &[Write, DenyOom], (1, 1, 1), "list", "Sets the value of an element in a list by its index.", list::lset),
+    Command::new("lrem", 4, &[Write], (1, 1, 1), "list", "Removes elements from a list. Deletes the list if the last element was removed.", list::lrem),
+    Command::new("ltrim", 4, &[Write], (1, 1, 1), "list", "Removes elements from both ends a list. Deletes the list if all elements were trimmed.", list::ltrim),
+    Command::new("linsert", 5, &[Write, DenyOom], (1, 1, 1), "list", "Inserts an element before or after another element in a list.", list::linsert),
+    Command::new("lmove", 5, &[Write, DenyOom], (1, 2, 1), "list", "Returns an element after popping it from one list and pushing it to another. Deletes the list if the last element was moved.", list::lmove),
+    Command::new("blpop", -3, &[Write, Blocking], (1, -2, 1), "list", "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.", list::blpop),
+    Command::new("brpop", -3, &[Write, Blocking], (1, -2, 1), "list", "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.", list::brpop),
+    Command::new("blmove", 6, &[Write, DenyOom, Blocking], (1, 2, 1), "list", "Pops an element from a list, pushes it to another list and returns it. Blocks until an element is available otherwise. Deletes the list if the last element was moved.", list::blmove),
+    // Hashes
+    Command::new("hset", -4, &[Write, DenyOom, Fast], (1, 1, 1), "hash", "Creates or modifies the value of a field in a hash.", hash::hset),
+    Command::new("hget", 3, &[ReadOnly, Fast], (1, 1, 1), "hash", "Returns the value of a field in a hash.", hash::hget),
+    Command::new("hmget", -3, &[ReadOnly, Fast], (1, 1, 1), "hash", "Returns the values of all fields in a hash.", hash::hmget),
+    Command::new("hdel", -3, &[Write, Fast], (1, 1, 1), "hash", "Deletes one or more fields and their values from a hash. Deletes the hash if no fields remain.", hash::hdel),
+    Command::new("hgetall", 2, &[ReadOnly], (1, 1, 1), "hash", "Returns all fields and values in a hash.", hash::hgetall),
+    Command::new("hincrby", 4, &[Write, DenyOom, Fast], (1, 1, 1), "hash", "Increments the integer value of a field in a hash by a number. Uses 0 as initial value if the field doesn't exist.", hash::hincrby),
+    Command::new("hincrbyfloat", 4, &[Write, DenyOom, Fast], (1, 1, 1), "hash", "Increments the floating point value of a field by a number. Uses 0 as initial value if the field doesn't exist.", hash::hincrbyfloat),
+    Command::new("hexists", 3, &[ReadOnly, Fast], (1, 1, 1), "hash", "Determines whether a field exists in a hash.", hash::hexists),
+    Command::new("hkeys", 2, &[ReadOnly], (1, 1, 1), "hash", "Returns all fields in a hash.", hash::hkeys),
+    Command::new("hvals", 2, &[ReadOnly], (1, 1, 1), "hash", "Returns all values in a hash.", hash::hvals),
+    Command::new("hlen", 2, &[ReadOnly, Fast], (1, 1, 1), "hash", "Returns the number of fields in a hash.", hash::hlen),
+    Command::new("hscan", -3, &[ReadOnly], (1, 1, 1), "hash", "Iterates over fields and values of a hash.", hash::hscan),
+    // Sets
+    Command::new("sadd", -3, &[Write, DenyOom, Fast], (1, 1, 1), "set", "Adds one or more members to a set. Creates the key if it doesn't exist.", set::sadd),
+    Command::new("srem", -3, &[Write, Fast], (1, 1, 1), "set", "Removes one or more members from a set. Deletes the set if the last member was removed.", set::srem),
+    Command::new("sismember", 3, &[ReadOnly, Fast], (1, 1, 1), "set", "Determines whether a member belongs to a set.", set::sismember),
+    Command::new("smembers", 2, &[ReadOnly], (1, 1, 1), "set", "Returns all members of a set.", set::smembers),
+    Command::new("scard", 2, &[ReadOnly, Fast], (1, 1, 1), "set", "Returns the number of members in a set.", set::scard),
+    Command::new("sinter", -2, &[ReadOnly], (1, -1, 1), "set", "Returns the intersect of multiple sets.", set::sinter),
+    Command::new("sunion", -2, &[ReadOnly], (1, -1, 1), "set", "Returns the union of multiple sets.", set::sunion),
+    Command::new("sdiff", -2, &[ReadOnly], (1, -1, 1), "set", "Returns the difference of multiple sets.", set::sdiff),
+    Command::new("sinterstore", -3, &[Write, DenyOom], (1, -1, 1), "set", "Stores the intersect of multiple sets in a key.", set::sinterstore),
+    Command::new("sunionstore", -3, &[Write, DenyOom], (1, -1, 1), "set", "Stores the union of multiple sets in a key.", set::sunionstore),
+    Command::new("sdiffstore", -3, &[Write, DenyOom], (1, -1, 1), "set", "Stores the difference of multiple sets in a key.", set::sdiffstore),
+    Command::new("srandmember", -2, &[ReadOnly], (1, 1, 1), "set", "Get one or multiple random members from a set", set::srandmember),
+    Command::new("spop", -2, &[Write, Fast], (1, 1, 1), "set", "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped.", set::spop),
+    // Sorted sets
+    Command::new("zadd", -4, &[Write, DenyOom, Fast], (1, 1, 1), "sorted-set", "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist.", zset::zadd),
+    Command::new("zincrby", 4, &[Write, DenyOom, Fast], (1, 1, 1), "sorted-set", "Increments the score of a member in a sorted set.", zset::zincrby),
+    Command::new("zrem", -3, &[Write, Fast], (1, 1, 1), "sorted-set", "Removes one or more members from a sorted set. Deletes the sorted set if all members were removed.", zset::zrem),
+    Command::new("zscore", 3, &[ReadOnly, Fast], (1, 1, 1), "sorted-set", "Returns the score of a member in a sorted set.", zset::zscore),
+    Command::new("zcard", 2, &[ReadOnly, Fast], (1, 1, 1), "sorted-set", "Returns the number of members in a sorted set.", zset::zcard),
+    Command::new("zrank", -3, &[ReadOnly, Fast], (1, 1, 1), "sorted-set", "Returns the index of a member in a sorted set ordered by ascending scores.", zset::zrank),
+    Command::new("zrange", -4, &[ReadOnly], (1, 1, 1), "sorted-set", "Returns members in a sorted set within a range of indexes.", zset::zrange),
+    Command::new("zpopmin", -2, &[Write, Fast], (1, 1, 1), "sorted-set", "Returns the lowest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.", zset::zpopmin),
+    Command::new("zpopmax", -2, &[Write, Fast], (1, 1, 1), "sorted-set", "Returns the highest-scoring members from a sorted set after removing them. Deletes the sorted set if the last member was popped.", zset::zpopmax),
+    Command::new("zunionstore", -4, &[Write, DenyOom], (1, 1, 1), "sorted-set", "Stores the union of multiple sorted sets in a key.", zset::zunionstore),
+    Command::new("zinterstore", -4, &[Write, DenyOom], (1, 1, 1), "sorted-set", "Stores the intersect of multiple sorted sets in a key.", zset::zinterstore),
+    // Streams
+    Command::new("xadd", -5, &[Write, DenyOom, Fast], (1, 1, 1), "stream", "Appends a new message to a stream. Creates the key if it doesn't exist.", stream::xadd),
+    Command::new("xlen", 2, &[ReadOnly, Fast], (1, 1, 1), "stream", "Return the number of messages in a stream.", stream::xlen),
+    Command::new("xrange", -4, &[ReadOnly], (1, 1, 1), "stream", "Returns the messages from a stream within a range of IDs.", stream::xrange),
+    Command::new("xrevrange", -4, &[ReadOnly], (1, 1, 1), "stream", "Returns the messages from a stream within a range of IDs in reverse order.", stream::xrevrange),
+    Command::new("xtrim", -4, &[Write], (1, 1, 1), "stream", "Deletes messages from the beginning of a stream.", stream::xtrim),
+    Command::new("xread", -4, &[ReadOnly, Blocking], (0, 0, 0), "stream", "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.", stream::xread),
+    Command::new("xreadgroup", -7, &[Write, Blocking], (0, 0, 0), "stream", "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.", stream::xreadgroup),
+    Command::new("xgroup", -3, &[Write, DenyOom], (2, 2, 1), "stream", "Creates, destroys and manages consumer groups and their consumers.", stream::xgroup),
+    Command::new("xack", -4, &[Write, Fast], (1, 1, 1), "stream", "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.", stream::xack),
+    Command::new("xpending", -3, &[ReadOnly], (1, 1, 1), "stream", "Returns the information and entries from a stream consumer group's pending entries list.", stream::xpending),
+    Command::new("xclaim", -6, &[Write, Fast], (1, 1, 1), "stream", "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member.", stream::xclaim),
+    Command::new("xautoclaim", -6, &[Write, Fast], (1, 1, 1), "stream", "Changes, or acquires, ownership of messages in a consumer group, as if the messages were delivered to as consumer group member.", stream::xautoclaim),
+    // Server
+    Command::new("command", -1, &[], (0, 0, 0), "server", "Returns detailed information about all commands.", command),
+    Command::new("config", -2, &[Admin], (0, 0, 0), "server", "Gets or sets configuration parameters.", config),
+    Command::new("info", -1, &[], (0, 0, 0), "server", "Returns information and statistics about the server.", info),
+    Command::new("sync", 1, &[Admin], (0, 0, 0), "server", "Sends every key and value to a replica.", sync),
+    Command::new("getserverid", 1, &[Fast], (0, 0, 0), "server", "Returns the process id of the server.", get_server_id),
+    Command::new("setleader", 1, &[Admin], (0, 0, 0), "server", "Restarts this replica as the leader.", set_leader),
+];
+
+pub fn lookup(name: &str) -> Option<&'static Command> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name.eq_ignore_ascii_case(name))
+}
+
+// Runs a single command and returns the reply to send back
+pub fn execute(message: &RESPMessage, context: &mut Context) -> Result<RESPMessage> {
+    let (name, args) = message.to_command()?;
+
+    let Some(command) = lookup(&name) else {
+        // Like Redis, quote the first few arguments to help spot a client speaking the wrong protocol
+        let quoted: String = args
+            .iter()
+            .take(3)
+            .map(|arg| match arg.pack_bytes() {
+                Ok(arg) => format!("'{}' ", String::from_utf8_lossy(&arg[..arg.len().min(128)])),
+                Err(_) => "'' ".to_string(),
+            })
+            .collect();
+        return Ok(RESPMessage::Error(format!(
+            "ERR unknown command '{}', with args beginning with: {}",
+            name, quoted
+        )));
+    };
+    if !command.accepts(args.len() + 1) {
+        return Ok(wrong_arity(command.name));
+    }
+    if command.flags.iter().any(|flag| matches!(flag, DenyOom)) && !context.keyspace.make_room(0) {
+        return Ok(out_of_memory());
+    }
+
+    (command.handler)(context, args).or_else(|e| match e.downcast::<WrongType>() {
+        Ok(wrong_type) => Ok(RESPMessage::Error(wrong_type.to_string())),
+        Err(e) => Err(e),
+    })
+}
+
+pub fn wrong_arity(name: &str) -> RESPMessage {
+    RESPMessage::Error(format!("ERR wrong number of arguments for '{}' command", name))
+}
+
+// Replied to commands that may use more memory when used memory is over maxmemory and the
+// eviction policy does not allow making room
+pub fn out_of_memory() -> RESPMessage {
+    RESPMessage::Error("OOM command not allowed when used memory > 'maxmemory'.".to_string())
+}
+
+pub fn syntax_error() -> Error {
+    Error::msg("syntax error")
+}
+
+// Turns a time in seconds or milliseconds, either from now or since the unix epoch,
+// into a unix time in milliseconds. None if it does not fit.
+fn unix_time_ms(time: i64, seconds: bool, relative: bool) -> Option<i64> {
+    let ms = if seconds { time.checked_mul(1000)? } else { time };
+    if relative {
+        ms.checked_add(now() as i64)
+    } else {
+        Some(ms)
+    }
+}
+
+// The expiry SET, GETEX, SETEX and PSETEX take after EX, PX, EXAT or PXAT (lowercased in
+// option), as a unix time in milliseconds. It has to be positive and fit, or the command
+// named fails.
+pub fn expire_time(option: &str, time: &RESPMessage, name: &str) -> Result<u128> {
+    let time = parse_integer(time)?;
+    let seconds = matches!(option, "ex" | "exat");
+    let relative = matches!(option, "ex" | "px");
+    let ms = Some(time)
+        .filter(|time| *time > 0)
+        .and_then(|time| unix_time_ms(time, seconds, relative))
+        .ok_or_else(|| Error::msg(format!("invalid expire time in '{}' command", name)))?;
+    Ok(ms as u128)
+}
+
+// Matches string against a glob-style pattern the way Redis' stringmatchlen does:
+// * matches anything, ? any single byte, [abc] or [a-z] a set of bytes ([^...] negates
+// it) and a backslash escapes the next byte
+pub fn glob_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let eq = |a: u8, b: u8| if nocase { a.eq_ignore_ascii_case(&b) } else { a == b };
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last *, should the rest of the pattern fail to match
+    let mut backtrack = None;
+
+    while s < string.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, s));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(p + 1),
+            Some(b'[') => {
+                let mut i = p + 1;
+                let negate = pattern.get(i) == Some(&b'^');
+                if negate {
+                    i += 1;
+                }
+                let mut found = false;
+                while i < pattern.len() && pattern[i] != b']' {
+                    if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                        i += 1;
+                        found |= eq(pattern[i], string[s]);
+                    } else if pattern.get(i + 1) == Some(&b'-') && i + 2 < pattern.len() {
+                        let (mut start, mut end) = (pattern[i], pattern[i + 2]);
+                        if start > end {
+                            (start, end) = (end, start);
+                        }
+                        let byte = string[s];
+                        found |= (start..=end).contains(&byte)
+                            || nocase && (start..=end).contains(&byte.to_ascii_lowercase())
+                            || nocase && (start..=end).contains(&byte.to_ascii_uppercase());
+                        i += 2;
+                    } else {
+                        found |= eq(pattern[i], string[s]);
+                    }
+                    i += 1;
+                }
+                (found != negate).then_some((i + 1).min(pattern.len()))
+            }
+            Some(b'\\') if p + 1 < pattern.len() => eq(pattern[p + 1], string[s]).then_some(p + 2),
+            Some(&byte) => eq(byte, string[s]).then_some(p + 1),
+            None => None,
+        };
+
+        match (matched, backtrack) {
+            (Some(next), _) => {
+                p = next;
+                s += 1;
+            }
+            // Let the last * swallow one more byte and try again
+            (None, Some((star, start))) => {
+                backtrack = Some((star, start + 1));
+                p = star + 1;
+                s = start + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|byte| *byte == b'*')
+}
+
+pub fn parse_integer(arg: &RESPMessage) -> Result<i64> {
+    arg.pack_string()
+        .ok()
+        .and_then(|arg| arg.parse().ok())
+        .ok_or_else(|| Error::msg("value is not an integer or out of range"))
+}
+
+fn ping(_: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    Ok(match args {
+        [] => RESPMessage::SimpleString("PONG".to_string()),
+        [message] => message.clone(),
+        _ => wrong_arity("ping"),
+    })
+}
+
+fn echo(_: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    Ok(args[0].clone())
+}
+
+// HELLO [protover [AUTH username password] [SETNAME clientname]]
+// Switches the protocol the connection speaks and describes the server
+fn hello(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let client = &mut *context.client;
+    let mut protocol = client.protocol;
+    if let Some(version) = args.first() {
+        protocol = match version.pack_string().map(|v| v.parse::<i64>()) {
+            Ok(Ok(2)) => RESPVersion::RESP2,
+            Ok(Ok(3)) => RESPVersion::RESP3,
+            Ok(Ok(_)) => {
+                return Ok(RESPMessage::Error(
+                    "NOPROTO unsupported protocol version".to_string(),
+                ))
+            }
+            _ => {
+                return Ok(RESPMessage::Error(
+                    "ERR Protocol version is not an integer or out of range".to_string(),
+                ))
+            }
+        };
+    }
+
+    let mut name = None;
+    let mut options = args.iter().skip(1);
+    while let Some(option) = options.next() {
+        let option = option.pack_string().unwrap_or_default().to_string();
+        match option.to_ascii_lowercase().as_ref() {
+            // There are no users or passwords yet, so any credentials are accepted
+            "auth" if options.len() >= 2 => {
+                options.nth(1);
+            }
+            "setname" if options.len() >= 1 => {
+                name = options.next().and_then(|n| n.pack_string().ok()).map(str::to_string);
+            }
+            _ => {
+                return Ok(RESPMessage::Error(format!(
+                    "ERR Syntax error in HELLO option '{}'",
+                    option
+                )))
+            }
+        }
+    }
+
+    client.protocol = protocol;
+    if name.is_some() {
+        client.name = name;
+    }
+
+    let role = if context.config.read().unwrap().is_master() {
+        "master"
+    } else {
+        "replica"
+    };
+    let bulk = |s: &'static str| RESPMessage::BulkString(Bytes::from_static(s.as_bytes()));
+    Ok(RESPMessage::Map(vec![
+        (bulk("server"), bulk("tinyredis")),
+        (bulk("version"), bulk(env!("CARGO_PKG_VERSION"))),
+        (
+            bulk("proto"),
+            RESPMessage::Integer(match protocol {
+                RESPVersion::RESP2 => 2,
+                RESPVersion::RESP3 => 3,
+            }),
+        ),
+        (bulk("id"), RESPMessage::Integer(client.id as i64)),
+        (bulk("mode"), bulk("standalone")),
+        (bulk("role"), bulk(role)),
+        (bulk("modules"), RESPMessage::Array(vec![])),
+    ]))
+}
+
+fn get(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    Ok(match context.keyspace.lock(key).get(key) {
+        Some(value) => RESPMessage::BulkString(value.as_string()?.clone().into()),
+        None => RESPMessage::Null,
+    })
+}
+
+// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+//     PXAT unix-time-milliseconds | KEEPTTL]
+fn set(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let value = args[1].pack_bytes()?;
+
+    let (mut nx, mut xx, mut get, mut keep_ttl) = (false, false, false, false);
+    let mut expires_at = None;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let option = option.pack_string().unwrap_or_default().to_ascii_lowercase();
+        match option.as_ref() {
+            "nx" if !xx => nx = true,
+            "xx" if !nx => xx = true,
+            "get" => get = true,
+            "keepttl" if expires_at.is_none() => keep_ttl = true,
+            "ex" | "px" | "exat" | "pxat" if !keep_ttl && expires_at.is_none() => {
+                let time = options.next().ok_or_else(syntax_error)?;
+                expires_at = Some(expire_time(&option, time, "set")?);
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let value = Value::String(value.to_vec());
+    if !context.keyspace.make_room_for([(key, &value)]) {
+        return Ok(out_of_memory());
+    }
+
+    let mut cache = context.keyspace.lock(key);
+    let old = if get {
+        cache.get(key).map(|value| value.as_string().cloned()).transpose()?
+    } else {
+        None
+    };
+    let old = || old.map_or(RESPMessage::Null, |value| RESPMessage::BulkString(value.into()));
+    let exists = cache.contains(key);
+    if (nx && exists) || (xx && !exists) {
+        return Ok(old());
+    }
+    if keep_ttl {
+        expires_at = cache.expires_at(key);
+    }
+
+    cache.set_expiring_at(key.to_vec(), value, expires_at);
+    Ok(if get {
+        old()
+    } else {
+        RESPMessage::SimpleString("OK".to_string())
+    })
+}
+
+// DEL key [key ...]
+fn del(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let keys = args
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut shards = context.keyspace.lock_all(keys.iter().copied());
+    let deleted = keys.iter().filter(|key| shards.shard(key).delete(key)).count();
+    Ok(RESPMessage::Integer(deleted as i64))
+}
+
+fn type_(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let mut cache = context.keyspace.lock(key);
+    let name = cache.get(key).map_or("none", Value::type_name);
+    Ok(RESPMessage::SimpleString(name.to_string()))
+}
+
+fn expire(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    set_expiry(context, args, "expire", true, true)
+}
+
+fn pexpire(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    set_expiry(context, args, "pexpire", false, true)
+}
+
+fn expireat(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    set_expiry(context, args, "expireat", true, false)
+}
+
+fn pexpireat(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    set_expiry(context, args, "pexpireat", false, false)
+}
+
+// EXPIRE key seconds [NX | XX | GT | LT] and its PEXPIRE, EXPIREAT and PEXPIREAT variants.
+// A time that has already passed deletes the key.
+fn set_expiry(
+    context: &mut Context,
+    args: &[RESPMessage],
+    name: &str,
+    seconds: bool,
+    relative: bool,
+) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let time = parse_integer(&args[1])?;
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &args[2..] {
+        let option = option.pack_string().unwrap_or_default();
+        match option.to_ascii_lowercase().as_ref() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "gt" => gt = true,
+            "lt" => lt = true,
+            _ => return Err(Error::msg(format!("Unsupported option {}", option))),
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Err(Error::msg(
+            "NX and XX, GT or LT options at the same time are not compatible",
+        ));
+    }
+    if gt && lt {
+        return Err(Error::msg("GT and LT options at the same time are not compatible"));
+    }
+
+    let expires_at = unix_time_ms(time, seconds, relative)
+        .ok_or_else(|| Error::msg(format!("invalid expire time in '{}' command", name)))?;
+
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        return Ok(RESPMessage::Integer(0));
+    }
+    // Keys without an expiry count as never expiring for GT and LT
+    let allowed = match cache.expires_at(key) {
+        None => !xx && !gt,
+        Some(current) => {
+            let current = current as i64;
+            !nx && (!gt || expires_at > current) && (!lt || expires_at < current)
+        }
+    };
+    if !allowed {
+        return Ok(RESPMessage::Integer(0));
+    }
+
+    if expires_at <= now() as i64 {
+        cache.delete(key);
+    } else {
+        cache.set_expires_at(key, Some(expires_at as u128));
+    }
+    Ok(RESPMessage::Integer(1))
+}
+
+fn ttl(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    get_expiry(context, args, |expires_at| (expires_at.saturating_sub(now()) + 500) / 1000)
+}
+
+fn pttl(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    get_expiry(context, args, |expires_at| expires_at.saturating_sub(now()))
+}
+
+fn expiretime(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    get_expiry(context, args, |expires_at| expires_at / 1000)
+}
+
+fn pexpiretime(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    get_expiry(context, args, |expires_at| expires_at)
+}
+
+// Replies with -2 if the key does not exist, -1 if it has no expiry, or its expiry
+// (a unix time in milliseconds) converted by reply
+fn get_expiry(
+    context: &mut Context,
+    args: &[RESPMessage],
+    reply: fn(u128) -> u128,
+) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        return Ok(RESPMessage::Integer(-2));
+    }
+    Ok(RESPMessage::Integer(match cache.expires_at(key) {
+        Some(expires_at) => reply(expires_at) as i64,
+        None => -1,
+    }))
+}
+
+fn persist(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let mut cache = context.keyspace.lock(key);
+    let removed = cache.expires_at(key).is_some() && cache.set_expires_at(key, None);
+    Ok(RESPMessage::Integer(removed as i64))
+}
+
+// OBJECT FREQ key
+fn object(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let subcommand = args[0].pack_string()?.to_ascii_lowercase();
+    match (subcommand.as_ref(), args.len()) {
+        ("freq", 2) => {
+            let key = args[1].pack_bytes()?;
+            let mut cache = context.keyspace.lock(key);
+            if !cache.policy.is_lfu() {
+                return Err(Error::msg(
+                    "An LFU maxmemory policy is not selected, access frequency not tracked. \
+                     Please note that when switching between policies at runtime LRU and LFU \
+                     data will take some time to adjust.",
+                ));
+            }
+            Ok(cache
+                .frequency(key)
+                .map_or(RESPMessage::Null, |frequency| RESPMessage::Integer(frequency.into())))
+        }
+        ("freq", _) => Ok(wrong_arity("object|freq")),
+        ("encoding", 2) => {
+            let key = args[1].pack_bytes()?;
+            let mut cache = context.keyspace.lock(key);
+            Ok(cache.get(key).map_or(RESPMessage::Null, |value| {
+                RESPMessage::BulkString(Bytes::from_static(value.encoding().as_bytes()))
+            }))
+        }
+        ("encoding", _) => Ok(wrong_arity("object|encoding")),
+        _ => Err(Error::msg(format!(
+            "unknown subcommand '{}'. Try OBJECT HELP.",
+            subcommand
+        ))),
+    }
+}
+
+// COMMAND [COUNT | INFO [command ...] | DOCS [command ...]]
+fn command(_: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let Some(subcommand) = args.first() else {
+        return Ok(RESPMessage::Array(COMMANDS.iter().map(Command::info).collect()));
+    };
+    let subcommand = subcommand.pack_string()?;
+    let names = args[1..]
+        .iter()
+        .map(|name| name.pack_string())
+        .collect::<Result<Vec<_>>>()?;
+    // With no names, INFO and DOCS describe every command
+    let selected: Vec<Option<&Command>> = if names.is_empty() {
+        COMMANDS.iter().map(Some).collect()
+    } else {
+        names.iter().map(|name| lookup(name)).collect()
+    };
+
+    Ok(match subcommand.to_ascii_lowercase().as_ref() {
+        "count" if names.is_empty() => RESPMessage::Integer(COMMANDS.len() as i64),
+        "info" => RESPMessage::Array(
+            selected
+                .into_iter()
+                .map(|command| command.map_or(RESPMessage::NullArray, Command::info))
+                .collect(),
+        ),
+        // Commands that don't exist are left out rather than replied with as nulls
+        "docs" => RESPMessage::Map(
+            selected
+                .into_iter()
+                .flatten()
+                .map(|command| {
+                    let name = RESPMessage::BulkString(Bytes::from_static(command.name.as_bytes()));
+                    (name, command.docs())
+                })
+                .collect(),
+        ),
+        "count" => wrong_arity("command|count"),
+        _ => RESPMessage::Error(format!(
+            "ERR unknown subcommand '{}'. Try COMMAND HELP.",
+            subcommand
+        )),
+    })
+}
+
+// CONFIG GET parameter [parameter ...] | CONFIG SET parameter value [parameter value ...]
+fn config(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let subcommand = args[0].pack_string()?.to_ascii_lowercase();
+    let args = args[1..]
+        .iter()
+        .map(|arg| Ok(arg.pack_string()?.to_string()))
+        .collect::<Result<Vec<_>>>()?;
+
+    match subcommand.as_ref() {
+        "get" if !args.is_empty() => {
+            let entries = context.config.read().unwrap().entries();
+            let bulk = |s: &str| RESPMessage::BulkString(Bytes::copy_from_slice(s.as_bytes()));
+            Ok(RESPMessage::Map(
+                entries
+                    .into_iter()
+                    .filter(|(name, _)| {
+                        args.iter()
+                            .any(|pattern| glob_match(pattern.as_bytes(), name.as_bytes(), true))
+                    })
+                    .map(|(name, value)| (bulk(name), bulk(&value)))
+                    .collect(),
+            ))
+        }
+        "set" if !args.is_empty() && args.len() % 2 == 0 => {
+            // Every parameter is applied to a copy first, so either all of them change or none do
+            let mut config = context.config.read().unwrap().clone();
+            let known: Vec<_> = config.entries().into_iter().map(|(name, _)| name).collect();
+            for pair in args.chunks(2) {
+                let (name, value) = (&pair[0], &pair[1]);
+                if !known.contains(&name.to_ascii_lowercase().as_ref()) {
+                    return Err(Error::msg(format!(
+                        "Unknown option or number of arguments for CONFIG SET - '{}'",
+                        name
+                    )));
+                }
+                config.set(name, value).map_err(|e| {
+                    Error::msg(format!(
+                        "CONFIG SET failed (possibly related to argument '{}') - {}",
+                        name, e
+                    ))
+                })?;
+            }
+
+            context.keyspace.configure(&config);
+            *context.config.write().unwrap() = config;
+            Ok(RESPMessage::SimpleString("OK".to_string()))
+        }
+        "get" | "set" => Ok(wrong_arity(&format!("config|{}", subcommand))),
+        _ => Err(Error::msg(format!(
+            "unknown subcommand '{}'. Try CONFIG HELP.",
+            subcommand
+        ))),
+    }
+}
+
+// INFO [section ...]
+// Replies with "field:value" lines grouped into sections, the way Redis does
+fn info(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let requested = args
+        .iter()
+        .map(|section| Ok(section.pack_string()?.to_ascii_lowercase()))
+        .collect::<Result<Vec<_>>>()?;
+    let everything = requested.is_empty()
+        || requested
+            .iter()
+            .any(|section| matches!(section.as_ref(), "all" | "default" | "everything"));
+
+    let config = context.config.read().unwrap().clone();
+    let keyspace = context.keyspace;
+    let stats = keyspace.stats();
+    let mut sections: Vec<(&str, Vec<(&str, String)>)> = vec![
+        (
+            "Server",
+            vec![
+                ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+                ("process_id", process::id().to_string()),
+                ("tcp_port", config.port),
+                ("hz", config.hz.to_string()),
+            ],
+        ),
+        (
+            "Memory",
+            vec![
+                ("used_memory", keyspace.used_memory().to_string()),
+                ("used_memory_human", human_bytes(keyspace.used_memory())),
+                ("maxmemory", config.maxmemory.to_string()),
+                ("maxmemory_human", human_bytes(config.maxmemory)),
+                ("maxmemory_policy", config.maxmemory_policy.name().to_string()),
+            ],
+        ),
+        (
+            "Stats",
+            vec![
+                ("expired_keys", stats.expired_keys.to_string()),
+                ("expired_stale_perc", format!("{:.2}", stats.expired_stale_perc)),
+                (
+                    "expired_time_cap_reached_count",
+                    stats.expired_time_cap_reached_count.to_string(),
+                ),
+                (
+                    "expire_cycle_cpu_milliseconds",
+                    stats.expire_cycle_cpu_milliseconds.to_string(),
+                ),
+                ("evicted_keys", stats.evicted_keys.to_string()),
+            ],
+        ),
+    ];
+    // Like Redis, only databases that hold keys are listed
+    let key_count = keyspace.key_count();
+    let databases = if key_count > 0 {
+        let db = format!("keys={},expires={}", key_count, keyspace.volatile_count());
+        vec![("db0", db)]
+    } else {
+        vec![]
+    };
+    sections.push(("Keyspace", databases));
+
+    let report = sections
+        .into_iter()
+        .filter(|(name, _)| everything || requested.contains(&name.to_ascii_lowercase()))
+        .map(|(name, fields)| {
+            let fields: String = fields
+                .into_iter()
+                .map(|(field, value)| format!("{}:{}\r\n", field, value))
+                .collect();
+            format!("# {}\r\n{}", name, fields)
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    Ok(RESPMessage::Verbatim("txt".to_string(), report.into()))
+}
+
+// Formats a size the way INFO's *_human fields do, e.g. 1.50K
+fn human_bytes(bytes: usize) -> String {
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let units = ["K", "M", "G", "T"];
+    let (mut size, mut unit) = (bytes as f64 / 1024.0, 0);
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2}{}", size, units[unit])
+}
+
+fn sync(context: &mut Context, _: &[RESPMessage]) -> Result<RESPMessage> {
+    // Acquire the lock on each shard in turn and retrieve all keys
+    let entries = context.keyspace.get_key(); //get keys and values from cache
+
+    // Each key is sent as an array of the key, its type, when it expires as unix time in
+    // milliseconds or -1 if it doesn't, and the elements of its value
+    let bulk = |bytes: Vec<u8>| RESPMessage::BulkString(bytes.into());
+    Ok(RESPMessage::Array(
+        entries
+            .into_iter()
+            .map(|(key, value, expires_at)| {
+                let (type_name, elements) = value.dump();
+                let expires_at = expires_at.map_or("-1".to_string(), |at| at.to_string());
+                let header = [
+                    bulk(key),
+                    bulk(type_name.as_bytes().to_vec()),
+                    bulk(expires_at.into_bytes()),
+                ];
+                RESPMessage::Array(header.into_iter().chain(elements.into_iter().map(bulk)).collect())
+            })
+            .collect(),
+    ))
+}
+
+fn get_server_id(_: &mut Context, _: &[RESPMessage]) -> Result<RESPMessage> {
+    println!("{}", process::id()); // todo: remove test print
+    Ok(RESPMessage::SimpleString(process::id().to_string()))
+}
+
+fn set_leader(_: &mut Context, _: &[RESPMessage]) -> Result<RESPMessage> {
+    println!("Recieved leader message, becoming leader...");
+    // start server on 6379
+    Process::new(std::env::args().next().unwrap())
+        .arg("6379")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Failed to start new instance of the program");
+
+    process::exit(0);
+}
@@ -0,0 +1,151 @@
+use crate::{cache::EvictionPolicy, resp::ProtocolLimits, value::ListpackLimits};
+use anyhow::{Error, Result};
+
+pub const DEFAULT_PORT: &str = "6379";
+
+// Settings taken from the command line, using the same names as redis.conf:
+// cargo run 6380 --proto-max-bulk-len 64mb
+#[derive(Clone)]
+pub struct Config {
+    pub port: String,
+    pub limits: ProtocolLimits,
+    // How many times a second background tasks such as active expiry run
+    pub hz: usize,
+    // In bytes, 0 means there is no limit
+    pub maxmemory: usize,
+    pub maxmemory_policy: EvictionPolicy,
+    pub maxmemory_samples: usize,
+    pub lfu_log_factor: usize,
+    // In minutes
+    pub lfu_decay_time: usize,
+    // Largest hash kept as a listpack rather than a hash table
+    pub hash_max_listpack: ListpackLimits,
+    // Largest set of integers kept as an intset rather than a hash table
+    pub set_max_intset_entries: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT.to_string(),
+            limits: ProtocolLimits::default(),
+            hz: 10,
+            maxmemory: 0,
+            // Unlike Redis, which refuses writes by default, keys are evicted so the
+            // cache always has room for new ones
+            maxmemory_policy: EvictionPolicy::AllKeysLru,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            hash_max_listpack: ListpackLimits {
+                entries: 128,
+                value: 64,
+            },
+            set_max_intset_entries: 512,
+        }
+    }
+}
+
+impl Config {
+    // The port comes first and is optional, every other setting is a --name value pair
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut config = Self::default();
+        let mut args = args.peekable();
+
+        if let Some(port) = args.next_if(|arg| !arg.starts_with("--")) {
+            config.port = port;
+        }
+        while let Some(arg) = args.next() {
+            let name = arg
+                .strip_prefix("--")
+                .ok_or_else(|| Error::msg(format!("Unexpected argument '{}'", arg)))?;
+            let value = args
+                .next()
+                .ok_or_else(|| Error::msg(format!("Missing value for '{}'", arg)))?;
+            config.set(name, &value)?;
+        }
+        Ok(config)
+    }
+
+    pub fn is_master(&self) -> bool {
+        self.port == DEFAULT_PORT
+    }
+
+    // Changes a single setting by name
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        match name.to_ascii_lowercase().as_ref() {
+            "proto-max-bulk-len" => self.limits.max_bulk_len = parse_memory(value)?,
+            "proto-max-multibulk-len" => self.limits.max_multibulk_len = parse_number(value)?,
+            "proto-max-nesting-depth" => self.limits.max_nesting_depth = parse_number(value)?,
+            "proto-max-inline-len" => self.limits.max_inline_len = parse_memory(value)?,
+            // Redis clamps hz to the same range
+            "hz" => self.hz = parse_number(value)?.clamp(1, 500),
+            "maxmemory" => self.maxmemory = parse_memory(value)?,
+            "maxmemory-policy" => {
+                self.maxmemory_policy = EvictionPolicy::parse(value).ok_or_else(|| {
+                    let names: Vec<_> = EvictionPolicy::ALL.iter().map(|p| p.name()).collect();
+                    Error::msg(format!(
+                        "argument(s) must be one of the following: {}",
+                        names.join(", ")
+                    ))
+                })?
+            }
+            "maxmemory-samples" => self.maxmemory_samples = parse_number(value)?.max(1),
+            "lfu-log-factor" => self.lfu_log_factor = parse_number(value)?,
+            "lfu-decay-time" => self.lfu_decay_time = parse_number(value)?,
+            "hash-max-listpack-entries" => self.hash_max_listpack.entries = parse_number(value)?,
+            "hash-max-listpack-value" => self.hash_max_listpack.value = parse_number(value)?,
+            "set-max-intset-entries" => self.set_max_intset_entries = parse_number(value)?,
+            _ => return Err(Error::msg(format!("Unknown option '{}'", name))),
+        }
+        Ok(())
+    }
+
+    // Every setting with its current value, as CONFIG GET lists them
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("port", self.port.clone()),
+            ("proto-max-bulk-len", self.limits.max_bulk_len.to_string()),
+            ("proto-max-multibulk-len", self.limits.max_multibulk_len.to_string()),
+            ("proto-max-nesting-depth", self.limits.max_nesting_depth.to_string()),
+            ("proto-max-inline-len", self.limits.max_inline_len.to_string()),
+            ("hz", self.hz.to_string()),
+            ("maxmemory", self.maxmemory.to_string()),
+            ("maxmemory-policy", self.maxmemory_policy.name().to_string()),
+            ("maxmemory-samples", self.maxmemory_samples.to_string()),
+            ("lfu-log-factor", self.lfu_log_factor.to_string()),
+            ("lfu-decay-time", self.lfu_decay_time.to_string()),
+            ("hash-max-listpack-entries", self.hash_max_listpack.entries.to_string()),
+            ("hash-max-listpack-value", self.hash_max_listpack.value.to_string()),
+            ("set-max-intset-entries", self.set_max_intset_entries.to_string()),
+        ]
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| Error::msg(format!("argument couldn't be parsed into an integer: '{}'", value)))
+}
+
+// Parses a size the way redis.conf does: 1k is 1000 bytes while 1kb is 1024 bytes,
+// and the same goes for m/mb and g/gb
+pub fn parse_memory(value: &str) -> Result<usize> {
+    let lowercase = value.to_ascii_lowercase();
+    let units: [(&str, usize); 6] = [
+        ("kb", 1 << 10),
+        ("mb", 1 << 20),
+        ("gb", 1 << 30),
+        ("k", 1_000),
+        ("m", 1_000_000),
+        ("g", 1_000_000_000),
+    ];
+
+    let (number, multiplier) = units
+        .iter()
+        .find_map(|(unit, multiplier)| Some((lowercase.strip_suffix(unit)?, *multiplier)))
+        .unwrap_or((&lowercase, 1));
+    parse_number(number)?
+        .checked_mul(multiplier)
+        .ok_or_else(|| Error::msg(format!("argument must be a memory value: '{}'", value)))
+}
@@ -0,0 +1,75 @@
+use crate::{config::Config, keyspace::Keyspace};
+use std::{
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+use tokio::time::{self, Duration};
+
+// How many keys with an expiry are checked at a time
+const KEYS_PER_LOOP: usize = 20;
+// Sampling stops once no more than this percentage of a sample had expired
+const ACCEPTABLE_STALE_PERC: usize = 25;
+// Share of each tick the cycle may spend removing keys
+const CYCLE_CPU_PERC: u64 = 25;
+
+// Keys that are written once and never read again would otherwise only be removed
+// when they are looked up, so like Redis, hz times a second a random sample of keys
+// with an expiry is checked and the expired ones are removed.
+// hz is read again on every tick so CONFIG SET takes effect straight away.
+pub async fn run(keyspace: Arc<Keyspace>, config: Arc<RwLock<Config>>) {
+    let mut next_shard = 0;
+    loop {
+        let hz = config.read().unwrap().hz as u64;
+        time::sleep(Duration::from_micros(1_000_000 / hz)).await;
+        cycle(
+            &keyspace,
+            Duration::from_micros(1_000_000 * CYCLE_CPU_PERC / 100 / hz),
+            &mut next_shard,
+        );
+    }
+}
+
+// Goes through the shards in turn, sampling each while a large share of its keys turn out
+// to be expired, as that means there are probably many more, but gives up once the time
+// budget is spent.
+// Like Redis' current_db, next_shard carries over to the next cycle, which starts with the
+// shard after the last one sampled so a cycle that runs out of time does not keep the
+// shards after it from ever being sampled.
+// The lock is taken for each sample rather than the whole cycle, so commands are not
+// held up for longer than one sample takes.
+fn cycle(keyspace: &Keyspace, budget: Duration, next_shard: &mut usize) {
+    let start = Instant::now();
+    let (mut sampled, mut expired) = (0, 0);
+    let mut time_cap_reached = false;
+
+    let shards = keyspace.shards();
+    'shards: for _ in 0..shards.len() {
+        let shard = &shards[*next_shard % shards.len()];
+        *next_shard = (*next_shard + 1) % shards.len();
+        loop {
+            let (sample_size, sample_expired) = shard.lock().unwrap().expire_sample(KEYS_PER_LOOP);
+            sampled += sample_size;
+            expired += sample_expired;
+
+            if start.elapsed() > budget {
+                time_cap_reached = true;
+                break 'shards;
+            }
+            if sample_size == 0 || sample_expired * 100 <= sample_size * ACCEPTABLE_STALE_PERC {
+                break;
+            }
+        }
+    }
+
+    let stats = &mut keyspace.stats.lock().unwrap();
+    stats.expire_cycle_cpu_milliseconds += start.elapsed().as_millis() as u64;
+    if time_cap_reached {
+        stats.expired_time_cap_reached_count += 1;
+    }
+    let stale_perc = if sampled == 0 {
+        0.0
+    } else {
+        expired as f64 * 100.0 / sampled as f64
+    };
+    stats.expired_stale_perc = stale_perc * 0.05 + stats.expired_stale_perc * 0.95;
+}
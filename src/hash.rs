@@ -0,0 +1,465 @@
+use crate::{
+    cache::Cache,
+    command::{glob_match, parse_integer, syntax_error, wrong_arity, Context},
+    resp::{format_double, RESPMessage},
+    value::{parse_float, parse_i64, ListpackLimits, Value, WrongType},
+};
+use anyhow::{Error, Result};
+use slab::Slab;
+use std::{collections::HashMap, mem::size_of};
+
+// A field and its value
+type Pair = (Vec<u8>, Vec<u8>);
+
+// Fields and their values. Like Redis, a hash starts out as a listpack: the pairs side by
+// side in a single vector, which takes up little memory and is quick to search while there
+// are few of them. Once it holds more fields than hash-max-listpack-entries, or a field or
+// value longer than hash-max-listpack-value, it is converted to a hash table for good.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    encoding: Encoding,
+    // How many bytes the pairs take up, kept up to date as they are set and removed
+    size: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Encoding {
+    Listpack(Vec<Pair>),
+    Table(Table),
+}
+
+// Counts a pointer and length for both the field and the value, plus in a hash table the
+// copy of the field it keeps to find its slot
+fn pair_size(field: &[u8], value: &[u8], encoding: &Encoding) -> usize {
+    let index = match encoding {
+        Encoding::Listpack(_) => 0,
+        Encoding::Table(_) => field.len() + size_of::<(Vec<u8>, usize)>(),
+    };
+    field.len() + value.len() + 2 * size_of::<Vec<u8>>() + index
+}
+
+// A hash table whose pairs stay in the same slot of a slab from when they are set until they
+// are removed, so HSCAN can walk it by slot and return every pair that was there all along
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    slots: HashMap<Vec<u8>, usize>,
+    pairs: Slab<Pair>,
+}
+
+impl Table {
+    fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        self.slots.get(field).map(|slot| &self.pairs[*slot].1)
+    }
+
+    // Returns the value the field had, if any
+    fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        match self.slots.get(&field) {
+            Some(slot) => Some(std::mem::replace(&mut self.pairs[*slot].1, value)),
+            None => {
+                let slot = self.pairs.insert((field.clone(), value));
+                self.slots.insert(field, slot);
+                None
+            }
+        }
+    }
+
+    // Returns the value the field had, if any
+    fn remove(&mut self, field: &[u8]) -> Option<Vec<u8>> {
+        let slot = self.slots.remove(field)?;
+        Some(self.pairs.remove(slot).1)
+    }
+
+    // Up to count pairs from the slot at cursor on, along with the slot to carry on from, which
+    // is 0 once the end is reached. Like Redis does with empty buckets, no more than ten times
+    // count slots are looked at, so a call returns quickly even when most slots are empty.
+    fn scan(&self, cursor: usize, count: usize) -> (usize, Vec<&Pair>) {
+        let end = self.pairs.capacity();
+        let mut slot = cursor;
+        let mut pairs = vec![];
+        let mut visited = 0;
+        while slot < end && pairs.len() < count && visited < count.saturating_mul(10) {
+            pairs.extend(self.pairs.get(slot));
+            slot += 1;
+            visited += 1;
+        }
+        (if slot < end { slot } else { 0 }, pairs)
+    }
+}
+
+impl FromIterator<Pair> for Table {
+    fn from_iter<I: IntoIterator<Item = Pair>>(pairs: I) -> Self {
+        let mut table = Self::default();
+        for (field, value) in pairs {
+            table.insert(field, value);
+        }
+        table
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Self::Listpack(vec![])
+    }
+}
+
+impl Hash {
+    pub fn encoding(&self) -> &'static str {
+        match self.encoding {
+            Encoding::Listpack(_) => "listpack",
+            Encoding::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => pairs.len(),
+            Encoding::Table(table) => table.len(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Vec<u8>> {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => pairs.iter().find(|(f, _)| f == field).map(|(_, v)| v),
+            Encoding::Table(table) => table.get(field),
+        }
+    }
+
+    // Sets the field, converting the hash to a hash table if it no longer fits the limits.
+    // Returns whether the field is new.
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>, limits: ListpackLimits) -> bool {
+        if let Encoding::Listpack(pairs) = &self.encoding {
+            let too_long = field.len() > limits.value || value.len() > limits.value;
+            let is_new = !pairs.iter().any(|(f, _)| *f == field);
+            if too_long || (is_new && pairs.len() >= limits.entries) {
+                self.convert();
+            }
+        }
+
+        let added = pair_size(&field, &value, &self.encoding);
+        let len = value.len();
+        let old = match &mut self.encoding {
+            Encoding::Listpack(pairs) => match pairs.iter_mut().find(|(f, _)| *f == field) {
+                Some(pair) => Some(std::mem::replace(&mut pair.1, value)),
+                None => {
+                    pairs.push((field, value));
+                    None
+                }
+            },
+            Encoding::Table(table) => table.insert(field, value),
+        };
+        match old {
+            Some(old) => {
+                self.size = self.size + len - old.len();
+                false
+            }
+            None => {
+                self.size += added;
+                true
+            }
+        }
+    }
+
+    // Returns whether the field was there
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        let removed = match &mut self.encoding {
+            Encoding::Listpack(pairs) => pairs
+                .iter()
+                .position(|(f, _)| f == field)
+                .map(|position| pairs.remove(position).1),
+            Encoding::Table(table) => table.remove(field),
+        };
+        match removed {
+            Some(value) => {
+                self.size -= pair_size(field, &value, &self.encoding);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&Vec<u8>, &Vec<u8>)> + '_> {
+        match &self.encoding {
+            Encoding::Listpack(pairs) => {
+                Box::new(pairs.iter().map(|(field, value)| (field, value)))
+            }
+            Encoding::Table(table) => {
+                Box::new(table.pairs.iter().map(|(_, (field, value))| (field, value)))
+            }
+        }
+    }
+
+    // Measuring the hash again here is no slower than converting it, which walks it anyway
+    fn convert(&mut self) {
+        if let Encoding::Listpack(pairs) = &mut self.encoding {
+            self.encoding = Encoding::Table(pairs.drain(..).collect());
+            self.size = self
+                .iter()
+                .map(|(field, value)| pair_size(field, value, &self.encoding))
+                .sum();
+        }
+    }
+}
+
+fn bulk(element: Vec<u8>) -> RESPMessage {
+    RESPMessage::BulkString(element.into())
+}
+
+fn listpack_limits(context: &Context) -> ListpackLimits {
+    context.config.read().unwrap().hash_max_listpack
+}
+
+// Changes the hash at key, creating it first if the key does not exist
+fn modify<T>(cache: &mut Cache, key: &[u8], f: impl FnOnce(&mut Hash) -> Result<T>) -> Result<T> {
+    if !cache.contains(key) {
+        cache.set_expiring_at(key.to_vec(), Value::Hash(Hash::default()), None);
+    }
+    cache
+        .modify(key, |value| f(value.as_hash_mut()?))
+        .expect("the hash exists")
+}
+
+// Runs f on the hash at key, or replies with missing if there is none
+fn read(
+    context: &mut Context,
+    key: &[u8],
+    missing: RESPMessage,
+    f: impl FnOnce(&Hash) -> RESPMessage,
+) -> Result<RESPMessage> {
+    let mut cache = context.keyspace.lock(key);
+    match cache.get(key) {
+        Some(value) => Ok(f(value.as_hash()?)),
+        None => Ok(missing),
+    }
+}
+
+// HSET key field value [field value ...]
+// Replies with how many of the fields are new
+pub fn hset(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    if args.len().is_multiple_of(2) {
+        return Ok(wrong_arity("hset"));
+    }
+    let key = args[0].pack_bytes()?;
+    let pairs = args[1..]
+        .chunks(2)
+        .map(|pair| {
+            Ok((
+                pair[0].pack_bytes()?.to_vec(),
+                pair[1].pack_bytes()?.to_vec(),
+            ))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let limits = listpack_limits(context);
+
+    let added = modify(&mut context.keyspace.lock(key), key, |hash| {
+        Ok(pairs
+            .into_iter()
+            .map(|(field, value)| hash.insert(field, value, limits))
+            .filter(|added| *added)
+            .count())
+    })?;
+    Ok(RESPMessage::Integer(added as i64))
+}
+
+// HGET key field
+pub fn hget(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, field) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    read(context, key, RESPMessage::Null, |hash| {
+        hash.get(field)
+            .map_or(RESPMessage::Null, |value| bulk(value.clone()))
+    })
+}
+
+// HMGET key field [field ...]
+pub fn hmget(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let fields = args[1..]
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+    let missing = RESPMessage::Array(vec![RESPMessage::Null; fields.len()]);
+    read(context, key, missing, |hash| {
+        RESPMessage::Array(
+            fields
+                .iter()
+                .map(|field| {
+                    hash.get(field)
+                        .map_or(RESPMessage::Null, |value| bulk(value.clone()))
+                })
+                .collect(),
+        )
+    })
+}
+
+// HDEL key field [field ...]
+// Deletes the hash once its last field is removed
+pub fn hdel(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let fields = args[1..]
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+
+    let removed = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let hash = value.as_hash_mut()?;
+            Ok::<_, WrongType>(fields.iter().filter(|field| hash.remove(field)).count())
+        })
+        .transpose()?;
+    Ok(RESPMessage::Integer(removed.unwrap_or(0) as i64))
+}
+
+// HGETALL key
+// A map to RESP3 clients, and fields and values one after the other to RESP2 ones
+pub fn hgetall(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    read(context, key, RESPMessage::Map(vec![]), |hash| {
+        RESPMessage::Map(
+            hash.iter()
+                .map(|(field, value)| (bulk(field.clone()), bulk(value.clone())))
+                .collect(),
+        )
+    })
+}
+
+// HKEYS key
+pub fn hkeys(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    read(context, key, RESPMessage::Array(vec![]), |hash| {
+        RESPMessage::Array(hash.iter().map(|(field, _)| bulk(field.clone())).collect())
+    })
+}
+
+// HVALS key
+pub fn hvals(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    read(context, key, RESPMessage::Array(vec![]), |hash| {
+        RESPMessage::Array(hash.iter().map(|(_, value)| bulk(value.clone())).collect())
+    })
+}
+
+// HLEN key
+pub fn hlen(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    read(context, key, RESPMessage::Integer(0), |hash| {
+        RESPMessage::Integer(hash.len() as i64)
+    })
+}
+
+// HEXISTS key field
+pub fn hexists(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, field) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    read(context, key, RESPMessage::Integer(0), |hash| {
+        RESPMessage::Integer(hash.get(field).is_some() as i64)
+    })
+}
+
+// HINCRBY key field increment
+// A missing field counts as 0
+pub fn hincrby(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, field) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let increment = parse_integer(&args[2])?;
+    let limits = listpack_limits(context);
+
+    let result = modify(&mut context.keyspace.lock(key), key, |hash| {
+        let current = match hash.get(field) {
+            Some(value) => {
+                parse_i64(value).ok_or_else(|| Error::msg("hash value is not an integer"))?
+            }
+            None => 0,
+        };
+        let result = current
+            .checked_add(increment)
+            .ok_or_else(|| Error::msg("increment or decrement would overflow"))?;
+        hash.insert(field.to_vec(), result.to_string().into_bytes(), limits);
+        Ok(result)
+    })?;
+    Ok(RESPMessage::Integer(result))
+}
+
+// HINCRBYFLOAT key field increment
+// A missing field counts as 0
+pub fn hincrbyfloat(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, field) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let increment = parse_float(args[2].pack_bytes()?)
+        .ok_or_else(|| Error::msg("value is not a valid float"))?;
+    let limits = listpack_limits(context);
+
+    let result = modify(&mut context.keyspace.lock(key), key, |hash| {
+        let current = match hash.get(field) {
+            Some(value) => {
+                parse_float(value).ok_or_else(|| Error::msg("hash value is not a float"))?
+            }
+            None => 0.0,
+        };
+        let result = current + increment;
+        if !result.is_finite() {
+            return Err(Error::msg("increment would produce NaN or Infinity"));
+        }
+        let result = format_double(result).into_bytes();
+        hash.insert(field.to_vec(), result.clone(), limits);
+        Ok(result)
+    })?;
+    Ok(bulk(result))
+}
+
+// HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]
+// A listpack is returned whole in one go, with cursor 0, as Redis does. The cursor of a
+// hash table is the slot to carry on from.
+pub fn hscan(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let cursor: usize = args[1]
+        .pack_string()
+        .ok()
+        .and_then(|cursor| cursor.parse().ok())
+        .ok_or_else(|| Error::msg("invalid cursor"))?;
+    let mut pattern = None;
+    let mut count = 10;
+    let mut novalues = false;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match option.pack_string()?.to_ascii_lowercase().as_ref() {
+            "match" => pattern = Some(options.next().ok_or_else(syntax_error)?.pack_bytes()?),
+            "count" => {
+                count = parse_integer(options.next().ok_or_else(syntax_error)?)?;
+                if count < 1 {
+                    return Err(syntax_error());
+                }
+            }
+            "novalues" => novalues = true,
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let mut cache = context.keyspace.lock(key);
+    let (next, pairs) = match cache.get(key) {
+        None => (0, vec![]),
+        Some(value) => match &value.as_hash()?.encoding {
+            Encoding::Listpack(pairs) => (0, pairs.iter().collect()),
+            Encoding::Table(table) => table.scan(cursor, count as usize),
+        },
+    };
+
+    let mut elements = vec![];
+    for (field, value) in pairs {
+        if pattern.is_none_or(|pattern| glob_match(pattern, field, false)) {
+            elements.push(bulk(field.clone()));
+            if !novalues {
+                elements.push(bulk(value.clone()));
+            }
+        }
+    }
+    Ok(RESPMessage::Array(vec![
+        bulk(next.to_string().into_bytes()),
+        RESPMessage::Array(elements),
+    ]))
+}
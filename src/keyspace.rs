@@ -0,0 +1,227 @@
+use crate::{
+    blocking::BlockedClients,
+    cache::{entry_size, Cache},
+    config::Config,
+    value::Value,
+};
+use std::{
+    collections::hash_map::RandomState,
+    hash::BuildHasher,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+// Enough shards that connections seldom wait for each other, even with many cores
+const SHARDS: usize = 16;
+
+// Counters reported by INFO
+#[derive(Clone, Default)]
+pub struct Stats {
+    // Keys removed because they expired, whether found by a lookup or the expiry cycle
+    pub expired_keys: u64,
+    // Running average of how many of the keys sampled by the expiry cycle had expired
+    pub expired_stale_perc: f64,
+    pub expired_time_cap_reached_count: u64,
+    pub expire_cycle_cpu_milliseconds: u64,
+    // Keys removed to make room for new ones
+    pub evicted_keys: u64,
+}
+
+// Every key lives in one of several caches picked by its hash, each behind its own lock,
+// so commands on different keys rarely have to wait for each other.
+// Commands that take several keys lock every shard involved in index order, so two of
+// them can never each hold a shard the other is waiting for.
+pub struct Keyspace {
+    shards: Vec<Mutex<Cache>>,
+    hasher: RandomState,
+    // Shared by the shards, which add and subtract the size of every entry they store
+    used_memory: Arc<AtomicUsize>,
+    maxmemory: AtomicUsize,
+    pub stats: Mutex<Stats>,
+    pub blocked: BlockedClients,
+}
+
+// The shards that hold a set of keys, locked together
+pub struct Locked<'a> {
+    keyspace: &'a Keyspace,
+    guards: Vec<(usize, MutexGuard<'a, Cache>)>,
+}
+
+impl Locked<'_> {
+    pub fn shard(&mut self, key: &[u8]) -> &mut Cache {
+        let index = self.keyspace.shard_index(key);
+        self.guards
+            .iter_mut()
+            .find(|(locked, _)| *locked == index)
+            .map(|(_, guard)| &mut **guard)
+            .expect("the shard of a key that was not locked")
+    }
+
+    // The key's value, as Cache::peek
+    pub fn peek(&self, key: &[u8]) -> Option<&Value> {
+        let index = self.keyspace.shard_index(key);
+        self.guards
+            .iter()
+            .find(|(locked, _)| *locked == index)
+            .map(|(_, guard)| &**guard)
+            .expect("the shard of a key that was not locked")
+            .peek(key)
+    }
+}
+
+impl Keyspace {
+    pub fn new(config: &Config) -> Self {
+        let used_memory = Arc::new(AtomicUsize::new(0));
+        let keyspace = Self {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(Cache::new(Arc::clone(&used_memory))))
+                .collect(),
+            hasher: RandomState::new(),
+            used_memory,
+            maxmemory: AtomicUsize::new(0),
+            stats: Mutex::new(Stats::default()),
+            blocked: BlockedClients::default(),
+        };
+        keyspace.configure(config);
+        keyspace
+    }
+
+    // Picks up the settings that affect the keyspace, at startup and after CONFIG SET.
+    // Lowering maxmemory evicts keys straight away, as far as the policy allows.
+    pub fn configure(&self, config: &Config) {
+        let previous = self.maxmemory.swap(config.maxmemory, Ordering::Relaxed);
+        for shard in &self.shards {
+            shard.lock().unwrap().configure(config);
+        }
+        if previous == 0 || config.maxmemory < previous {
+            self.make_room(0);
+        }
+    }
+
+    fn shard_index(&self, key: &[u8]) -> usize {
+        self.hasher.hash_one(key) as usize % self.shards.len()
+    }
+
+    pub fn shards(&self) -> &[Mutex<Cache>] {
+        &self.shards
+    }
+
+    // Locks the shard that holds key
+    pub fn lock(&self, key: &[u8]) -> MutexGuard<'_, Cache> {
+        self.shards[self.shard_index(key)].lock().unwrap()
+    }
+
+    // Locks every shard that holds one of the keys, in index order
+    pub fn lock_all<'k>(&self, keys: impl IntoIterator<Item = &'k [u8]>) -> Locked<'_> {
+        let mut indices: Vec<usize> = keys.into_iter().map(|key| self.shard_index(key)).collect();
+        indices.sort_unstable();
+        indices.dedup();
+        Locked {
+            keyspace: self,
+            guards: indices
+                .into_iter()
+                .map(|index| (index, self.shards[index].lock().unwrap()))
+                .collect(),
+        }
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    pub fn maxmemory(&self) -> usize {
+        self.maxmemory.load(Ordering::Relaxed)
+    }
+
+    // Evicts keys under the eviction policy until size more bytes fit under maxmemory.
+    // Returns false if the policy ran out of keys it may evict first, in which case whatever
+    // needed the room should be refused.
+    // Like Redis, commands that may use more memory make room for nothing before they run,
+    // so a command that grew a value past maxmemory is made up for by the next one.
+    pub fn make_room(&self, size: usize) -> bool {
+        let maxmemory = self.maxmemory();
+        while maxmemory > 0 && self.used_memory() + size > maxmemory {
+            if !self.evict() {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Makes room for values about to replace whatever their keys hold, so storing them
+    // keeps used memory under maxmemory. Overwriting a key only needs room for what the new
+    // value takes up over the old one.
+    // This has to happen before the keys' shards are locked to store the values, as
+    // evicting locks every shard in turn.
+    pub fn make_room_for<'a>(
+        &self,
+        entries: impl IntoIterator<Item = (&'a [u8], &'a Value)>,
+    ) -> bool {
+        let size = entries
+            .into_iter()
+            .map(|(key, value)| {
+                let stored = self.lock(key).size_of(key).unwrap_or(0);
+                entry_size(key, value).saturating_sub(stored)
+            })
+            .sum();
+        self.make_room(size)
+    }
+
+    // Every shard offers the key it would evict and the one ranked lowest goes, much like
+    // Redis fills its eviction pool from every database. The shards are locked one at a
+    // time, so the key may be gone by the time it is evicted, which only means another
+    // round.
+    fn evict(&self) -> bool {
+        let candidate = self
+            .shards
+            .iter()
+            .enumerate()
+            .filter_map(|(index, shard)| {
+                let (rank, key) = shard.lock().unwrap().eviction_candidate()?;
+                Some((rank, index, key))
+            })
+            .min_by_key(|(rank, _, _)| *rank);
+        let Some((_, index, key)) = candidate else {
+            return false;
+        };
+
+        if self.shards[index].lock().unwrap().delete(&key) {
+            self.stats.lock().unwrap().evicted_keys += 1;
+        }
+        true
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().key_count())
+            .sum()
+    }
+
+    pub fn volatile_count(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().volatile_count())
+            .sum()
+    }
+
+    // The counters with the keys each shard found expired added in
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.lock().unwrap().clone();
+        stats.expired_keys += self
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().expired_keys)
+            .sum::<u64>();
+        stats
+    }
+
+    pub fn get_key(&self) -> Vec<(Vec<u8>, Value, Option<u128>)> {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.lock().unwrap().get_key())
+            .collect()
+    }
+}
@@ -0,0 +1,534 @@
+use crate::{
+    blocking::Blocked,
+    cache::Cache,
+    command::{parse_integer, syntax_error, wrong_arity, Context},
+    keyspace::{Keyspace, Locked},
+    resp::RESPMessage,
+    value::{Value, WrongType},
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::{vec_deque, VecDeque},
+    mem::size_of,
+    ops::Range,
+    time::Duration,
+};
+
+// Which end of a list to push to or pop from
+#[derive(Clone, Copy)]
+enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    fn parse(arg: &RESPMessage) -> Result<Self> {
+        match arg.pack_bytes()?.to_ascii_lowercase().as_slice() {
+            b"left" => Ok(Self::Left),
+            b"right" => Ok(Self::Right),
+            _ => Err(syntax_error()),
+        }
+    }
+}
+
+// The elements of a list, along with how many bytes they take up. The count is kept up to
+// date as elements come and go, so measuring the list never has to walk it.
+#[derive(Debug, Clone, Default)]
+pub struct List {
+    elements: VecDeque<Vec<u8>>,
+    size: usize,
+}
+
+// An element takes up its bytes and a pointer and length
+fn element_size(element: &[u8]) -> usize {
+    element.len() + size_of::<Vec<u8>>()
+}
+
+impl List {
+    pub fn len(&self) -> usize {
+        self.elements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn iter(&self) -> vec_deque::Iter<'_, Vec<u8>> {
+        self.elements.iter()
+    }
+
+    fn push(&mut self, element: Vec<u8>, end: End) {
+        self.size += element_size(&element);
+        match end {
+            End::Left => self.elements.push_front(element),
+            End::Right => self.elements.push_back(element),
+        }
+    }
+
+    fn pop(&mut self, end: End) -> Option<Vec<u8>> {
+        let element = match end {
+            End::Left => self.elements.pop_front(),
+            End::Right => self.elements.pop_back(),
+        }?;
+        self.size -= element_size(&element);
+        Some(element)
+    }
+
+    fn insert(&mut self, index: usize, element: Vec<u8>) {
+        self.size += element_size(&element);
+        self.elements.insert(index, element);
+    }
+
+    // Replaces the element at index, which has to be in the list
+    fn set(&mut self, index: usize, element: Vec<u8>) {
+        self.size += element_size(&element);
+        self.size -= element_size(&self.elements[index]);
+        self.elements[index] = element;
+    }
+
+    fn retain(&mut self, mut f: impl FnMut(&Vec<u8>) -> bool) {
+        let mut removed = 0;
+        self.elements.retain(|element| {
+            let keep = f(element);
+            if !keep {
+                removed += element_size(element);
+            }
+            keep
+        });
+        self.size -= removed;
+    }
+
+    fn reverse(&mut self) {
+        self.elements.make_contiguous().reverse();
+    }
+
+    // Removes every element outside of the range, which has to be within the list
+    fn keep(&mut self, range: Range<usize>) {
+        for element in self.elements.drain(range.end..) {
+            self.size -= element_size(&element);
+        }
+        for element in self.elements.drain(..range.start) {
+            self.size -= element_size(&element);
+        }
+    }
+}
+
+impl From<Vec<Vec<u8>>> for List {
+    fn from(elements: Vec<Vec<u8>>) -> Self {
+        let size = elements.iter().map(|element| element_size(element)).sum();
+        Self {
+            elements: elements.into(),
+            size,
+        }
+    }
+}
+
+fn bulk(element: Vec<u8>) -> RESPMessage {
+    RESPMessage::BulkString(element.into())
+}
+
+// Pushes the elements one at a time, creating the list if the key does not exist.
+// Returns the length of the list.
+fn push(cache: &mut Cache, key: &[u8], elements: Vec<Vec<u8>>, end: End) -> Result<usize> {
+    if !cache.contains(key) {
+        cache.set_expiring_at(key.to_vec(), Value::List(List::default()), None);
+    }
+    let len = cache
+        .modify(key, |value| {
+            let list = value.as_list_mut()?;
+            for element in elements {
+                list.push(element, end);
+            }
+            Ok::<_, WrongType>(list.len())
+        })
+        .expect("the list exists")?;
+    Ok(len)
+}
+
+// The indices from start to stop, both included, either of which counts back from the end
+// of the list when negative. Empty if they don't overlap the list.
+pub fn range(start: i64, stop: i64, len: usize) -> Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (start + len).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        stop + len
+    } else {
+        stop.min(len - 1)
+    };
+    if start > stop || start >= len {
+        0..0
+    } else {
+        start as usize..stop as usize + 1
+    }
+}
+
+// Where index, which counts back from the end of the list when negative, falls in it
+fn index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+// LPUSH key element [element ...]
+pub fn lpush(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    push_command(context, args, End::Left)
+}
+
+// RPUSH key element [element ...]
+pub fn rpush(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    push_command(context, args, End::Right)
+}
+
+fn push_command(context: &mut Context, args: &[RESPMessage], end: End) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let elements = args[1..]
+        .iter()
+        .map(|arg| Ok(arg.pack_bytes()?.to_vec()))
+        .collect::<Result<Vec<_>>>()?;
+    let len = push(&mut context.keyspace.lock(key), key, elements, end)?;
+    context.keyspace.blocked.signal(key);
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// LPOP key [count]
+pub fn lpop(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    pop_command(context, args, "lpop", End::Left)
+}
+
+// RPOP key [count]
+pub fn rpop(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    pop_command(context, args, "rpop", End::Right)
+}
+
+// Without a count a single element is replied with, and with one an array of them
+fn pop_command(
+    context: &mut Context,
+    args: &[RESPMessage],
+    name: &str,
+    end: End,
+) -> Result<RESPMessage> {
+    if args.len() > 2 {
+        return Ok(wrong_arity(name));
+    }
+    let key = args[0].pack_bytes()?;
+    let count = match args.get(1) {
+        Some(count) => {
+            let count = parse_integer(count)?;
+            if count < 0 {
+                return Err(Error::msg("value is out of range, must be positive"));
+            }
+            Some(count as usize)
+        }
+        None => None,
+    };
+
+    let popped = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let list = value.as_list_mut()?;
+            let count = count.unwrap_or(1).min(list.len());
+            Ok::<_, WrongType>((0..count).filter_map(|_| list.pop(end)).collect::<Vec<_>>())
+        })
+        .transpose()?;
+
+    Ok(match (popped, count) {
+        (None, None) => RESPMessage::Null,
+        (None, Some(_)) => RESPMessage::NullArray,
+        (Some(popped), None) => popped.into_iter().next().map_or(RESPMessage::Null, bulk),
+        (Some(popped), Some(_)) => RESPMessage::Array(popped.into_iter().map(bulk).collect()),
+    })
+}
+
+// LRANGE key start stop
+pub fn lrange(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (start, stop) = (parse_integer(&args[1])?, parse_integer(&args[2])?);
+
+    let mut cache = context.keyspace.lock(key);
+    let Some(value) = cache.get(key) else {
+        return Ok(RESPMessage::Array(vec![]));
+    };
+    let list = value.as_list()?;
+    Ok(RESPMessage::Array(
+        list.elements
+            .range(range(start, stop, list.len()))
+            .map(|element| bulk(element.clone()))
+            .collect(),
+    ))
+}
+
+// LLEN key
+pub fn llen(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let len = match context.keyspace.lock(key).get(key) {
+        Some(value) => value.as_list()?.len(),
+        None => 0,
+    };
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// LINDEX key index
+pub fn lindex(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let position = parse_integer(&args[1])?;
+
+    let mut cache = context.keyspace.lock(key);
+    let Some(value) = cache.get(key) else {
+        return Ok(RESPMessage::Null);
+    };
+    let list = value.as_list()?;
+    Ok(match index(position, list.len()) {
+        Some(index) => bulk(list.elements[index].clone()),
+        None => RESPMessage::Null,
+    })
+}
+
+// LSET key index element
+pub fn lset(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let position = parse_integer(&args[1])?;
+    let element = args[2].pack_bytes()?.to_vec();
+
+    context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let list = value.as_list_mut()?;
+            let index =
+                index(position, list.len()).ok_or_else(|| Error::msg("index out of range"))?;
+            list.set(index, element);
+            Ok(())
+        })
+        .unwrap_or_else(|| Err(Error::msg("no such key")))?;
+    Ok(RESPMessage::SimpleString("OK".to_string()))
+}
+
+// LREM key count element
+// Removes the first count occurrences of the element, the last -count when count is
+// negative, or all of them when it is 0
+pub fn lrem(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let count = parse_integer(&args[1])?;
+    let element = args[2].pack_bytes()?;
+
+    let removed = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let list = value.as_list_mut()?;
+            let limit = if count == 0 {
+                usize::MAX
+            } else {
+                count.unsigned_abs() as usize
+            };
+            let mut removed = 0;
+            // Searching from the tail is searching the reversed list from the head
+            if count < 0 {
+                list.reverse();
+            }
+            list.retain(|item| {
+                let remove = removed < limit && item == element;
+                removed += remove as usize;
+                !remove
+            });
+            if count < 0 {
+                list.reverse();
+            }
+            Ok::<_, WrongType>(removed)
+        })
+        .transpose()?;
+    Ok(RESPMessage::Integer(removed.unwrap_or(0) as i64))
+}
+
+// LTRIM key start stop
+pub fn ltrim(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (start, stop) = (parse_integer(&args[1])?, parse_integer(&args[2])?);
+
+    context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let list = value.as_list_mut()?;
+            let keep = range(start, stop, list.len());
+            list.keep(keep);
+            Ok::<_, WrongType>(())
+        })
+        .transpose()?;
+    Ok(RESPMessage::SimpleString("OK".to_string()))
+}
+
+// LINSERT key BEFORE | AFTER pivot element
+// Replies with the new length, -1 if there is no pivot or 0 if there is no list
+pub fn linsert(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let before = match args[1].pack_bytes()?.to_ascii_lowercase().as_slice() {
+        b"before" => true,
+        b"after" => false,
+        _ => return Err(syntax_error()),
+    };
+    let pivot = args[2].pack_bytes()?;
+    let element = args[3].pack_bytes()?.to_vec();
+
+    let len = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let list = value.as_list_mut()?;
+            let Some(position) = list.iter().position(|item| item == pivot) else {
+                return Ok(-1);
+            };
+            list.insert(if before { position } else { position + 1 }, element);
+            Ok::<_, WrongType>(list.len() as i64)
+        })
+        .transpose()?;
+    Ok(RESPMessage::Integer(len.unwrap_or(0)))
+}
+
+// LMOVE source destination LEFT | RIGHT LEFT | RIGHT
+// Both keys may be the same list, which rotates it
+pub fn lmove(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (source, destination) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let (from, to) = (End::parse(&args[2])?, End::parse(&args[3])?);
+
+    let keyspace = context.keyspace;
+    let mut shards = keyspace.lock_all([source, destination]);
+    let moved = move_element(keyspace, &mut shards, source, destination, from, to)?;
+    Ok(moved.map_or(RESPMessage::Null, bulk))
+}
+
+// Pops an element from the source list and pushes it to the destination, with the shards
+// of both keys locked. None if there is no source list.
+fn move_element(
+    keyspace: &Keyspace,
+    shards: &mut Locked,
+    source: &[u8],
+    destination: &[u8],
+    from: End,
+    to: End,
+) -> Result<Option<Vec<u8>>> {
+    // Both keys are checked before either list changes
+    match shards.shard(source).get(source) {
+        Some(value) => value.as_list()?,
+        None => return Ok(None),
+    };
+    if let Some(value) = shards.shard(destination).get(destination) {
+        value.as_list()?;
+    }
+
+    let element = shards
+        .shard(source)
+        .modify(source, |value| {
+            value.as_list_mut().ok().and_then(|list| list.pop(from))
+        })
+        .flatten()
+        .expect("the source is a list, which is never empty");
+    push(
+        shards.shard(destination),
+        destination,
+        vec![element.clone()],
+        to,
+    )?;
+    keyspace.blocked.signal(destination);
+    Ok(Some(element))
+}
+
+// A timeout in seconds, which may have a fraction, or None for 0, which waits forever
+fn parse_timeout(arg: &RESPMessage) -> Result<Option<Duration>> {
+    let seconds: f64 = arg
+        .pack_string()
+        .ok()
+        .and_then(|seconds| seconds.parse().ok())
+        .filter(|seconds: &f64| seconds.is_finite())
+        .ok_or_else(|| Error::msg("timeout is not a float or out of range"))?;
+    if seconds < 0.0 {
+        return Err(Error::msg("timeout is negative"));
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    let timeout =
+        Duration::try_from_secs_f64(seconds).map_err(|_| Error::msg("timeout is out of range"))?;
+    Ok(Some(timeout))
+}
+
+// Leaves the client blocked on the keys, to be replied to with reply if nothing arrives
+// before the timeout. The shards of the keys must still be locked, so nothing can be
+// pushed to them before the client is in line.
+fn block(
+    context: &mut Context,
+    keys: Vec<Vec<u8>>,
+    timeout: Option<Duration>,
+    reply: RESPMessage,
+) -> Result<RESPMessage> {
+    context
+        .keyspace
+        .blocked
+        .block(&keys, &context.client.waiter);
+    context.client.blocked = Some(Blocked {
+        keys,
+        timeout,
+        reply,
+        command: None,
+    });
+    // Never sent, as the command runs again once the client is woken
+    Ok(RESPMessage::Null)
+}
+
+// BLPOP key [key ...] timeout
+pub fn blpop(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    blocking_pop(context, args, End::Left)
+}
+
+// BRPOP key [key ...] timeout
+pub fn brpop(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    blocking_pop(context, args, End::Right)
+}
+
+// Pops from the first of the keys that holds a list, replying with that key and the element
+fn blocking_pop(context: &mut Context, args: &[RESPMessage], end: End) -> Result<RESPMessage> {
+    let (keys, timeout) = args.split_at(args.len() - 1);
+    let timeout = parse_timeout(&timeout[0])?;
+    let keys = keys
+        .iter()
+        .map(|key| Ok(key.pack_bytes()?.to_vec()))
+        .collect::<Result<Vec<_>>>()?;
+
+    let keyspace = context.keyspace;
+    let mut shards = keyspace.lock_all(keys.iter().map(Vec::as_slice));
+    for key in &keys {
+        let popped = shards
+            .shard(key)
+            .modify(key, |value| {
+                Ok::<_, WrongType>(value.as_list_mut()?.pop(end))
+            })
+            .transpose()?
+            .flatten();
+        if let Some(element) = popped {
+            return Ok(RESPMessage::Array(vec![bulk(key.clone()), bulk(element)]));
+        }
+    }
+    block(context, keys, timeout, RESPMessage::NullArray)
+}
+
+// BLMOVE source destination LEFT | RIGHT LEFT | RIGHT timeout
+pub fn blmove(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (source, destination) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let (from, to) = (End::parse(&args[2])?, End::parse(&args[3])?);
+    let timeout = parse_timeout(&args[4])?;
+
+    let keyspace = context.keyspace;
+    let mut shards = keyspace.lock_all([source, destination]);
+    match move_element(keyspace, &mut shards, source, destination, from, to)? {
+        Some(element) => Ok(bulk(element)),
+        None => block(context, vec![source.to_vec()], timeout, RESPMessage::Null),
+    }
+}
@@ -1,6 +1,20 @@
 mod server;
 mod resp;
+mod codec;
+mod command;
+mod config;
+mod expire;
 mod cache;
+mod keyspace;
+mod value;
+mod list;
+mod hash;
+mod set;
+mod zset;
+mod stream;
+mod string;
+mod blocking;
+#[allow(non_snake_case)]
 mod simpleElection;
 
 use anyhow::{Result};
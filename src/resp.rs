@@ -1,168 +1,651 @@
-use std::str;
+use std::{fmt, ops::Range, str};
 use anyhow::{Result, Error};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 const CRLF: &[u8] = b"\r\n";
 
-#[derive(Eq, PartialEq, Clone, Debug)]
+// The protocol version a connection speaks, switched with the HELLO command
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default)]
+pub enum RESPVersion {
+    #[default]
+    RESP2,
+    RESP3,
+}
+
+#[derive(PartialEq, Clone, Debug)]
 pub enum RESPMessage {
     SimpleString(String),
     Error(String),
-    Integer(u64),
-    BulkString(String),
+    Integer(i64),
+    BulkString(Bytes),
     Array(Vec<RESPMessage>),
-    Null
+    // A missing value: the null bulk string ($-1) and null array (*-1) of RESP2,
+    // both of which are sent as the single Null type (_) to RESP3 connections
+    Null,
+    NullArray,
+    // RESP3 types, sent as their closest RESP2 equivalent to RESP2 connections
+    Map(Vec<(RESPMessage, RESPMessage)>),
+    Set(Vec<RESPMessage>),
+    Double(f64),
+    Boolean(bool),
+    BigNumber(String),
+    // Format (such as "txt" or "mkd") and content
+    Verbatim(String, Bytes),
+    Push(Vec<RESPMessage>),
+    // Attributes and the reply they describe
+    Attribute(Vec<(RESPMessage, RESPMessage)>, Box<RESPMessage>),
+}
+
+// Why a client's input could not be decoded. The connection cannot be trusted to
+// be in sync after any of these, so the server reports it and hangs up.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum ProtocolError {
+    UnknownType(u8),
+    InvalidInteger,
+    InvalidDouble,
+    InvalidBoolean,
+    InvalidBulkLength,
+    InvalidMultibulkLength,
+    InvalidVerbatim,
+    InvalidUtf8,
+    MissingCRLF,
+    UnbalancedQuotes,
+    BulkTooLong,
+    TooManyElements,
+    TooDeep,
+    LineTooLong,
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownType(byte) => {
+                write!(f, "unknown message type '{}'", (*byte as char).escape_default())
+            }
+            Self::InvalidInteger => write!(f, "invalid integer"),
+            Self::InvalidDouble => write!(f, "invalid double"),
+            Self::InvalidBoolean => write!(f, "invalid boolean"),
+            Self::InvalidBulkLength => write!(f, "invalid bulk length"),
+            Self::InvalidMultibulkLength => write!(f, "invalid multibulk length"),
+            Self::InvalidVerbatim => write!(f, "invalid verbatim string"),
+            Self::InvalidUtf8 => write!(f, "invalid UTF-8 in simple string"),
+            Self::MissingCRLF => write!(f, "expected CRLF after bulk string"),
+            Self::UnbalancedQuotes => write!(f, "unbalanced quotes in request"),
+            Self::BulkTooLong => write!(f, "bulk length exceeds proto-max-bulk-len"),
+            Self::TooManyElements => {
+                write!(f, "multibulk length exceeds proto-max-multibulk-len")
+            }
+            Self::TooDeep => write!(f, "nesting exceeds proto-max-nesting-depth"),
+            Self::LineTooLong => write!(f, "line exceeds proto-max-inline-len"),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+// Upper bounds on what a peer may send. Lengths are checked as soon as their
+// header has been read, so an oversized message is rejected before anything is
+// allocated for it or its payload is waited for.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct ProtocolLimits {
+    // Longest bulk string, in bytes
+    pub max_bulk_len: usize,
+    // Most elements in a single array, set, push or map (counting keys and values)
+    pub max_multibulk_len: usize,
+    // Deepest nesting of arrays inside arrays
+    pub max_nesting_depth: usize,
+    // Longest inline command or header line, in bytes
+    pub max_inline_len: usize,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+            max_nesting_depth: 32,
+            max_inline_len: 64 * 1024,
+        }
+    }
+}
+
+// Returns the position of the first CRLF in bytes, or None if the line has not fully arrived yet
+fn find_crlf(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(CRLF.len()).position(|window| window == CRLF)
+}
+
+// Formats a double the way Redis does, e.g. 1.5, 3, 1e+21, 1e-07, inf or -inf: laid out
+// as %.17g would, with the fewest digits that still read back as the same double
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        return "nan".to_string();
+    } else if d.is_infinite() {
+        return if d > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    let scientific = format!("{:e}", d);
+    let (mantissa, exponent) = scientific.split_once('e').expect("{:e} has an exponent");
+    let exponent: i32 = exponent.parse().expect("the exponent is an integer");
+    if (-4..17).contains(&exponent) {
+        d.to_string()
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+    }
+}
+
+fn parse_double(line: &[u8]) -> Result<f64, ProtocolError> {
+    match line {
+        b"inf" | b"+inf" => Ok(f64::INFINITY),
+        b"-inf" => Ok(f64::NEG_INFINITY),
+        _ => parse_line(line, ProtocolError::InvalidDouble),
+    }
+}
+
+fn parse_line<T: str::FromStr>(line: &[u8], error: ProtocolError) -> Result<T, ProtocolError> {
+    str::from_utf8(line)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(error)
+}
+
+fn hex_digit(byte: Option<&u8>) -> Option<u8> {
+    (*byte? as char).to_digit(16).map(|digit| digit as u8)
 }
 
-fn start_to_crlf(bytes: &[u8]) -> usize {
-    let mut index = 0;
-    for byte in bytes {
-        if *byte == b'\r' {
-            break;
+// Splits an inline command into its arguments the same way redis-cli does:
+// arguments are separated by whitespace, "double quotes" allow escapes such as
+// \n or \x41 and 'single quotes' take everything literally except \'
+fn split_inline_args(line: &[u8]) -> Result<Vec<Vec<u8>>, ProtocolError> {
+    let mut args = vec![];
+    let mut bytes = line.iter().peekable();
+
+    loop {
+        while bytes.next_if(|byte| byte.is_ascii_whitespace()).is_some() {}
+        if bytes.peek().is_none() {
+            return Ok(args);
+        }
+
+        let mut arg = vec![];
+        let mut in_double_quotes = false;
+        let mut in_single_quotes = false;
+        loop {
+            if in_double_quotes {
+                match bytes.next() {
+                    None => return Err(ProtocolError::UnbalancedQuotes),
+                    Some(b'\\') => {
+                        let mut lookahead = bytes.clone();
+                        if lookahead.next() == Some(&b'x') {
+                            if let (Some(high), Some(low)) =
+                                (hex_digit(lookahead.next()), hex_digit(lookahead.next()))
+                            {
+                                arg.push(high * 16 + low);
+                                bytes = lookahead;
+                                continue;
+                            }
+                        }
+                        match bytes.next() {
+                            Some(b'n') => arg.push(b'\n'),
+                            Some(b'r') => arg.push(b'\r'),
+                            Some(b't') => arg.push(b'\t'),
+                            Some(b'b') => arg.push(0x08),
+                            Some(b'a') => arg.push(0x07),
+                            Some(&other) => arg.push(other),
+                            None => return Err(ProtocolError::UnbalancedQuotes),
+                        }
+                    }
+                    Some(b'"') => {
+                        // The closing quote must end the argument
+                        if bytes.peek().is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                            return Err(ProtocolError::UnbalancedQuotes);
+                        }
+                        break;
+                    }
+                    Some(&byte) => arg.push(byte),
+                }
+            } else if in_single_quotes {
+                match bytes.next() {
+                    None => return Err(ProtocolError::UnbalancedQuotes),
+                    Some(b'\\') if bytes.peek() == Some(&&b'\'') => {
+                        bytes.next();
+                        arg.push(b'\'');
+                    }
+                    Some(b'\'') => {
+                        if bytes.peek().is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                            return Err(ProtocolError::UnbalancedQuotes);
+                        }
+                        break;
+                    }
+                    Some(&byte) => arg.push(byte),
+                }
+            } else {
+                match bytes.next() {
+                    None => break,
+                    Some(byte) if byte.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double_quotes = true,
+                    Some(b'\'') => in_single_quotes = true,
+                    Some(&byte) => arg.push(byte),
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+fn line_string(line: &[u8]) -> Result<String, ProtocolError> {
+    str::from_utf8(line)
+        .map(str::to_string)
+        .map_err(|_| ProtocolError::InvalidUtf8)
+}
+
+// What parse_value found: a complete message, or the type and length of an aggregate
+enum Parsed {
+    Message(RESPMessage),
+    Aggregate(u8, usize),
+}
+
+// An aggregate that parse has read the header of but not all of the elements yet
+struct Pending {
+    message_type: u8,
+    len: usize,
+    elements: Vec<RESPMessage>,
+}
+
+impl Pending {
+    fn new(message_type: u8, len: usize) -> Self {
+        Self {
+            message_type,
+            // Attributes are followed by the reply they describe
+            len: if message_type == b'|' { len + 1 } else { len },
+            // The length is only a claim until the elements arrive, so don't trust it
+            // with a large allocation
+            elements: Vec::with_capacity(len.min(1024)),
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.elements.len() == self.len
+    }
+
+    fn finish(mut self) -> RESPMessage {
+        match self.message_type {
+            b'*' => RESPMessage::Array(self.elements),
+            b'~' => RESPMessage::Set(self.elements),
+            b'>' => RESPMessage::Push(self.elements),
+            b'%' => RESPMessage::Map(RESPMessage::into_pairs(self.elements)),
+            _ => {
+                let reply = self.elements.pop().unwrap();
+                RESPMessage::Attribute(RESPMessage::into_pairs(self.elements), Box::new(reply))
+            }
         }
-        index += 1;
     }
-    index
 }
 
 impl RESPMessage {
-    // Function that encodes data into the respective RESP format
+    // Function that encodes data into the respective RESP format.
+    // Uses RESP2, which is what every connection speaks until it sends HELLO 3.
     pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_for(RESPVersion::RESP2)
+    }
+
+    // Encodes data for a connection speaking the given protocol version
+    pub fn serialize_for(&self, version: RESPVersion) -> Vec<u8> {
         let mut result: Vec<u8> = vec![];
+        self.write_to(version, &mut result);
+        result
+    }
+
+    fn write_line<B: BufMut>(result: &mut B, prefix: u8, line: &[u8]) {
+        result.put_u8(prefix);
+        result.put_slice(line);
+        result.put_slice(CRLF);
+    }
+
+    // Writes a prefixed integer line such as ":42" or "$5" without going through a String
+    fn write_number<B: BufMut>(result: &mut B, prefix: u8, number: i64) {
+        let mut digits = [0u8; 20];
+        let mut start = digits.len();
+        let mut remaining = number.unsigned_abs();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (remaining % 10) as u8;
+            remaining /= 10;
+            if remaining == 0 {
+                break;
+            }
+        }
+
+        result.put_u8(prefix);
+        if number < 0 {
+            result.put_u8(b'-');
+        }
+        result.put_slice(&digits[start..]);
+        result.put_slice(CRLF);
+    }
+
+    fn write_bulk<B: BufMut>(result: &mut B, s: &[u8]) {
+        Self::write_number(result, b'$', s.len() as i64);
+        result.put_slice(s);
+        result.put_slice(CRLF);
+    }
+
+    fn write_aggregate<'a, B: BufMut>(
+        result: &mut B,
+        version: RESPVersion,
+        prefix: u8,
+        len: usize,
+        elements: impl Iterator<Item = &'a RESPMessage>,
+    ) {
+        Self::write_number(result, prefix, len as i64);
+        for element in elements {
+            element.write_to(version, result);
+        }
+    }
+
+    // Appends the encoded message to result, which is either a plain Vec<u8> or
+    // the write buffer of a RESPCodec
+    pub fn write_to<B: BufMut>(&self, version: RESPVersion, result: &mut B) {
+        let resp3 = version == RESPVersion::RESP3;
         match self {
-            Self::SimpleString(s) => {
-                result.push(b'+');
-                let mut string_bytes: Vec<u8> = s.as_bytes().to_owned();
-                result.append(&mut string_bytes);
-                result.append(&mut CRLF.to_owned());
-            },
-            Self::Error(s) => {
-                result.push(b'-');
-                let mut string_bytes: Vec<u8> = s.as_bytes().to_owned();
-                result.append(&mut string_bytes);
-                result.append(&mut CRLF.to_owned());
-            },
-            Self::Integer(i) => {
-                result.push(b':');
-                let mut integer_bytes: Vec<u8> = i.to_string().as_bytes().to_owned();
-                result.append(&mut integer_bytes);
-                result.append(&mut CRLF.to_owned());
-            }
-            Self::BulkString(s) => {
-                result.push(b'$');
-
-                let mut string_bytes: Vec<u8> = s.as_bytes().to_owned();
-                let mut length_bytes: Vec<u8> = s.len().to_string().as_bytes().to_owned();
-
-                result.append(&mut length_bytes);
-                result.append(&mut CRLF.to_owned());
-                result.append(&mut string_bytes);
-                result.append(&mut CRLF.to_owned());
-            },
-            Self::Array(a) => {
-                result.push(b'*');
-
-                // Get the length of the array
-                let mut length_bytes = a.len().to_string().as_bytes().to_owned();
-                result.append(&mut length_bytes);
-                result.append(&mut CRLF.to_owned());
-
-                // Handle each element of the array
-                for element in a {
-                    let mut element_bytes = element.serialize();
-                    result.append(&mut element_bytes);
+            Self::SimpleString(s) => Self::write_line(result, b'+', s.as_bytes()),
+            Self::Error(s) => Self::write_line(result, b'-', s.as_bytes()),
+            Self::Integer(i) => Self::write_number(result, b':', *i),
+            Self::BulkString(s) => Self::write_bulk(result, s),
+            Self::Array(a) => Self::write_aggregate(result, version, b'*', a.len(), a.iter()),
+            Self::Null | Self::NullArray if resp3 => Self::write_line(result, b'_', b""),
+            Self::Null => Self::write_line(result, b'$', b"-1"),
+            Self::NullArray => Self::write_line(result, b'*', b"-1"),
+            Self::Map(pairs) => {
+                // RESP2 has no maps, so they are flattened into key, value, key, value...
+                let (prefix, len) = if resp3 { (b'%', pairs.len()) } else { (b'*', pairs.len() * 2) };
+                let elements = pairs.iter().flat_map(|(key, value)| [key, value]);
+                Self::write_aggregate(result, version, prefix, len, elements);
+            }
+            Self::Set(a) => {
+                let prefix = if resp3 { b'~' } else { b'*' };
+                Self::write_aggregate(result, version, prefix, a.len(), a.iter());
+            }
+            Self::Push(a) => {
+                let prefix = if resp3 { b'>' } else { b'*' };
+                Self::write_aggregate(result, version, prefix, a.len(), a.iter());
+            }
+            Self::Double(d) if resp3 => Self::write_line(result, b',', format_double(*d).as_bytes()),
+            Self::Double(d) => Self::write_bulk(result, format_double(*d).as_bytes()),
+            Self::Boolean(b) if resp3 => Self::write_line(result, b'#', if *b { b"t" } else { b"f" }),
+            Self::Boolean(b) => Self::write_number(result, b':', *b as i64),
+            Self::BigNumber(n) if resp3 => Self::write_line(result, b'(', n.as_bytes()),
+            Self::BigNumber(n) => Self::write_bulk(result, n.as_bytes()),
+            Self::Verbatim(format, s) if resp3 => {
+                Self::write_number(result, b'=', (format.len() + 1 + s.len()) as i64);
+                result.put_slice(format.as_bytes());
+                result.put_u8(b':');
+                result.put_slice(s);
+                result.put_slice(CRLF);
+            }
+            Self::Verbatim(_, s) => Self::write_bulk(result, s),
+            Self::Attribute(pairs, reply) => {
+                // RESP2 clients have no way to receive attributes, they only get the reply
+                if resp3 {
+                    let elements = pairs.iter().flat_map(|(key, value)| [key, value]);
+                    Self::write_aggregate(result, version, b'|', pairs.len(), elements);
                 }
-            },
-            Self::Null => {
-                result.push(b'$');
-                // add b'-1' to the result
-                let mut null_bytes = b"-1".to_vec();
-                result.append(&mut null_bytes);
-                result.append(&mut CRLF.to_owned());
+                reply.write_to(version, result);
             }
         }
-        result
     }
 
-    // Function that decodes data from the respective RESP format
-    pub fn deserialize(bytes: &[u8]) -> (Self, usize) {
-        match bytes[0] {
-            b'+' => {
-                let len: usize = start_to_crlf(&bytes[1..]);
-                return (
-                    Self::SimpleString(str::from_utf8(&bytes[1..len]).unwrap().to_string()),
-                    len + 3, // +3 for the CRLF and the + 
-                );
-            },
-            b'-' => {
-                let len: usize = start_to_crlf(&bytes[1..]);
-                return (
-                    Self::Error(str::from_utf8(&bytes[1..len]).unwrap().to_string()),
-                    len + 3, // +3 for the CRLF and the - 
-                );
-            },
-            b':' => {
-                let len: usize = start_to_crlf(&bytes[1..]);
-                return (
-                    Self::Integer(str::from_utf8(&bytes[1..len]).unwrap().parse().unwrap()),
-                    len + 3, // +3 for the CRLF and the : 
-                );
-            },
-            b'$' => {
-                let len_len = start_to_crlf(&bytes[1..]);
-                let len: usize = str::from_utf8(&bytes[1..=len_len])
-                    .unwrap()
-                    .parse()
-                    .unwrap();
-
-                return (
-                    Self::BulkString(
-                        str::from_utf8(&bytes[len_len + 3..len_len + 3 + len])
-                            .unwrap()
-                            .to_string(),
-                    ),
-                    len_len + 3 + len + 2,
-                );
-            },
-            // TODO: potentially buggy
-            b'*' => {
-                let len_len = start_to_crlf(&bytes[1..]);
-                let num_elements: usize = str::from_utf8(&bytes[1..=len_len])
-                    .unwrap()
-                    .parse()
-                    .unwrap();
-
-                let mut result: Vec<Self> = vec![];
-                let mut used_length_in_elements = 0;
-                let header_size = 1 + len_len + 2;
-
-                for _ in 0..num_elements {
-                    let (element, used_size) =
-                        Self::deserialize(&bytes[header_size + used_length_in_elements..]);
-                    result.push(element);
-                    used_length_in_elements += used_size;
+    // Parses a single value starting at bytes[start..]: either a complete scalar, or just
+    // the header of an aggregate whose elements follow it. Returns the index right after
+    // what was parsed, None if it has not fully arrived yet, or an error if the bytes can
+    // never become a valid message.
+    // Bulk string and verbatim payloads are left empty: their positions are pushed to
+    // payloads instead, so they can be sliced out of the frame once it is complete.
+    fn parse_value(
+        bytes: &[u8],
+        start: usize,
+        limits: &ProtocolLimits,
+        payloads: &mut Vec<Range<usize>>,
+    ) -> Result<Option<(Parsed, usize)>, ProtocolError> {
+        let Some(&message_type) = bytes.get(start) else {
+            return Ok(None);
+        };
+        if !b"+-:$*_,#(=%~>|".contains(&message_type) {
+            return Err(ProtocolError::UnknownType(message_type));
+        }
+        let Some(line_len) = find_crlf(&bytes[start + 1..]) else {
+            if bytes.len() - start > limits.max_inline_len {
+                return Err(ProtocolError::LineTooLong);
+            }
+            return Ok(None);
+        };
+        if line_len > limits.max_inline_len {
+            return Err(ProtocolError::LineTooLong);
+        }
+        let line = &bytes[start + 1..start + 1 + line_len];
+        let header_end = start + 1 + line_len + CRLF.len();
+
+        let message = match message_type {
+            b'+' => Self::SimpleString(line_string(line)?),
+            b'-' => Self::Error(line_string(line)?),
+            b':' => Self::Integer(parse_line(line, ProtocolError::InvalidInteger)?),
+            b'$' if line == b"-1" => Self::Null,
+            b'$' | b'=' => {
+                let len: usize = parse_line(line, ProtocolError::InvalidBulkLength)?;
+                if len > limits.max_bulk_len {
+                    return Err(ProtocolError::BulkTooLong);
+                }
+                let content_end = header_end
+                    .checked_add(len)
+                    .ok_or(ProtocolError::InvalidBulkLength)?;
+                let end = content_end + CRLF.len();
+                if bytes.len() < end {
+                    return Ok(None);
+                }
+                if &bytes[content_end..end] != CRLF {
+                    return Err(ProtocolError::MissingCRLF);
                 }
 
-                (Self::Array(result), header_size + used_length_in_elements)
+                let message = if message_type == b'$' {
+                    payloads.push(header_end..content_end);
+                    Self::BulkString(Bytes::new())
+                } else {
+                    // Verbatim strings start with a three letter format and a colon
+                    let content = &bytes[header_end..content_end];
+                    if content.len() < 4 || content[3] != b':' {
+                        return Err(ProtocolError::InvalidVerbatim);
+                    }
+                    let format =
+                        str::from_utf8(&content[..3]).map_err(|_| ProtocolError::InvalidVerbatim)?;
+                    payloads.push(header_end + 4..content_end);
+                    Self::Verbatim(format.to_string(), Bytes::new())
+                };
+                return Ok(Some((Parsed::Message(message), end)));
+            }
+            b'_' => Self::Null,
+            b',' => Self::Double(parse_double(line)?),
+            b'#' => match line {
+                b"t" => Self::Boolean(true),
+                b"f" => Self::Boolean(false),
+                _ => return Err(ProtocolError::InvalidBoolean),
             },
-            _ => (Self::Error("Invalid RESP message type".to_string()), 0),
+            b'(' => Self::BigNumber(line_string(line)?),
+            b'*' if line == b"-1" => Self::NullArray,
+            b'*' | b'~' | b'>' => {
+                let len = parse_line(line, ProtocolError::InvalidMultibulkLength)?;
+                return Ok(Some((Parsed::Aggregate(message_type, len), header_end)));
+            }
+            _ => {
+                let num_pairs: usize = parse_line(line, ProtocolError::InvalidMultibulkLength)?;
+                let len = num_pairs
+                    .checked_mul(2)
+                    .ok_or(ProtocolError::InvalidMultibulkLength)?;
+                return Ok(Some((Parsed::Aggregate(message_type, len), header_end)));
+            }
+        };
+        Ok(Some((Parsed::Message(message), header_end)))
+    }
+
+    // Parses the message starting at bytes[start..] and returns it with the index right
+    // after it, with the same None and error cases as parse_value.
+    // Aggregates are kept on an explicit stack rather than parsed recursively, so deeply
+    // nested input can only ever hit the nesting limit, never the end of the call stack.
+    fn parse(
+        bytes: &[u8],
+        start: usize,
+        limits: &ProtocolLimits,
+        payloads: &mut Vec<Range<usize>>,
+    ) -> Result<Option<(Self, usize)>, ProtocolError> {
+        let mut stack: Vec<Pending> = vec![];
+        let mut used = start;
+
+        loop {
+            let Some((parsed, end)) = Self::parse_value(bytes, used, limits, payloads)? else {
+                return Ok(None);
+            };
+            used = end;
+
+            let mut message = match parsed {
+                Parsed::Message(message) => message,
+                Parsed::Aggregate(message_type, len) => {
+                    if len > limits.max_multibulk_len {
+                        return Err(ProtocolError::TooManyElements);
+                    }
+                    if stack.len() >= limits.max_nesting_depth {
+                        return Err(ProtocolError::TooDeep);
+                    }
+                    let aggregate = Pending::new(message_type, len);
+                    if !aggregate.is_complete() {
+                        stack.push(aggregate);
+                        continue;
+                    }
+                    aggregate.finish()
+                }
+            };
+
+            // Hand the message to the aggregate waiting for it, which may in turn complete
+            // and have to be handed to its own parent
+            loop {
+                let Some(parent) = stack.last_mut() else {
+                    return Ok(Some((message, used)));
+                };
+                parent.elements.push(message);
+                if !parent.is_complete() {
+                    break;
+                }
+                message = stack.pop().unwrap().finish();
+            }
+        }
+    }
+
+    fn into_pairs(elements: Vec<Self>) -> Vec<(Self, Self)> {
+        let mut elements = elements.into_iter();
+        let mut pairs = vec![];
+        while let (Some(key), Some(value)) = (elements.next(), elements.next()) {
+            pairs.push((key, value));
+        }
+        pairs
+    }
+
+    // Hands the payloads recorded by parse out to the empty bulk strings and verbatim
+    // strings, in the same order parse met them, as slices of the frame
+    fn fill_payloads(&mut self, payloads: Vec<Range<usize>>, frame: &Bytes) {
+        let mut payloads = payloads.into_iter();
+        // Children are pushed in reverse so they are popped in the order they were parsed
+        let mut stack = vec![self];
+
+        while let Some(message) = stack.pop() {
+            match message {
+                Self::BulkString(s) | Self::Verbatim(_, s) => {
+                    if let Some(range) = payloads.next() {
+                        *s = frame.slice(range);
+                    }
+                }
+                Self::Array(elements) | Self::Set(elements) | Self::Push(elements) => {
+                    stack.extend(elements.iter_mut().rev());
+                }
+                Self::Map(pairs) => {
+                    stack.extend(pairs.iter_mut().rev().flat_map(|(key, value)| [value, key]));
+                }
+                Self::Attribute(pairs, reply) => {
+                    stack.push(reply.as_mut());
+                    stack.extend(pairs.iter_mut().rev().flat_map(|(key, value)| [value, key]));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Function that decodes data from the respective RESP format.
+    // Takes the message off the front of src, or returns None (leaving src untouched)
+    // if it has not fully arrived yet and more data has to be read.
+    // Bulk strings share src's memory instead of being copied out of it.
+    pub fn deserialize(
+        src: &mut BytesMut,
+        limits: &ProtocolLimits,
+    ) -> Result<Option<Self>, ProtocolError> {
+        let mut payloads = vec![];
+        let Some((mut message, used)) = Self::parse(src, 0, limits, &mut payloads)? else {
+            return Ok(None);
+        };
+
+        let frame = src.split_to(used).freeze();
+        message.fill_payloads(payloads, &frame);
+        Ok(Some(message))
+    }
+
+    // Decodes a command sent by a client. Like Redis, anything that does not start
+    // with '*' is an inline command (e.g. "SET foo bar\r\n" typed into telnet or nc),
+    // which is split into the same Array of BulkStrings a RESP client would send.
+    pub fn deserialize_request(
+        src: &mut BytesMut,
+        limits: &ProtocolLimits,
+    ) -> Result<Option<Self>, ProtocolError> {
+        match src.first() {
+            None => Ok(None),
+            Some(b'*') => Self::deserialize(src, limits),
+            Some(_) => {
+                let line_len = src.iter().position(|byte| *byte == b'\n');
+                if line_len.unwrap_or(src.len()) > limits.max_inline_len {
+                    return Err(ProtocolError::LineTooLong);
+                }
+                let Some(line_len) = line_len else {
+                    return Ok(None);
+                };
+                let line = src[..line_len].strip_suffix(b"\r").unwrap_or(&src[..line_len]);
+
+                let args = split_inline_args(line)?
+                    .into_iter()
+                    .map(|arg| Self::BulkString(arg.into()))
+                    .collect();
+                src.advance(line_len + 1);
+                Ok(Some(Self::Array(args)))
+            }
         }
     }
 
     // This is the function that we will use to convert the RESPMessage to a String
     pub fn pack_string(&self) -> Result<&str> {
         match self {
-            Self::SimpleString(s) | Self::BulkString(s) => Ok(s),
+            Self::SimpleString(s) => Ok(s),
+            Self::BulkString(s) => Ok(str::from_utf8(s)?),
+            _ => Err(Error::msg("Trying to decode non-string")),
+        }
+    }
+
+    // Same as pack_string, but hands out the raw bytes so binary payloads survive untouched
+    pub fn pack_bytes(&self) -> Result<&[u8]> {
+        match self {
+            Self::SimpleString(s) => Ok(s.as_bytes()),
+            Self::BulkString(s) => Ok(s),
             _ => Err(Error::msg("Trying to decode non-string")),
         }
     }
 
-    pub fn to_command(&self) -> Result<(String, Vec<RESPMessage>), Error> {
+    // The command name and the arguments after it, borrowed from the array
+    pub fn to_command(&self) -> Result<(String, &[RESPMessage]), Error> {
         match self {
             RESPMessage::Array(elements) => {
-                println!("Elements: {:?}", elements);
-                if let Some(RESPMessage::BulkString(command)) = elements.get(0) {
-                    let args: Vec<RESPMessage> = elements.iter().skip(1).cloned().collect();
-                    Ok((command.clone(), args))
+                if let Some(RESPMessage::BulkString(command)) = elements.first() {
+                    Ok((String::from_utf8_lossy(command).into_owned(), &elements[1..]))
                 } else {
                     Err(Error::msg("First element of the Array must be a BulkString"))
                 }
@@ -193,4 +676,4 @@ impl RESPMessage {
     // }
 
 
-}
\ No newline at end of file
+}
@@ -1,161 +1,157 @@
 use crate::{
-    cache::{self, Cache},
+    codec::{RESPCodec, RESPCodecError},
+    command::{self, Client, Context},
+    config::Config,
+    expire,
+    keyspace::Keyspace,
     resp::RESPMessage,
-    simpleElection::{self, *},
+    simpleElection,
+    value::Value,
 };
 use anyhow::{Error, Result};
+use bytes::Bytes;
+use futures::{FutureExt, SinkExt, StreamExt};
 use rand::Rng;
 use std::env;
 use std::net::SocketAddr;
-use std::process::{Command, Stdio};
 use std::thread;
 
-use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, RwLock};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, timeout, Duration, Instant};
+use tokio_util::codec::Framed;
 
-use std::{
-    collections::btree_map::Keys,
-    io::{Read, Write},
-    process,
-    sync::{Arc, Mutex},
-};
-use tokio::time::{timeout, Duration};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
-    stream,
-};
+type Connection<'a> = Framed<&'a mut TcpStream, RESPCodec>;
 
-const MESSAGE_SIZE: usize = 512;
-const CACHE_SIZE: usize = 3;
+// Why a blocked client stopped waiting
+enum Wake {
+    // One of its keys may have something for it now
+    Signalled,
+    TimedOut,
+    HungUp,
+}
 
 pub struct Server {
     listener: TcpListener,
-    cache: Arc<Mutex<Cache>>,
+    keyspace: Arc<Keyspace>,
+    // Shared with every connection, which may change it with CONFIG SET
+    config: Arc<RwLock<Config>>,
 }
 
 //TODO, create a list of servers that connecto master, ping them all to see if alive, if yes add to a list. Send set to each item ont he list
 impl Server {
-    // Use cargo run <PORT> when starting the server
-    // cargo run 6379 key_1 Apple key_2 Orange key_3 Banana
+    // Use cargo run <PORT> [--option value]... when starting the server
+    // cargo run 6380 --proto-max-bulk-len 64mb
     pub async fn new() -> Result<Self, Error> {
         // get arguments from command line ie. port numbers
-        let args: Vec<String> = env::args().collect();
-        let PORT = &args[1];
+        let config = Config::from_args(env::args().skip(1))?;
 
-        //gets keys and values from command line
-        let mut keys: Vec<String> = Vec::new(); //list of keys
-        let mut values: Vec<String> = Vec::new(); //list of values
-                                           
-                                                
-        let listener = TcpListener::bind(format!("127.0.0.1:{}", PORT)).await?;
-        let cache = Arc::new(Mutex::new(Cache::new(CACHE_SIZE)));
+        let listener = TcpListener::bind(format!("127.0.0.1:{}", config.port)).await?;
+        let keyspace = Arc::new(Keyspace::new(&config));
 
-        if PORT == "6379" {
+        if config.is_master() {
             println!("Master Server Started");
 
             let length = 10; //set length of replication ID
 
             let mut rng = rand::thread_rng();
             let charset = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
-            let RUN_ID: String = (0..length)
+            let _run_id: String = (0..length)
                 .map(|_| rng.gen_range(0..charset.len()))
                 .map(|i| charset.chars().nth(i).unwrap())
                 .collect();
         } else {
             println!("Replication Server Started");
             let server_addr = SocketAddr::from(([127, 0, 0, 1], 6379)); // connect to the master server
-            let mut stream = TcpStream::connect(server_addr).await?;
-            let mut buffer = [0; MESSAGE_SIZE];
+            let stream = TcpStream::connect(server_addr).await?;
+            let mut framed = Framed::new(stream, RESPCodec::for_replies(config.limits));
 
-
-            let bulk_message: RESPMessage = RESPMessage::BulkString("SYNC".to_string());
+            let bulk_message: RESPMessage = RESPMessage::BulkString(Bytes::from_static(b"SYNC"));
 
             let array = RESPMessage::Array(vec![bulk_message]);
 
-            let serialized = array.serialize();
-
-            match stream.write_all(&serialized).await {
-                Ok(_) => {}
-                Err(e) => println!("Error: {}", e),
-                _ => println!("Default"),
+            if let Err(e) = framed.send(array).await {
+                println!("Error: {}", e);
             }
 
-            // Read data from the stream into the buffer
+            // Wait until the whole SYNC response has arrived
             let timeout_duration = Duration::from_secs(5);
-            match timeout(timeout_duration, stream.read(&mut buffer)).await {
-                Ok(result) => match result {
-                    Ok(bytes_read) => {
-                        if bytes_read == 0 {
-                            println!("No data received from master");
-                        } else {
-                            println!("Received response from master");
-                            let mut cache = cache.lock().unwrap();
-
-                            // Deserialize the received bytes into a RESPMessage
-                            let (response, _) = RESPMessage::deserialize(&buffer); //read response
-
-                            let response_str = response.pack_string(); //convert from RESP to string
-
-                            //remove from result
-                            let result = response_str
-                                .map(|value| value.to_string())
-                                .unwrap_or_else(|err| format!("Error: {:?}", err));
-
-
-                            let response_array: Vec<&str> = result.split(" ").collect(); //convert string to array
-
-                            for i in 0..response_array.len() / 2 {
-                                //loop array
-                                if i == 0 {
-                                    cache.set(
-                                        response_array[i].to_string(),
-                                        response_array[3].to_string(),
-                                        Some(1000),
-                                    ); //set to cache
-                                } else {
-                                    cache.set(
-                                        response_array[i].to_string(),
-                                        response_array[i +3 ].to_string(),
-                                        Some(1000),
-                                    );
-                                }
-                            }
-
-                            let save = cache.get_key();
-                            let (mut save_k, save_v) = save;
-                            println!("Saved Values in Replica are:");
-                            for i in 0..save_k.len(){
-                                println!("Key {:?} -> Value {:?}", save_k[i] , save_v[i]);
-
-                            }
+            let response = match timeout(timeout_duration, framed.next()).await {
+                Ok(Some(Ok(response))) => Some(response),
+                Ok(Some(Err(e))) => {
+                    println!("Error: {}", e);
+                    None
+                }
+                Ok(None) => {
+                    println!("No data received from master");
+                    None
+                }
+                Err(_) => {
+                    println!("Timeout occurred");
+                    None
+                }
+            };
 
-                            println!("SYNC complete");
-                        }
+            if let Some(response) = response {
+                println!("Received response from master");
+
+                // Each key arrives as an array of the key, its type, when it expires and its
+                // elements
+                let entries = match response {
+                    RESPMessage::Array(entries) => entries,
+                    _ => vec![],
+                };
+                for entry in entries {
+                    let RESPMessage::Array(parts) = entry else {
+                        continue;
+                    };
+                    let Ok(parts) = parts
+                        .iter()
+                        .map(|part| part.pack_bytes().map(<[u8]>::to_vec))
+                        .collect::<Result<Vec<_>>>()
+                    else {
+                        continue;
+                    };
+                    let [key, type_name, expires_at, elements @ ..] = parts.as_slice() else {
+                        continue;
+                    };
+                    let expires_at = match String::from_utf8_lossy(expires_at).parse::<i128>() {
+                        Ok(-1) => None,
+                        Ok(at) if at >= 0 => Some(at as u128),
+                        _ => continue,
+                    };
+                    if let Some(value) = Value::restore(type_name, elements.to_vec(), &config) {
+                        keyspace
+                            .lock(key)
+                            .set_expiring_at(key.clone(), value, expires_at);
                     }
-                    Err(e) => println!("Error: {}", e),
-                },
-                Err(_) => println!("Timeout occurred"),
+                }
+
+                println!("SYNC complete");
             }
 
             //TODO: implement sync so replica has all the same data as MASTER
         }
 
-        Ok(Self { listener, cache })
+        Ok(Self {
+            listener,
+            keyspace,
+            config: Arc::new(RwLock::new(config)),
+        })
     }
     
     pub async fn run(server: Server) -> Result<()> {
         println!("PROCESS_ID: {}", std::process::id());
         let args: Vec<String> = env::args().collect();
         println!("{:?}", args);
-        let PORT = &args[1];
 
         // spawn thread to handle election stuff
-        if PORT != "6379" {
-            let handle = thread::spawn(|| {
+        if !server.config.read().unwrap().is_master() {
+            thread::spawn(|| {
                 // pass a list of potential port numbers that backups can be on
                 // ping leader will call an election using these ports if a pong is not
                 // recieved from the leader in 10 seconds
-                simpleElection::ping_leader(&vec![
+                simpleElection::ping_leader(&[
                     String::from("6380"),
                     String::from("6381"),
                     String::from("6382"),
@@ -169,6 +165,11 @@ impl Server {
                 ]);
             });
         }
+        tokio::spawn(expire::run(
+            Arc::clone(&server.keyspace),
+            Arc::clone(&server.config),
+        ));
+
         //TODO: loop through all replication and forward CACHE Commands to replicas
         loop {
             let incoming = server.listener.accept().await;
@@ -177,13 +178,13 @@ impl Server {
                 //check connection
                 Ok((mut stream, addr)) => {
                     println!("Handling connection from: {}", addr);
-                    let cache = Arc::clone(&server.cache);
+                    let keyspace = Arc::clone(&server.keyspace);
+                    let config = Arc::clone(&server.config);
                     tokio::spawn(async move {
-                        Self::handle_connection(&mut stream, cache).await.unwrap();
+                        if let Err(e) = Self::handle_connection(&mut stream, keyspace, config).await {
+                            println!("Connection from {} failed: {}", addr, e);
+                        }
                     });
-
-
-
                 }
                 Err(e) => {
                     println!("Error: {e}");
@@ -192,125 +193,141 @@ impl Server {
         }
     }
 
-    async fn handle_connection(stream: &mut TcpStream, cache: Arc<Mutex<Cache>>) -> Result<()> {
-        let mut buffer = [0; MESSAGE_SIZE];
-      
-        loop {
-            let bytes_read = stream.read(&mut buffer).await?;
-            if bytes_read == 0 {
-                println!("Closing connection.");
-                break;
-            }
-
-            let (message, _) = RESPMessage::deserialize(&buffer);
-
-            let (command, args) = message.to_command()?;
+    async fn handle_connection(
+        stream: &mut TcpStream,
+        keyspace: Arc<Keyspace>,
+        config: Arc<RwLock<Config>>,
+    ) -> Result<()> {
+        let limits = config.read().unwrap().limits;
+        let mut framed = Framed::new(stream, RESPCodec::for_requests(limits));
+        let mut client = Client::new();
+        // A message that arrived while the client was blocked
+        let mut pending = None;
 
-
-            let response = match command.to_ascii_lowercase().as_ref() {
-                "ping" => RESPMessage::SimpleString("PONG".to_string()),
-                "echo" => args.first().unwrap().clone(),
-                "get" => {
-                    let key = args.get(0).map(|arg| arg.pack_string());
-
-                    match key {
-                        Some(Ok(key)) => match cache.lock().unwrap().get(key.as_ref()) {
-                            Some(value) => {
-                                println!("Got value: {:?}", value);
-                                RESPMessage::BulkString(value)
-                            },
-                            None => RESPMessage::Null,
-                        },
-                        _ => RESPMessage::Error("Invalid key".to_string()),
+        loop {
+            // Pipelined commands are answered together: replies are only flushed once
+            // every command that has already arrived has been handled
+            let message = match pending.take() {
+                Some(message) => Some(message),
+                None => match framed.next().now_or_never() {
+                    Some(message) => message,
+                    None => {
+                        framed.flush().await?;
+                        framed.next().await
                     }
+                },
+            };
+
+            match message {
+                Some(Ok(message)) => {
+                    let run = Self::run_command(
+                        message,
+                        &keyspace,
+                        &config,
+                        &mut client,
+                        &mut framed,
+                        &mut pending,
+                    );
+                    let Some(response) = run.await? else {
+                        println!("Client hung up while blocked.");
+                        break;
+                    };
+                    framed.codec_mut().version = client.protocol;
+                    framed.codec_mut().limits = config.read().unwrap().limits;
+                    framed.feed(response).await?;
                 }
-                "set" => {
-                    let key = args.get(0).map(|arg| arg.pack_string());
-                    let value = args.get(1).map(|arg| arg.pack_string());
-                    let px = args.get(3).map(|arg| arg.pack_string());
-                    match (key, value) {
-                        (Some(Ok(key)), Some(Ok(value))) => {
-                            println!("Setting key: {:?} to value: {:?}", key, value);
-                            let result: Result<(), ()> = match px {
-                                Some(Ok(px)) => {
-                                    let ttl = px.parse::<u64>().ok().map(|ms| ms / 1000);
-                                    let mut cache = cache.lock().unwrap();
-                                    let set_result =
-                                        cache.set(key.to_string(), value.to_string(), ttl);
-                                    
-                                    match set_result {
-                                        Some(_) => Ok(()),
-                                        None => Err(()),
-                                    }
-                                }
-                                _ => {
-                                    let mut cache = cache.lock().unwrap();
-                                    cache.set(key.to_string(), value.to_string(), None);
-                                    Ok(())
-                                }
-                            };
-                            match result {
-                                Ok(_) => RESPMessage::SimpleString("OK".to_string()),
-                                Err(_) => RESPMessage::Error("Error".to_string()),
-                            }
-                        }
-                        _ => RESPMessage::Error("Invalid key or value".to_string()),
-                    }
+                // Nothing after malformed input can be trusted, so answer the commands
+                // that came before it, report the error and hang up
+                Some(Err(RESPCodecError::Protocol(e))) => {
+                    println!("Protocol error: {}", e);
+                    let error = RESPMessage::Error(format!("ERR Protocol error: {}", e));
+                    framed.send(error).await?;
+                    framed.close().await?;
+                    break;
                 }
-                "getserverid" => {
-                    println!("{}", process::id().to_string()); // todo: remove test print
-                    RESPMessage::SimpleString(process::id().to_string())
+                Some(Err(RESPCodecError::Io(e))) => return Err(e.into()),
+                None => {
+                    println!("Closing connection.");
+                    break;
                 }
-                "setleader" => {
-                    println!("Recieved leader message, becoming leader...");
-                    // start server on 6379
-                    let mut child = Command::new(std::env::args().next().unwrap())
-                        .arg("6379")
-                        .stdin(Stdio::null())
-                        .stdout(Stdio::null())
-                        .stderr(Stdio::null())
-                        .spawn()
-                        .expect("Failed to start new instance of the program");
+            }
+        }
+        Ok(())
+    }
 
-                    RESPMessage::SimpleString("OK".to_string());
-                    process::exit(0);
-                }
-                "del" => {
-                    let key = args.get(0).map(|arg| arg.pack_string());
-                    match key {
-                        Some(Ok(key)) => match cache.lock().unwrap().remove(key.as_ref()) {
-                            Some(value) => RESPMessage::BulkString(value.to_string()),
-                            None => RESPMessage::Null,
-                        },
-                        _ => RESPMessage::Error("Invalid Request".to_string()),
-                    }
-                }
-                "sync" => {
-                    // Acquire the lock on the cache and retrieve all keys
-                    let mut resp = cache.lock().unwrap().get_key(); //get keys and values from cache
-                    let (mut cache_k, cache_v) = resp; //returns to arrays
+    // Runs the command, and while it is blocked, runs it again whenever one of its keys is
+    // signalled until it can reply or its timeout passes. None if the client hung up first.
+    async fn run_command(
+        mut message: RESPMessage,
+        keyspace: &Keyspace,
+        config: &RwLock<Config>,
+        client: &mut Client,
+        framed: &mut Connection<'_>,
+        pending: &mut Option<Result<RESPMessage, RESPCodecError>>,
+    ) -> Result<Option<RESPMessage>> {
+        // The timeout counts from when the command first blocked
+        let mut deadline = None;
+        let mut blocked_on = vec![];
+
+        let response = loop {
+            let mut context = Context {
+                keyspace,
+                config,
+                client,
+            };
+            let response = command::execute(&message, &mut context)
+                .unwrap_or_else(|e| RESPMessage::Error(format!("ERR {}", e)));
+            let Some(blocked) = client.blocked.take() else {
+                break Some(response);
+            };
 
-                    println!("Master Values {:?}, {:?}", cache_k, cache_v);
+            let deadline = *deadline
+                .get_or_insert_with(|| blocked.timeout.map(|timeout| Instant::now() + timeout));
+            blocked_on = blocked.keys;
+            if let Some(command) = blocked.command {
+                message = command;
+            }
+            // Replies to the commands that came before are not held back
+            framed.flush().await?;
+            match Self::wait(client, deadline, framed, pending).await {
+                Wake::Signalled => continue,
+                Wake::TimedOut => break Some(blocked.reply),
+                Wake::HungUp => break None,
+            }
+        };
 
-                    cache_k.extend(cache_v); //combine to 1 array
-                    let mut joined_str = String::new();
+        if !blocked_on.is_empty() {
+            keyspace.blocked.unblock(&blocked_on, client.id);
+        }
+        Ok(response)
+    }
 
-                    for i in 0..cache_k.len() {
-                        joined_str = cache_k.join(" ");
-                        RESPMessage::SimpleString(cache_k[i].to_string());
-                    }
+    // Waits for the blocked client to be signalled, its deadline to pass or it to hang up.
+    // The first message it sends meanwhile is kept in pending, after which it is no longer
+    // read from until the client is unblocked.
+    async fn wait(
+        client: &Client,
+        deadline: Option<Instant>,
+        framed: &mut Connection<'_>,
+        pending: &mut Option<Result<RESPMessage, RESPCodecError>>,
+    ) -> Wake {
+        let timeout = async {
+            match deadline {
+                Some(deadline) => time::sleep_until(deadline).await,
+                None => futures::future::pending().await,
+            }
+        };
+        tokio::pin!(timeout);
 
-                    RESPMessage::BulkString(joined_str)
-                }
-                _ => RESPMessage::Error("Error".to_string()),
-            };
-            let serialized_response = RESPMessage::serialize(&response);
-            match stream.write_all(&serialized_response).await {
-                Ok(_) => println!("Write to stream OK"),
-                Err(e) => println!("Error {}", e),
-                _ => println!("Default catch"),
-            };
+        loop {
+            tokio::select! {
+                _ = client.waiter.wait() => return Wake::Signalled,
+                _ = &mut timeout => return Wake::TimedOut,
+                message = framed.next(), if pending.is_none() => match message {
+                    Some(message) => *pending = Some(message),
+                    None => return Wake::HungUp,
+                },
+            }
         }
-        Ok(())
     }
 }
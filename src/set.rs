@@ -0,0 +1,504 @@
+use crate::{
+    cache::Cache,
+    command::{parse_integer, wrong_arity, Context},
+    keyspace::Locked,
+    resp::RESPMessage,
+    value::{parse_i64, Value, WrongType},
+};
+use anyhow::{Error, Result};
+use rand::{seq::index, Rng};
+use std::{collections::HashMap, mem::size_of};
+
+// Members in no particular order. Like Redis, a set whose members are all integers is kept
+// as an intset: the numbers sorted in a single vector and found by binary search. Once it has
+// a member that isn't an integer, or more than set-max-intset-entries of them, it is
+// converted to a hash table for good.
+#[derive(Debug, Clone)]
+pub enum Set {
+    Intset(Vec<i64>),
+    Table(Table),
+}
+
+// A hash table whose members are also kept side by side in a vector, so one can be picked
+// at random by its position. Removing a member moves the last one into its place.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    positions: HashMap<Vec<u8>, usize>,
+    members: Vec<Vec<u8>>,
+    // How many bytes the members take up, kept up to date as they are added and removed
+    size: usize,
+}
+
+// A member of a table has a pointer and length, and is kept twice along with its position
+fn member_size(member: &[u8]) -> usize {
+    2 * member.len() + size_of::<Vec<u8>>() + size_of::<(Vec<u8>, usize)>()
+}
+
+impl Table {
+    fn contains(&self, member: &[u8]) -> bool {
+        self.positions.contains_key(member)
+    }
+
+    fn insert(&mut self, member: Vec<u8>) -> bool {
+        if self.contains(&member) {
+            return false;
+        }
+        self.size += member_size(&member);
+        self.positions.insert(member.clone(), self.members.len());
+        self.members.push(member);
+        true
+    }
+
+    fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(position) = self.positions.remove(member) else {
+            return false;
+        };
+        self.size -= member_size(member);
+        self.members.swap_remove(position);
+        if let Some(moved) = self.members.get(position) {
+            self.positions.insert(moved.clone(), position);
+        }
+        true
+    }
+}
+
+impl FromIterator<Vec<u8>> for Table {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(members: I) -> Self {
+        let mut table = Self::default();
+        for member in members {
+            table.insert(member);
+        }
+        table
+    }
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Self::Intset(vec![])
+    }
+}
+
+impl Set {
+    pub fn from_members(
+        members: impl IntoIterator<Item = Vec<u8>>,
+        max_intset_entries: usize,
+    ) -> Self {
+        let mut set = Self::default();
+        for member in members {
+            set.insert(member, max_intset_entries);
+        }
+        set
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::Intset(_) => "intset",
+            Self::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Intset(integers) => integers.len(),
+            Self::Table(table) => table.members.len(),
+        }
+    }
+
+    // An intset takes 8 bytes a member, while a table counts its members as they come and go
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Intset(integers) => integers.len() * size_of::<i64>(),
+            Self::Table(table) => table.size,
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Intset(integers) => {
+                parse_i64(member).is_some_and(|integer| integers.binary_search(&integer).is_ok())
+            }
+            Self::Table(table) => table.contains(member),
+        }
+    }
+
+    // Adds the member, converting the set to a hash table if it is no longer an intset.
+    // Returns whether the member is new.
+    pub fn insert(&mut self, member: Vec<u8>, max_intset_entries: usize) -> bool {
+        if let Self::Intset(integers) = self {
+            match parse_i64(&member).map(|integer| (integer, integers.binary_search(&integer))) {
+                Some((_, Ok(_))) => return false,
+                Some((integer, Err(position))) if integers.len() < max_intset_entries => {
+                    integers.insert(position, integer);
+                    return true;
+                }
+                _ => self.convert(),
+            }
+        }
+        match self {
+            Self::Table(table) => table.insert(member),
+            Self::Intset(_) => unreachable!("the set was converted"),
+        }
+    }
+
+    // Returns whether the member was there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Self::Intset(integers) => {
+                let position =
+                    parse_i64(member).and_then(|integer| integers.binary_search(&integer).ok());
+                position.map(|position| integers.remove(position)).is_some()
+            }
+            Self::Table(table) => table.remove(member),
+        }
+    }
+
+    // The members of an intset come in ascending order
+    pub fn members(&self) -> Vec<Vec<u8>> {
+        match self {
+            Self::Intset(integers) => integers
+                .iter()
+                .map(|integer| integer.to_string().into_bytes())
+                .collect(),
+            Self::Table(table) => table.members.clone(),
+        }
+    }
+
+    // The member at a position in the intset or the table's vector
+    fn member(&self, position: usize) -> Vec<u8> {
+        match self {
+            Self::Intset(integers) => integers[position].to_string().into_bytes(),
+            Self::Table(table) => table.members[position].clone(),
+        }
+    }
+
+    // Up to count different members, picked at random
+    pub fn random_members(&self, count: usize) -> Vec<Vec<u8>> {
+        let len = self.len();
+        index::sample(&mut rand::thread_rng(), len, count.min(len))
+            .into_iter()
+            .map(|position| self.member(position))
+            .collect()
+    }
+
+    // count members picked at random, each of which may be picked more than once
+    pub fn random_members_repeated(&self, count: usize) -> Vec<Vec<u8>> {
+        let len = self.len();
+        if len == 0 {
+            return vec![];
+        }
+        let mut rng = rand::thread_rng();
+        (0..count)
+            .map(|_| self.member(rng.gen_range(0..len)))
+            .collect()
+    }
+
+    fn convert(&mut self) {
+        if let Self::Intset(_) = self {
+            *self = Self::Table(self.members().into_iter().collect());
+        }
+    }
+}
+
+fn bulk(member: Vec<u8>) -> RESPMessage {
+    RESPMessage::BulkString(member.into())
+}
+
+fn max_intset_entries(context: &Context) -> usize {
+    context.config.read().unwrap().set_max_intset_entries
+}
+
+// Sent as a set to RESP3 clients and an array to RESP2 ones
+fn reply(members: Vec<Vec<u8>>) -> RESPMessage {
+    RESPMessage::Set(members.into_iter().map(bulk).collect())
+}
+
+// SADD key member [member ...]
+// Replies with how many of the members are new
+pub fn sadd(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let members = args[1..]
+        .iter()
+        .map(|arg| Ok(arg.pack_bytes()?.to_vec()))
+        .collect::<Result<Vec<_>>>()?;
+    let max_intset_entries = max_intset_entries(context);
+
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        cache.set_expiring_at(key.to_vec(), Value::Set(Set::default()), None);
+    }
+    let added = cache
+        .modify(key, |value| {
+            let set = value.as_set_mut()?;
+            Ok::<_, WrongType>(
+                members
+                    .into_iter()
+                    .map(|member| set.insert(member, max_intset_entries))
+                    .filter(|added| *added)
+                    .count(),
+            )
+        })
+        .expect("the set exists")?;
+    Ok(RESPMessage::Integer(added as i64))
+}
+
+// SREM key member [member ...]
+// Deletes the set once its last member is removed
+pub fn srem(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let members = args[1..]
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+
+    let removed = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let set = value.as_set_mut()?;
+            Ok::<_, WrongType>(members.iter().filter(|member| set.remove(member)).count())
+        })
+        .transpose()?;
+    Ok(RESPMessage::Integer(removed.unwrap_or(0) as i64))
+}
+
+// Runs f on the set at key, or replies with missing if there is none
+fn read(
+    cache: &mut Cache,
+    key: &[u8],
+    missing: RESPMessage,
+    f: impl FnOnce(&Set) -> RESPMessage,
+) -> Result<RESPMessage> {
+    match cache.get(key) {
+        Some(value) => Ok(f(value.as_set()?)),
+        None => Ok(missing),
+    }
+}
+
+// SISMEMBER key member
+pub fn sismember(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, member) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    read(
+        &mut context.keyspace.lock(key),
+        key,
+        RESPMessage::Integer(0),
+        |set| RESPMessage::Integer(set.contains(member) as i64),
+    )
+}
+
+// SMEMBERS key
+pub fn smembers(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    read(&mut context.keyspace.lock(key), key, reply(vec![]), |set| {
+        reply(set.members())
+    })
+}
+
+// SCARD key
+pub fn scard(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    read(
+        &mut context.keyspace.lock(key),
+        key,
+        RESPMessage::Integer(0),
+        |set| RESPMessage::Integer(set.len() as i64),
+    )
+}
+
+#[derive(Clone, Copy)]
+enum Operation {
+    // The members every set has
+    Inter,
+    // The members any set has
+    Union,
+    // The members of the first set none of the others have
+    Diff,
+}
+
+// Combines the sets at keys, with the shards of all of them locked. A missing key counts as
+// an empty set.
+fn combine(
+    shards: &mut Locked,
+    keys: &[&[u8]],
+    operation: Operation,
+    max_intset_entries: usize,
+) -> Result<Set> {
+    // Each key is looked up once to count the use and drop it if it has expired, after which
+    // the sets can all be borrowed at once
+    for key in keys {
+        shards.shard(key).get(key);
+    }
+    let empty = Set::default();
+    let mut sets = vec![];
+    for key in keys {
+        sets.push(match shards.peek(key) {
+            Some(value) => value.as_set()?,
+            None => &empty,
+        });
+    }
+
+    let members: Vec<Vec<u8>> = match operation {
+        Operation::Inter => {
+            // Only the members of the smallest set have to be looked up in the others
+            sets.sort_unstable_by_key(|set| set.len());
+            let (smallest, others) = sets.split_first().expect("there is at least one key");
+            smallest
+                .members()
+                .into_iter()
+                .filter(|member| others.iter().all(|set| set.contains(member)))
+                .collect()
+        }
+        Operation::Union => sets.iter().flat_map(|set| set.members()).collect(),
+        Operation::Diff => {
+            let (first, others) = sets.split_first().expect("there is at least one key");
+            first
+                .members()
+                .into_iter()
+                .filter(|member| !others.iter().any(|set| set.contains(member)))
+                .collect()
+        }
+    };
+    Ok(Set::from_members(members, max_intset_entries))
+}
+
+fn combine_command(
+    context: &mut Context,
+    args: &[RESPMessage],
+    operation: Operation,
+) -> Result<RESPMessage> {
+    let keys = args
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+    let max_intset_entries = max_intset_entries(context);
+    let mut shards = context.keyspace.lock_all(keys.iter().copied());
+    let set = combine(&mut shards, &keys, operation, max_intset_entries)?;
+    Ok(reply(set.members()))
+}
+
+// Replaces whatever the destination holds with the result, or deletes it if the result is
+// empty. Replies with the number of members in the result.
+fn store_command(
+    context: &mut Context,
+    args: &[RESPMessage],
+    operation: Operation,
+) -> Result<RESPMessage> {
+    let keys = args
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+    let (destination, sources) = keys.split_first().expect("the arity was checked");
+    let max_intset_entries = max_intset_entries(context);
+
+    let mut shards = context.keyspace.lock_all(keys.iter().copied());
+    let set = combine(&mut shards, sources, operation, max_intset_entries)?;
+    let len = set.len();
+    let cache = shards.shard(destination);
+    if len == 0 {
+        cache.delete(destination);
+    } else {
+        cache.set_expiring_at(destination.to_vec(), Value::Set(set), None);
+    }
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// SINTER key [key ...]
+pub fn sinter(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    combine_command(context, args, Operation::Inter)
+}
+
+// SUNION key [key ...]
+pub fn sunion(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    combine_command(context, args, Operation::Union)
+}
+
+// SDIFF key [key ...]
+pub fn sdiff(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    combine_command(context, args, Operation::Diff)
+}
+
+// SINTERSTORE destination key [key ...]
+pub fn sinterstore(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    store_command(context, args, Operation::Inter)
+}
+
+// SUNIONSTORE destination key [key ...]
+pub fn sunionstore(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    store_command(context, args, Operation::Union)
+}
+
+// SDIFFSTORE destination key [key ...]
+pub fn sdiffstore(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    store_command(context, args, Operation::Diff)
+}
+
+// SRANDMEMBER key [count]
+// Without a count a single member is replied with. A positive count picks that many
+// different members at most, while a negative one picks -count members that may repeat.
+pub fn srandmember(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    if args.len() > 2 {
+        return Ok(wrong_arity("srandmember"));
+    }
+    let key = args[0].pack_bytes()?;
+    let count = args.get(1).map(parse_integer).transpose()?;
+    // As in Redis, so a negative count can't have a huge number of members picked
+    if count.is_some_and(|count| count.unsigned_abs() > (i64::MAX / 2) as u64) {
+        return Err(Error::msg("value is out of range"));
+    }
+
+    let mut cache = context.keyspace.lock(key);
+    let Some(count) = count else {
+        return read(&mut cache, key, RESPMessage::Null, |set| {
+            set.random_members(1)
+                .into_iter()
+                .next()
+                .map_or(RESPMessage::Null, bulk)
+        });
+    };
+    read(&mut cache, key, RESPMessage::Array(vec![]), |set| {
+        let members = if count >= 0 {
+            set.random_members(count as usize)
+        } else {
+            set.random_members_repeated(count.unsigned_abs() as usize)
+        };
+        RESPMessage::Array(members.into_iter().map(bulk).collect())
+    })
+}
+
+// SPOP key [count]
+// Deletes the set once its last member is popped
+pub fn spop(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    if args.len() > 2 {
+        return Ok(wrong_arity("spop"));
+    }
+    let key = args[0].pack_bytes()?;
+    let count = match args.get(1) {
+        Some(count) => {
+            let count = parse_integer(count)?;
+            if count < 0 {
+                return Err(Error::msg("value is out of range, must be positive"));
+            }
+            Some(count as usize)
+        }
+        None => None,
+    };
+
+    let popped = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let set = value.as_set_mut()?;
+            let popped = set.random_members(count.unwrap_or(1));
+            for member in &popped {
+                set.remove(member);
+            }
+            Ok::<_, WrongType>(popped)
+        })
+        .transpose()?;
+
+    Ok(match (popped, count) {
+        (None, None) => RESPMessage::Null,
+        (None, Some(_)) => reply(vec![]),
+        (Some(popped), None) => popped.into_iter().next().map_or(RESPMessage::Null, bulk),
+        (Some(popped), Some(_)) => reply(popped),
+    })
+}
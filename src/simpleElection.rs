@@ -1,4 +1,5 @@
 use crate::{resp::RESPMessage};
+use bytes::Bytes;
 use std::{collections::HashMap, process};
 use std::net::{TcpStream};
 use std::io::{Read, Write};
@@ -26,12 +27,12 @@ pub fn ping_leader(ports: &[String]) {
 // a simple election algorithm that elects the server with the highest process id
 fn start_election(ports: &[String]) {
     let mut server_ids = HashMap::new();
+    // match response with process ID regex (if not a match the server at that port is not alive)
+    let reg = Regex::new(r"^\+\d+\r\n").unwrap();
     // loop over the secondary server ports and get their server Ids
     for port in ports {
         // send get server id message a save response
         let response = send_get_server_id_message(port.to_string());
-        // match response with process ID regex (if not a match the server at that port is not alive)
-        let reg = Regex::new(r"^\+\d+\r\n").unwrap();
 
         match reg.is_match(&response) {
             true => {
@@ -50,7 +51,7 @@ fn start_election(ports: &[String]) {
     
     // loop over the server IDs and find the server with the largest I
     let mut max_id = std::process::id() as i32;
-    for (&server_id, port) in server_ids.iter() {
+    for &server_id in server_ids.keys() {
         if server_id > max_id {
             max_id = server_id;
         }
@@ -64,7 +65,7 @@ fn start_election(ports: &[String]) {
         },
         None => {
             println!("Initiating server has highest id of {}. Becoming leader...", max_id);
-            let mut child = Command::new(std::env::args().next().unwrap())
+            Command::new(std::env::args().next().unwrap())
                 .arg("6379")
                 .stdin(Stdio::null())
                 .stdout(Stdio::null())
@@ -81,14 +82,14 @@ fn send_get_server_id_message(port: String) -> String {
     let addr = "localhost:".to_string() + "" + &port;
     let mut stream = match TcpStream::connect(addr) {
         Ok(stream) => stream,
-        Err(e) => {
+        Err(_) => {
             return "NOTALIVE".to_string();
         }
     };
 
-    stream.set_read_timeout(Some(Duration::from_secs(1)));
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
 
-    let bulk_message: RESPMessage = RESPMessage::BulkString("GETSERVERID".to_string());
+    let bulk_message: RESPMessage = RESPMessage::BulkString(Bytes::from_static(b"GETSERVERID"));
 
     let array = RESPMessage::Array(vec![
         bulk_message
@@ -99,13 +100,10 @@ fn send_get_server_id_message(port: String) -> String {
     match stream.write_all(&serialized) {
         Ok(_) => {},
         Err(e) => println!("Error: {}", e),
-        _ => println!("Default"),
     }
     let mut response = String::new();
-    match stream.read_to_string(&mut response) {
-        Ok(_) => println!("{}", response),
-        Err(e) => {},
-        _ => println!("Default case"),
+    if stream.read_to_string(&mut response).is_ok() {
+        println!("{}", response);
     }
     response
 }
@@ -120,9 +118,9 @@ fn send_set_leader_message(port: String) -> String {
         }
     };
 
-    stream.set_read_timeout(Some(Duration::from_secs(1)));
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
 
-    let bulk_message: RESPMessage = RESPMessage::BulkString("SETLEADER".to_string());
+    let bulk_message: RESPMessage = RESPMessage::BulkString(Bytes::from_static(b"SETLEADER"));
 
     let array = RESPMessage::Array(vec![
         bulk_message
@@ -133,13 +131,10 @@ fn send_set_leader_message(port: String) -> String {
     match stream.write_all(&serialized) {
         Ok(_) => println!("Write to stream OK"),
         Err(e) => println!("Error: {}", e),
-        _ => println!("Default"),
     }
     let mut response = String::new();
-    match stream.read_to_string(&mut response) {
-        Ok(_) => println!("{}", response),
-        Err(e) => {},
-        _ => println!("Default case"),
+    if stream.read_to_string(&mut response).is_ok() {
+        println!("{}", response);
     }
     response  
 }
@@ -153,9 +148,9 @@ fn send_ping_message_to_leader() -> String {
         }
     };
 
-    stream.set_read_timeout(Some(Duration::from_secs(1)));
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
 
-    let bulk_message: RESPMessage = RESPMessage::BulkString("PING".to_string());
+    let bulk_message: RESPMessage = RESPMessage::BulkString(Bytes::from_static(b"PING"));
 
     let array = RESPMessage::Array(vec![
         bulk_message
@@ -166,13 +161,10 @@ fn send_ping_message_to_leader() -> String {
     match stream.write_all(&serialized) {
         Ok(_) => println!("Write to stream OK"),
         Err(e) => println!("Error: {}", e),
-        _ => println!("Default"),
     }
     let mut response = String::new();
-    match stream.read_to_string(&mut response) {
-        Ok(_) => println!("{}", response),
-        Err(e) => {},
-        _ => println!("Default case"),
+    if stream.read_to_string(&mut response).is_ok() {
+        println!("{}", response);
     }
     response 
 }
\ No newline at end of file
@@ -0,0 +1,1275 @@
+use crate::{
+    blocking::Blocked,
+    cache::now,
+    command::{parse_integer, syntax_error, wrong_arity, Context},
+    resp::{RESPMessage, RESPVersion},
+    value::{Value, WrongType},
+};
+use anyhow::{Error, Result};
+use std::{
+    collections::{btree_map, BTreeMap},
+    fmt,
+    mem::size_of,
+    ops::Bound::{self, Excluded, Included, Unbounded},
+    str::FromStr,
+    time::Duration,
+};
+
+// The fields of an entry and their values, in the order they were given
+type Fields = Vec<(Vec<u8>, Vec<u8>)>;
+
+// An entry ID: the unix time in milliseconds the entry was added at, and a sequence number
+// telling apart the entries added in the same millisecond
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamId {
+    ms: u64,
+    seq: u64,
+}
+
+impl StreamId {
+    const MIN: Self = Self { ms: 0, seq: 0 };
+    const MAX: Self = Self {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    // Parses ms-seq, or just ms, in which case the sequence number is seq
+    fn parse(bytes: &[u8], seq: u64) -> Option<Self> {
+        let id = std::str::from_utf8(bytes).ok()?;
+        Some(match id.split_once('-') {
+            Some((ms, given)) => Self {
+                ms: ms.parse().ok()?,
+                seq: given.parse().ok()?,
+            },
+            None => Self {
+                ms: id.parse().ok()?,
+                seq,
+            },
+        })
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+// An entry delivered to a consumer of a group that it hasn't acknowledged yet
+#[derive(Debug, Clone)]
+struct Pending {
+    consumer: Vec<u8>,
+    // Unix time in milliseconds
+    delivered_at: u128,
+    deliveries: u64,
+}
+
+// A consumer group: how far into the stream it has read, and the entries delivered to
+// its consumers that are still pending
+#[derive(Debug, Clone)]
+struct Group {
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, Pending>,
+    // Each consumer and the unix time in milliseconds it was last seen at
+    consumers: BTreeMap<Vec<u8>, u128>,
+    // How many bytes the consumers take up, kept up to date as they are added and removed
+    consumers_size: usize,
+}
+
+fn consumer_size(consumer: &[u8]) -> usize {
+    consumer.len() + size_of::<u128>()
+}
+
+// An entry takes its ID, and the fields and values with a pointer and length each
+fn entry_size(fields: &Fields) -> usize {
+    let fields: usize = fields
+        .iter()
+        .map(|(field, value)| field.len() + value.len() + 2 * size_of::<Vec<u8>>())
+        .sum();
+    size_of::<StreamId>() + fields
+}
+
+impl Group {
+    fn new(last_delivered: StreamId) -> Self {
+        Self {
+            last_delivered,
+            pending: BTreeMap::new(),
+            consumers: BTreeMap::new(),
+            consumers_size: 0,
+        }
+    }
+
+    // Notes that the consumer was seen now, adding it if it's new
+    fn see(&mut self, consumer: &[u8]) {
+        self.seen_at(consumer.to_vec(), now());
+    }
+
+    // Notes that the consumer was last seen at the unix time in milliseconds
+    fn seen_at(&mut self, consumer: Vec<u8>, time: u128) {
+        let size = consumer_size(&consumer);
+        if self.consumers.insert(consumer, time).is_none() {
+            self.consumers_size += size;
+        }
+    }
+
+    fn remove_consumer(&mut self, consumer: &[u8]) {
+        if self.consumers.remove(consumer).is_some() {
+            self.consumers_size -= consumer_size(consumer);
+        }
+    }
+}
+
+// The entries of a stream ordered by their IDs, and its consumer groups
+#[derive(Debug, Clone, Default)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Fields>,
+    // The ID of the last entry added, which new ones have to come after even once it
+    // was trimmed away
+    last_id: StreamId,
+    groups: BTreeMap<Vec<u8>, Group>,
+    // How many bytes the entries take up, kept up to date as they are added and trimmed
+    entries_size: usize,
+}
+
+// How XADD is given the ID of a new entry: generated entirely, generated after the given
+// milliseconds or given explicitly
+enum NewId {
+    Auto,
+    Sequence(u64),
+    Explicit(StreamId),
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // The entries and consumers are counted as they come and go, leaving only the groups
+    // themselves to add up
+    pub fn size(&self) -> usize {
+        let groups: usize = self
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                name.len()
+                    + group.consumers_size
+                    + group.pending.len() * size_of::<(StreamId, Pending)>()
+            })
+            .sum();
+        self.entries_size + groups
+    }
+
+    fn insert(&mut self, id: StreamId, fields: Fields) {
+        self.entries_size += entry_size(&fields);
+        self.entries.insert(id, fields);
+    }
+
+    // The ID XADD gives the new entry, which has to come after every entry added before
+    fn next_id(&self, id: NewId) -> Result<StreamId> {
+        let last = self.last_id;
+        let id = match id {
+            NewId::Auto if last == StreamId::MAX => {
+                return Err(Error::msg(
+                    "The stream has exhausted the last possible ID, unable to add more items",
+                ))
+            }
+            // The clock may have gone back, in which case the entry counts as added in
+            // the same millisecond as the last one
+            NewId::Auto => {
+                let ms = (now() as u64).max(last.ms);
+                match last.seq.checked_add(1) {
+                    Some(seq) if ms == last.ms => StreamId { ms, seq },
+                    None if ms == last.ms => StreamId { ms: ms + 1, seq: 0 },
+                    _ => StreamId { ms, seq: 0 },
+                }
+            }
+            NewId::Sequence(ms) if ms == last.ms => StreamId {
+                ms,
+                seq: last.seq.saturating_add(1),
+            },
+            NewId::Sequence(ms) => StreamId { ms, seq: 0 },
+            NewId::Explicit(id) => id,
+        };
+        if id <= last {
+            return Err(Error::msg(
+                "The ID specified in XADD is equal or smaller than the target stream top item",
+            ));
+        }
+        Ok(id)
+    }
+
+    // Removes entries from the start of the stream until it meets the threshold, or
+    // until the limit is reached. Returns how many were removed.
+    fn trim(&mut self, trim: &Trim) -> usize {
+        let mut removed = 0;
+        while trim.limit.is_none_or(|limit| removed < limit) {
+            let over = match trim.threshold {
+                Threshold::MaxLen(max) => self.entries.len() > max,
+                Threshold::MinId(min) => self
+                    .entries
+                    .first_key_value()
+                    .is_some_and(|(id, _)| *id < min),
+            };
+            if !over {
+                break;
+            }
+            if let Some((_, fields)) = self.entries.pop_first() {
+                self.entries_size -= entry_size(&fields);
+            }
+            removed += 1;
+        }
+        removed
+    }
+
+    // The stream as SYNC sends it: the last ID, the number of entries followed by the ID,
+    // number of fields and fields and values of each, then the number of groups followed
+    // by the name and last delivered ID of each, its consumers with when they were last
+    // seen and its pending entries with their consumer, delivery time and deliveries
+    pub fn dump(&self) -> Vec<Vec<u8>> {
+        let text = |value: &dyn fmt::Display| value.to_string().into_bytes();
+        let mut elements = vec![text(&self.last_id), text(&self.entries.len())];
+        for (id, fields) in &self.entries {
+            elements.extend([text(id), text(&fields.len())]);
+            for (field, value) in fields {
+                elements.extend([field.clone(), value.clone()]);
+            }
+        }
+        elements.push(text(&self.groups.len()));
+        for (name, group) in &self.groups {
+            elements.extend([name.clone(), text(&group.last_delivered)]);
+            elements.push(text(&group.consumers.len()));
+            for (consumer, seen) in &group.consumers {
+                elements.extend([consumer.clone(), text(seen)]);
+            }
+            elements.push(text(&group.pending.len()));
+            for (id, pending) in &group.pending {
+                elements.extend([
+                    text(id),
+                    pending.consumer.clone(),
+                    text(&pending.delivered_at),
+                    text(&pending.deliveries),
+                ]);
+            }
+        }
+        elements
+    }
+
+    // Builds the stream back from what dump returned. None if the elements don't fit.
+    pub fn restore(elements: Vec<Vec<u8>>) -> Option<Self> {
+        let mut elements = elements.into_iter();
+        let mut stream = Self {
+            last_id: read_id(&mut elements)?,
+            ..Self::default()
+        };
+        for _ in 0..read_number::<usize>(&mut elements)? {
+            let id = read_id(&mut elements)?;
+            let fields = (0..read_number::<usize>(&mut elements)?)
+                .map(|_| Some((elements.next()?, elements.next()?)))
+                .collect::<Option<_>>()?;
+            stream.insert(id, fields);
+        }
+        for _ in 0..read_number::<usize>(&mut elements)? {
+            let name = elements.next()?;
+            let mut group = Group::new(read_id(&mut elements)?);
+            for _ in 0..read_number::<usize>(&mut elements)? {
+                let consumer = elements.next()?;
+                group.seen_at(consumer, read_number(&mut elements)?);
+            }
+            for _ in 0..read_number::<usize>(&mut elements)? {
+                let id = read_id(&mut elements)?;
+                let pending = Pending {
+                    consumer: elements.next()?,
+                    delivered_at: read_number(&mut elements)?,
+                    deliveries: read_number(&mut elements)?,
+                };
+                group.pending.insert(id, pending);
+            }
+            stream.groups.insert(name, group);
+        }
+        elements.next().is_none().then_some(stream)
+    }
+}
+
+fn read_number<T: FromStr>(elements: &mut impl Iterator<Item = Vec<u8>>) -> Option<T> {
+    std::str::from_utf8(&elements.next()?).ok()?.parse().ok()
+}
+
+fn read_id(elements: &mut impl Iterator<Item = Vec<u8>>) -> Option<StreamId> {
+    StreamId::parse(&elements.next()?, 0)
+}
+
+// The part of the map between the bounds. Empty rather than a panic when they are the
+// wrong way round.
+fn range<V>(
+    map: &BTreeMap<StreamId, V>,
+    start: Bound<StreamId>,
+    end: Bound<StreamId>,
+) -> btree_map::Range<'_, StreamId, V> {
+    let (Included(first) | Excluded(first), Included(last) | Excluded(last)) = (start, end) else {
+        return map.range((start, end));
+    };
+    let empty =
+        first > last || (first == last && !matches!((start, end), (Included(_), Included(_))));
+    if empty {
+        // Nothing comes before 0-0
+        map.range((Unbounded, Excluded(StreamId::MIN)))
+    } else {
+        map.range((start, end))
+    }
+}
+
+// What a stream is trimmed down to: at most a number of entries, or the entries from an
+// ID on
+#[derive(Clone, Copy)]
+enum Threshold {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+struct Trim {
+    threshold: Threshold,
+    // How many entries may be removed at most
+    limit: Option<usize>,
+}
+
+impl Trim {
+    // Parses MAXLEN | MINID [= | ~] threshold [LIMIT count] at the start of args, and
+    // returns how many of them it took up. An approximate ~ trim removes every entry it
+    // can, which is as much as Redis may remove, and is the only one LIMIT can be used with.
+    fn parse(args: &[RESPMessage]) -> Result<(Self, usize)> {
+        let maxlen = match args[0].pack_bytes()?.to_ascii_lowercase().as_slice() {
+            b"maxlen" => true,
+            b"minid" => false,
+            _ => return Err(syntax_error()),
+        };
+        let (approximate, mut used) = match args.get(1).map(RESPMessage::pack_bytes).transpose()? {
+            Some(b"~") => (true, 2),
+            Some(b"=") => (false, 2),
+            _ => (false, 1),
+        };
+        let threshold = args.get(used).ok_or_else(syntax_error)?;
+        let threshold = if maxlen {
+            let max = usize::try_from(parse_integer(threshold)?)
+                .map_err(|_| Error::msg("The MAXLEN argument must be >= 0."))?;
+            Threshold::MaxLen(max)
+        } else {
+            Threshold::MinId(parse_id(threshold, 0)?)
+        };
+        used += 1;
+
+        let mut limit = None;
+        let option = args.get(used).map(RESPMessage::pack_bytes).transpose()?;
+        if option.is_some_and(|option| option.eq_ignore_ascii_case(b"limit")) {
+            if !approximate {
+                return Err(Error::msg(
+                    "syntax error, LIMIT cannot be used without the special ~ option",
+                ));
+            }
+            let count = args.get(used + 1).ok_or_else(syntax_error)?;
+            let count = usize::try_from(parse_integer(count)?)
+                .map_err(|_| Error::msg("The LIMIT argument must be >= 0."))?;
+            // LIMIT 0 means no limit
+            limit = (count > 0).then_some(count);
+            used += 2;
+        }
+        Ok((Self { threshold, limit }, used))
+    }
+}
+
+fn invalid_id() -> Error {
+    Error::msg("Invalid stream ID specified as stream command argument")
+}
+
+fn parse_id(arg: &RESPMessage, seq: u64) -> Result<StreamId> {
+    StreamId::parse(arg.pack_bytes()?, seq).ok_or_else(invalid_id)
+}
+
+// A bound of XRANGE and the like: - or + for the lowest or highest possible ID, or an ID,
+// which is excluded when it starts with (. An ID without a sequence number gets seq.
+fn parse_bound(arg: &RESPMessage, seq: u64) -> Result<Bound<StreamId>> {
+    Ok(match arg.pack_bytes()? {
+        b"-" => Included(StreamId::MIN),
+        b"+" => Included(StreamId::MAX),
+        [b'(', id @ ..] => Excluded(StreamId::parse(id, seq).ok_or_else(invalid_id)?),
+        id => Included(StreamId::parse(id, seq).ok_or_else(invalid_id)?),
+    })
+}
+
+fn bulk(bytes: Vec<u8>) -> RESPMessage {
+    RESPMessage::BulkString(bytes.into())
+}
+
+fn bulk_id(id: StreamId) -> RESPMessage {
+    bulk(id.to_string().into_bytes())
+}
+
+// An entry as [id, [field, value, ...]], or [id, nil] when it was trimmed away while
+// still pending
+fn entry(id: StreamId, fields: Option<&Fields>) -> RESPMessage {
+    let fields = fields.map_or(RESPMessage::NullArray, |fields| {
+        RESPMessage::Array(
+            fields
+                .iter()
+                .flat_map(|(field, value)| [bulk(field.clone()), bulk(value.clone())])
+                .collect(),
+        )
+    });
+    RESPMessage::Array(vec![bulk_id(id), fields])
+}
+
+fn lossy(bytes: &[u8]) -> std::borrow::Cow<'_, str> {
+    String::from_utf8_lossy(bytes)
+}
+
+// XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]] * | id field value
+// [field value ...]
+// The ID may also be ms-* to only have the sequence number generated
+pub fn xadd(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (mut nomkstream, mut trim) = (false, None);
+    let mut rest = &args[1..];
+    while let Some(option) = rest.first() {
+        match option.pack_bytes()?.to_ascii_lowercase().as_slice() {
+            b"nomkstream" => {
+                nomkstream = true;
+                rest = &rest[1..];
+            }
+            b"maxlen" | b"minid" => {
+                let (parsed, used) = Trim::parse(rest)?;
+                trim = Some(parsed);
+                rest = &rest[used..];
+            }
+            _ => break,
+        }
+    }
+    let [id, fields @ ..] = rest else {
+        return Err(syntax_error());
+    };
+    if fields.is_empty() || !fields.len().is_multiple_of(2) {
+        return Ok(wrong_arity("xadd"));
+    }
+    let id = match id.pack_bytes()? {
+        b"*" => NewId::Auto,
+        id => match id.strip_suffix(b"-*") {
+            Some(ms) => NewId::Sequence(
+                std::str::from_utf8(ms)
+                    .ok()
+                    .and_then(|ms| ms.parse().ok())
+                    .ok_or_else(invalid_id)?,
+            ),
+            None => match StreamId::parse(id, 0).ok_or_else(invalid_id)? {
+                StreamId::MIN => {
+                    return Err(Error::msg(
+                        "The ID specified in XADD must be greater than 0-0",
+                    ))
+                }
+                id => NewId::Explicit(id),
+            },
+        },
+    };
+    let fields = fields
+        .chunks(2)
+        .map(|pair| {
+            Ok((
+                pair[0].pack_bytes()?.to_vec(),
+                pair[1].pack_bytes()?.to_vec(),
+            ))
+        })
+        .collect::<Result<Fields>>()?;
+
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        if nomkstream {
+            return Ok(RESPMessage::Null);
+        }
+        cache.set_expiring_at(key.to_vec(), Value::Stream(Stream::default()), None);
+    }
+    let id = cache
+        .modify(key, |value| {
+            let stream = value.as_stream_mut()?;
+            let id = stream.next_id(id)?;
+            stream.insert(id, fields);
+            stream.last_id = id;
+            if let Some(trim) = &trim {
+                stream.trim(trim);
+            }
+            Ok::<_, Error>(id)
+        })
+        .expect("the stream exists")?;
+    drop(cache);
+    context.keyspace.blocked.signal_all(key);
+    Ok(bulk_id(id))
+}
+
+// XLEN key
+pub fn xlen(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let mut cache = context.keyspace.lock(key);
+    let len = match cache.get(key) {
+        Some(value) => value.as_stream()?.len(),
+        None => 0,
+    };
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// XRANGE key start end [COUNT count]
+pub fn xrange(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    range_command(context, args, false)
+}
+
+// XREVRANGE key end start [COUNT count]
+pub fn xrevrange(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    range_command(context, args, true)
+}
+
+fn range_command(context: &mut Context, args: &[RESPMessage], rev: bool) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (start, end) = if rev {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+    let (start, end) = (parse_bound(start, 0)?, parse_bound(end, u64::MAX)?);
+    let count = match &args[3..] {
+        [] => usize::MAX,
+        [option, count] if option.pack_bytes()?.eq_ignore_ascii_case(b"count") => {
+            // A negative count reads nothing
+            parse_integer(count)?.max(0) as usize
+        }
+        _ => return Err(syntax_error()),
+    };
+
+    let mut cache = context.keyspace.lock(key);
+    let Some(value) = cache.get(key) else {
+        return Ok(RESPMessage::Array(vec![]));
+    };
+    let entries = range(&value.as_stream()?.entries, start, end);
+    let entries: Vec<_> = if rev {
+        entries
+            .rev()
+            .take(count)
+            .map(|(id, fields)| entry(*id, Some(fields)))
+            .collect()
+    } else {
+        entries
+            .take(count)
+            .map(|(id, fields)| entry(*id, Some(fields)))
+            .collect()
+    };
+    Ok(RESPMessage::Array(entries))
+}
+
+// XTRIM key MAXLEN | MINID [= | ~] threshold [LIMIT count]
+pub fn xtrim(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (trim, used) = Trim::parse(&args[1..])?;
+    if used != args.len() - 1 {
+        return Err(syntax_error());
+    }
+    let removed = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            Ok::<_, WrongType>(value.as_stream_mut()?.trim(&trim))
+        })
+        .transpose()?
+        .unwrap_or(0);
+    Ok(RESPMessage::Integer(removed as i64))
+}
+
+// The options XREAD and XREADGROUP take before STREAMS
+struct ReadOptions {
+    count: usize,
+    // How long to block for, forever if None, when BLOCK was given
+    block: Option<Option<Duration>>,
+    noack: bool,
+}
+
+impl ReadOptions {
+    // Parses the options up to STREAMS and returns them with the arguments after it.
+    // NOACK is only taken by XREADGROUP.
+    fn parse(args: &[RESPMessage], group: bool) -> Result<(Self, &[RESPMessage])> {
+        let mut options = Self {
+            count: usize::MAX,
+            block: None,
+            noack: false,
+        };
+        let mut rest = args;
+        loop {
+            let [option, after @ ..] = rest else {
+                return Err(syntax_error());
+            };
+            match option.pack_bytes()?.to_ascii_lowercase().as_slice() {
+                b"streams" => return Ok((options, after)),
+                b"count" => {
+                    let count = parse_integer(after.first().ok_or_else(syntax_error)?)?;
+                    // So does a count that isn't positive
+                    if count > 0 {
+                        options.count = count as usize;
+                    }
+                    rest = &after[1..];
+                }
+                b"block" => {
+                    let ms = after
+                        .first()
+                        .ok_or_else(syntax_error)?
+                        .pack_string()
+                        .ok()
+                        .and_then(|ms| ms.parse::<i64>().ok())
+                        .ok_or_else(|| Error::msg("timeout is not an integer or out of range"))?;
+                    if ms < 0 {
+                        return Err(Error::msg("timeout is negative"));
+                    }
+                    options.block = Some((ms > 0).then(|| Duration::from_millis(ms as u64)));
+                    rest = &after[1..];
+                }
+                b"noack" if group => {
+                    options.noack = true;
+                    rest = after;
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+    }
+}
+
+// Splits the arguments after STREAMS into the keys and the ID given for each of them
+fn split_streams<'a>(
+    args: &'a [RESPMessage],
+    name: &str,
+    special: &str,
+) -> Result<(Vec<Vec<u8>>, &'a [RESPMessage])> {
+    if args.is_empty() {
+        return Err(syntax_error());
+    }
+    if !args.len().is_multiple_of(2) {
+        return Err(Error::msg(format!(
+            "Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.",
+            name, special
+        )));
+    }
+    let (keys, ids) = args.split_at(args.len() / 2);
+    let keys = keys
+        .iter()
+        .map(|key| Ok(key.pack_bytes()?.to_vec()))
+        .collect::<Result<_>>()?;
+    Ok((keys, ids))
+}
+
+// What XREAD and XREADGROUP reply with: the entries read from each stream, as a map from
+// key to entries for RESP3 clients and as [key, entries] pairs for RESP2 ones
+fn read_reply(context: &Context, read: Vec<(Vec<u8>, Vec<RESPMessage>)>) -> RESPMessage {
+    let read = read
+        .into_iter()
+        .map(|(key, entries)| (bulk(key), RESPMessage::Array(entries)));
+    if context.client.protocol == RESPVersion::RESP3 {
+        RESPMessage::Map(read.collect())
+    } else {
+        RESPMessage::Array(
+            read.map(|(key, entries)| RESPMessage::Array(vec![key, entries]))
+                .collect(),
+        )
+    }
+}
+
+// Leaves the client blocked on the keys like list::block does, to be replied to with nil
+// if nothing is added to them before the timeout
+fn block(
+    context: &mut Context,
+    keys: Vec<Vec<u8>>,
+    timeout: Option<Duration>,
+    command: Option<RESPMessage>,
+) -> Result<RESPMessage> {
+    context
+        .keyspace
+        .blocked
+        .block(&keys, &context.client.waiter);
+    context.client.blocked = Some(Blocked {
+        keys,
+        timeout,
+        reply: RESPMessage::NullArray,
+        command,
+    });
+    // Never sent, as the command runs again once the client is woken
+    Ok(RESPMessage::Null)
+}
+
+// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+// Reads the entries after each ID, where $ stands for the last ID of the stream
+pub fn xread(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (options, streams) = ReadOptions::parse(args, false)?;
+    let (keys, ids) = split_streams(streams, "xread", "$")?;
+
+    let keyspace = context.keyspace;
+    let mut shards = keyspace.lock_all(keys.iter().map(Vec::as_slice));
+    let mut after = Vec::with_capacity(keys.len());
+    let mut read = vec![];
+    for (key, id) in keys.iter().zip(ids) {
+        let stream = shards
+            .shard(key)
+            .get(key)
+            .map(Value::as_stream)
+            .transpose()?;
+        let id = match id.pack_bytes()? {
+            b"$" => stream.map_or(StreamId::MIN, |stream| stream.last_id),
+            b">" => {
+                return Err(Error::msg(
+                    "The > ID can be specified only when calling XREADGROUP using the GROUP \
+                     <group> <consumer> option.",
+                ))
+            }
+            id => StreamId::parse(id, 0).ok_or_else(invalid_id)?,
+        };
+        after.push(id);
+        let Some(stream) = stream else {
+            continue;
+        };
+        let entries: Vec<_> = range(&stream.entries, Excluded(id), Unbounded)
+            .take(options.count)
+            .map(|(id, fields)| entry(*id, Some(fields)))
+            .collect();
+        if !entries.is_empty() {
+            read.push((key.clone(), entries));
+        }
+    }
+
+    if !read.is_empty() {
+        return Ok(read_reply(context, read));
+    }
+    let Some(timeout) = options.block else {
+        return Ok(RESPMessage::NullArray);
+    };
+    // Once woken, the client reads on from the IDs $ stood for when it blocked rather than
+    // from whatever the last IDs are by then
+    let mut command = vec![bulk(b"XREAD".to_vec())];
+    command.extend_from_slice(&args[..args.len() - after.len()]);
+    command.extend(after.into_iter().map(bulk_id));
+    block(context, keys, timeout, Some(RESPMessage::Array(command)))
+}
+
+// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds] [NOACK] STREAMS key
+// [key ...] id [id ...]
+// With > reads the entries no consumer of the group was given yet, which are then pending
+// for this one unless NOACK is given. With an ID reads the entries after it that are
+// already pending for the consumer, which never blocks.
+pub fn xreadgroup(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    if !args[0].pack_bytes()?.eq_ignore_ascii_case(b"group") {
+        return Err(syntax_error());
+    }
+    let (name, consumer) = (args[1].pack_bytes()?, args[2].pack_bytes()?);
+    let (options, streams) = ReadOptions::parse(&args[3..], true)?;
+    let (keys, ids) = split_streams(streams, "xreadgroup", ">")?;
+    // None for >
+    let ids = ids
+        .iter()
+        .map(|id| match id.pack_bytes()? {
+            b">" => Ok(None),
+            id => Ok(Some(StreamId::parse(id, 0).ok_or_else(invalid_id)?)),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let keyspace = context.keyspace;
+    let mut shards = keyspace.lock_all(keys.iter().map(Vec::as_slice));
+    // Every group has to exist before any of them is read from
+    for key in &keys {
+        let stream = shards
+            .shard(key)
+            .get(key)
+            .map(Value::as_stream)
+            .transpose()?;
+        if !stream.is_some_and(|stream| stream.groups.contains_key(name)) {
+            return Ok(RESPMessage::Error(format!(
+                "NOGROUP No such key '{}' or consumer group '{}' in XREADGROUP with GROUP option",
+                lossy(key),
+                lossy(name)
+            )));
+        }
+    }
+
+    let time = now();
+    let mut read = vec![];
+    for (key, id) in keys.iter().zip(&ids) {
+        let entries = shards
+            .shard(key)
+            .modify(key, |value| {
+                let stream = value.as_stream_mut()?;
+                let group = stream.groups.get_mut(name).expect("the group exists");
+                group.see(consumer);
+                let Some(after) = id else {
+                    let new: Vec<_> =
+                        range(&stream.entries, Excluded(group.last_delivered), Unbounded)
+                            .take(options.count)
+                            .collect();
+                    for (&id, _) in &new {
+                        group.last_delivered = id;
+                        if !options.noack {
+                            let pending = Pending {
+                                consumer: consumer.to_vec(),
+                                delivered_at: time,
+                                deliveries: 1,
+                            };
+                            group.pending.insert(id, pending);
+                        }
+                    }
+                    return Ok(new
+                        .into_iter()
+                        .map(|(id, fields)| entry(*id, Some(fields)))
+                        .collect());
+                };
+                let history = group
+                    .pending
+                    .range_mut((Excluded(*after), Unbounded))
+                    .filter(|(_, pending)| pending.consumer == consumer)
+                    .take(options.count)
+                    .map(|(id, pending)| {
+                        pending.delivered_at = time;
+                        pending.deliveries += 1;
+                        entry(*id, stream.entries.get(id))
+                    })
+                    .collect();
+                Ok::<Vec<_>, WrongType>(history)
+            })
+            .expect("the stream exists")?;
+        // The consumer's pending entries are replied with even when there are none
+        if id.is_some() || !entries.is_empty() {
+            read.push((key.clone(), entries));
+        }
+    }
+
+    if !read.is_empty() {
+        return Ok(read_reply(context, read));
+    }
+    match options.block {
+        Some(timeout) => block(context, keys, timeout, None),
+        None => Ok(RESPMessage::NullArray),
+    }
+}
+
+// What XGROUP was asked to do, and with which ID or consumer. None stands for $, the
+// last ID of the stream.
+enum GroupCommand<'a> {
+    Create(Option<StreamId>),
+    SetId(Option<StreamId>),
+    Destroy,
+    CreateConsumer(&'a [u8]),
+    DelConsumer(&'a [u8]),
+}
+
+// XGROUP CREATE key group id | $ [MKSTREAM] | XGROUP SETID key group id | $ |
+// XGROUP DESTROY key group | XGROUP CREATECONSUMER key group consumer |
+// XGROUP DELCONSUMER key group consumer
+pub fn xgroup(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let subcommand = args[0].pack_string()?.to_ascii_lowercase();
+    let arity = match subcommand.as_ref() {
+        "create" => 4..=5,
+        "setid" | "createconsumer" | "delconsumer" => 4..=4,
+        "destroy" => 3..=3,
+        _ => {
+            return Err(Error::msg(format!(
+                "unknown subcommand '{}'. Try XGROUP HELP.",
+                subcommand
+            )))
+        }
+    };
+    if !arity.contains(&args.len()) {
+        return Ok(wrong_arity(&format!("xgroup|{}", subcommand)));
+    }
+    let (key, name) = (args[1].pack_bytes()?, args[2].pack_bytes()?);
+    let id = |arg: &RESPMessage| match arg.pack_bytes()? {
+        b"$" => Ok(None),
+        _ => parse_id(arg, 0).map(Some),
+    };
+    let command = match subcommand.as_ref() {
+        "create" => GroupCommand::Create(id(&args[3])?),
+        "setid" => GroupCommand::SetId(id(&args[3])?),
+        "destroy" => GroupCommand::Destroy,
+        "createconsumer" => GroupCommand::CreateConsumer(args[3].pack_bytes()?),
+        _ => GroupCommand::DelConsumer(args[3].pack_bytes()?),
+    };
+    let mkstream = match args.get(4) {
+        Some(option) if option.pack_bytes()?.eq_ignore_ascii_case(b"mkstream") => true,
+        Some(_) => return Err(syntax_error()),
+        None => false,
+    };
+
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        if !mkstream {
+            return Err(Error::msg(
+                "The XGROUP subcommand requires the key to exist. Note that for CREATE you may \
+                 want to use the MKSTREAM option to create an empty stream automatically.",
+            ));
+        }
+        cache.set_expiring_at(key.to_vec(), Value::Stream(Stream::default()), None);
+    }
+    let reply = cache
+        .modify(key, |value| {
+            let stream = value.as_stream_mut()?;
+            let last_id = stream.last_id;
+            if let GroupCommand::Create(id) = command {
+                if stream.groups.contains_key(name) {
+                    return Ok(RESPMessage::Error(
+                        "BUSYGROUP Consumer Group name already exists".to_string(),
+                    ));
+                }
+                stream
+                    .groups
+                    .insert(name.to_vec(), Group::new(id.unwrap_or(last_id)));
+                return Ok(RESPMessage::SimpleString("OK".to_string()));
+            }
+            if let GroupCommand::Destroy = command {
+                let destroyed = stream.groups.remove(name).is_some();
+                return Ok(RESPMessage::Integer(destroyed.into()));
+            }
+            let Some(group) = stream.groups.get_mut(name) else {
+                return Ok(RESPMessage::Error(format!(
+                    "NOGROUP No such consumer group '{}' for key name '{}'",
+                    lossy(name),
+                    lossy(key)
+                )));
+            };
+            Ok::<_, WrongType>(match command {
+                GroupCommand::SetId(id) => {
+                    group.last_delivered = id.unwrap_or(last_id);
+                    RESPMessage::SimpleString("OK".to_string())
+                }
+                GroupCommand::CreateConsumer(consumer) => {
+                    let created = !group.consumers.contains_key(consumer);
+                    if created {
+                        group.see(consumer);
+                    }
+                    RESPMessage::Integer(created.into())
+                }
+                // Replies with how many entries were pending for the consumer
+                GroupCommand::DelConsumer(consumer) => {
+                    group.remove_consumer(consumer);
+                    let before = group.pending.len();
+                    group
+                        .pending
+                        .retain(|_, pending| pending.consumer != consumer);
+                    RESPMessage::Integer((before - group.pending.len()) as i64)
+                }
+                GroupCommand::Create(_) | GroupCommand::Destroy => unreachable!(),
+            })
+        })
+        .expect("the stream exists")?;
+    Ok(reply)
+}
+
+// XACK key group id [id ...]
+pub fn xack(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, name) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let ids = args[2..]
+        .iter()
+        .map(|id| parse_id(id, 0))
+        .collect::<Result<Vec<_>>>()?;
+    let acknowledged = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let group = value.as_stream_mut()?.groups.get_mut(name);
+            Ok::<_, WrongType>(group.map_or(0, |group| {
+                ids.iter()
+                    .filter(|id| group.pending.remove(id).is_some())
+                    .count()
+            }))
+        })
+        .transpose()?
+        .unwrap_or(0);
+    Ok(RESPMessage::Integer(acknowledged as i64))
+}
+
+fn no_such_group(key: &[u8], name: &[u8]) -> RESPMessage {
+    RESPMessage::Error(format!(
+        "NOGROUP No such key '{}' or consumer group '{}'",
+        lossy(key),
+        lossy(name)
+    ))
+}
+
+// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+// Without a range replies with a summary: how many entries are pending, the lowest and
+// highest of their IDs and how many are pending for each consumer
+pub fn xpending(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, name) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let mut rest = &args[2..];
+    let mut min_idle = None;
+    if let [option, idle, after @ ..] = rest {
+        if option.pack_bytes()?.eq_ignore_ascii_case(b"idle") {
+            min_idle = Some(parse_integer(idle)?.max(0) as u128);
+            rest = after;
+        }
+    }
+    let extended = match rest {
+        [] if min_idle.is_none() => None,
+        [start, end, count, consumer @ ..] if consumer.len() <= 1 => Some((
+            parse_bound(start, 0)?,
+            parse_bound(end, u64::MAX)?,
+            parse_integer(count)?.max(0) as usize,
+            consumer.first().map(RESPMessage::pack_bytes).transpose()?,
+        )),
+        _ => return Err(syntax_error()),
+    };
+
+    let mut cache = context.keyspace.lock(key);
+    let stream = cache.get(key).map(Value::as_stream).transpose()?;
+    let Some(group) = stream.and_then(|stream| stream.groups.get(name)) else {
+        return Ok(no_such_group(key, name));
+    };
+    let Some((start, end, count, consumer)) = extended else {
+        let (Some((first, _)), Some((last, _))) = (
+            group.pending.first_key_value(),
+            group.pending.last_key_value(),
+        ) else {
+            return Ok(RESPMessage::Array(vec![
+                RESPMessage::Integer(0),
+                RESPMessage::Null,
+                RESPMessage::Null,
+                RESPMessage::NullArray,
+            ]));
+        };
+        let mut consumers = BTreeMap::<&[u8], usize>::new();
+        for pending in group.pending.values() {
+            *consumers.entry(&pending.consumer).or_default() += 1;
+        }
+        return Ok(RESPMessage::Array(vec![
+            RESPMessage::Integer(group.pending.len() as i64),
+            bulk_id(*first),
+            bulk_id(*last),
+            RESPMessage::Array(
+                consumers
+                    .into_iter()
+                    .map(|(consumer, count)| {
+                        RESPMessage::Array(vec![
+                            bulk(consumer.to_vec()),
+                            bulk(count.to_string().into_bytes()),
+                        ])
+                    })
+                    .collect(),
+            ),
+        ]));
+    };
+
+    let time = now();
+    let min_idle = min_idle.unwrap_or(0);
+    let pending = range(&group.pending, start, end)
+        .filter(|(_, pending)| consumer.is_none_or(|consumer| pending.consumer == consumer))
+        .filter(|(_, pending)| time.saturating_sub(pending.delivered_at) >= min_idle)
+        .take(count)
+        .map(|(id, pending)| {
+            RESPMessage::Array(vec![
+                bulk_id(*id),
+                bulk(pending.consumer.clone()),
+                RESPMessage::Integer(time.saturating_sub(pending.delivered_at) as i64),
+                RESPMessage::Integer(pending.deliveries as i64),
+            ])
+        })
+        .collect();
+    Ok(RESPMessage::Array(pending))
+}
+
+// Hands a pending entry that has been idle for at least min_idle milliseconds over to the
+// consumer, counting it as delivered again unless justid is set. Entries trimmed from the
+// stream are no longer pending, and None is returned for them like for entries that
+// weren't idle long enough.
+fn claim(
+    stream: &mut Stream,
+    name: &[u8],
+    id: StreamId,
+    consumer: &[u8],
+    min_idle: u128,
+    justid: bool,
+) -> Option<RESPMessage> {
+    let group = stream.groups.get_mut(name).expect("the group exists");
+    let Some(fields) = stream.entries.get(&id) else {
+        group.pending.remove(&id);
+        return None;
+    };
+    let pending = group.pending.get_mut(&id)?;
+    let time = now();
+    if time.saturating_sub(pending.delivered_at) < min_idle {
+        return None;
+    }
+    pending.consumer = consumer.to_vec();
+    pending.delivered_at = time;
+    if !justid {
+        pending.deliveries += 1;
+    }
+    Some(if justid {
+        bulk_id(id)
+    } else {
+        entry(id, Some(fields))
+    })
+}
+
+// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms] [TIME unix-time-ms]
+// [RETRYCOUNT count] [FORCE] [JUSTID] [LASTID lastid]
+// FORCE claims entries that are in the stream but not pending, and LASTID moves the
+// group's last delivered ID forward
+pub fn xclaim(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, name, consumer) = (
+        args[0].pack_bytes()?,
+        args[1].pack_bytes()?,
+        args[2].pack_bytes()?,
+    );
+    let min_idle = parse_integer(&args[3])
+        .map_err(|_| Error::msg("Invalid min-idle-time argument for XCLAIM"))?
+        .max(0) as u128;
+    // The IDs go on until the first argument that isn't one
+    let ids: Vec<StreamId> = args[4..]
+        .iter()
+        .map_while(|arg| arg.pack_bytes().ok().and_then(|id| StreamId::parse(id, 0)))
+        .collect();
+    if ids.is_empty() {
+        return Err(invalid_id());
+    }
+    let (mut delivered_at, mut retrycount, mut force, mut justid, mut last_id) =
+        (None, None, false, false, None);
+    let mut rest = &args[4 + ids.len()..];
+    while let [option, after @ ..] = rest {
+        let option = option.pack_bytes()?.to_ascii_lowercase();
+        rest = after;
+        match option.as_slice() {
+            b"force" => force = true,
+            b"justid" => justid = true,
+            b"idle" | b"time" | b"retrycount" | b"lastid" => {
+                let [value, after @ ..] = rest else {
+                    return Err(syntax_error());
+                };
+                rest = after;
+                match option.as_slice() {
+                    b"idle" => {
+                        let idle = parse_integer(value)?.max(0) as u128;
+                        delivered_at = Some(now().saturating_sub(idle));
+                    }
+                    b"time" => delivered_at = Some(parse_integer(value)?.max(0) as u128),
+                    b"retrycount" => retrycount = Some(parse_integer(value)?.max(0) as u64),
+                    _ => last_id = Some(parse_id(value, 0)?),
+                }
+            }
+            _ => {
+                return Err(Error::msg(format!(
+                    "Unrecognized XCLAIM option '{}'",
+                    lossy(&option)
+                )))
+            }
+        }
+    }
+
+    let claimed = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let stream = value.as_stream_mut()?;
+            let Some(group) = stream.groups.get_mut(name) else {
+                return Ok(None);
+            };
+            group.see(consumer);
+            if let Some(last_id) = last_id {
+                group.last_delivered = group.last_delivered.max(last_id);
+            }
+            let mut claimed = vec![];
+            for id in ids {
+                if force && stream.entries.contains_key(&id) {
+                    let group = stream.groups.get_mut(name).expect("the group exists");
+                    group.pending.entry(id).or_insert_with(|| Pending {
+                        consumer: consumer.to_vec(),
+                        delivered_at: now(),
+                        deliveries: 0,
+                    });
+                }
+                let Some(reply) = claim(stream, name, id, consumer, min_idle, justid) else {
+                    continue;
+                };
+                let group = stream.groups.get_mut(name).expect("the group exists");
+                let pending = group.pending.get_mut(&id).expect("the entry was claimed");
+                if let Some(delivered_at) = delivered_at {
+                    pending.delivered_at = delivered_at;
+                }
+                if let Some(retrycount) = retrycount {
+                    pending.deliveries = retrycount;
+                }
+                claimed.push(reply);
+            }
+            Ok::<_, WrongType>(Some(claimed))
+        })
+        .transpose()?
+        .flatten();
+    Ok(claimed.map_or_else(|| no_such_group(key, name), RESPMessage::Array))
+}
+
+// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID]
+// Claims up to count of the pending entries from start on, and replies with the ID to
+// carry on from, 0-0 once every entry was looked at, the claimed entries and the IDs of
+// the entries that were no longer in the stream
+pub fn xautoclaim(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, name, consumer) = (
+        args[0].pack_bytes()?,
+        args[1].pack_bytes()?,
+        args[2].pack_bytes()?,
+    );
+    let min_idle = parse_integer(&args[3])
+        .map_err(|_| Error::msg("Invalid min-idle-time argument for XAUTOCLAIM"))?
+        .max(0) as u128;
+    let start = parse_bound(&args[4], 0)?;
+    let (mut count, mut justid) = (100, false);
+    let mut rest = &args[5..];
+    while let [option, after @ ..] = rest {
+        match option.pack_bytes()?.to_ascii_lowercase().as_slice() {
+            b"justid" => {
+                justid = true;
+                rest = after;
+            }
+            b"count" => {
+                let value = after.first().ok_or_else(syntax_error)?;
+                count = parse_integer(value)?;
+                if !(1..=i64::MAX / 10).contains(&count) {
+                    return Err(Error::msg("COUNT must be > 0"));
+                }
+                rest = &after[1..];
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    let count = count as usize;
+
+    let reply = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let stream = value.as_stream_mut()?;
+            let Some(group) = stream.groups.get_mut(name) else {
+                return Ok(None);
+            };
+            group.see(consumer);
+            // Like Redis, no more than ten times count entries are looked at, so a long
+            // list of entries that weren't idle long enough doesn't hold up the server
+            let attempts = count * 10;
+            let ids: Vec<StreamId> = range(&group.pending, start, Included(StreamId::MAX))
+                .map(|(id, _)| *id)
+                .take(attempts + 1)
+                .collect();
+            let (mut claimed, mut deleted) = (vec![], vec![]);
+            let mut cursor = StreamId::MIN;
+            for (looked_at, id) in ids.into_iter().enumerate() {
+                if claimed.len() + deleted.len() == count || looked_at == attempts {
+                    cursor = id;
+                    break;
+                }
+                if !stream.entries.contains_key(&id) {
+                    deleted.push(bulk_id(id));
+                }
+                if let Some(reply) = claim(stream, name, id, consumer, min_idle, justid) {
+                    claimed.push(reply);
+                }
+            }
+            Ok::<_, WrongType>(Some(RESPMessage::Array(vec![
+                bulk_id(cursor),
+                RESPMessage::Array(claimed),
+                RESPMessage::Array(deleted),
+            ])))
+        })
+        .transpose()?
+        .flatten();
+    Ok(reply.unwrap_or_else(|| no_such_group(key, name)))
+}
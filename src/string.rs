@@ -0,0 +1,327 @@
+use crate::{
+    cache::now,
+    command::{expire_time, out_of_memory, parse_integer, syntax_error, wrong_arity, Context},
+    resp::RESPMessage,
+    value::Value,
+};
+use anyhow::{Error, Result};
+
+fn bulk_or_null(value: Option<Vec<u8>>) -> RESPMessage {
+    value.map_or(RESPMessage::Null, |value| {
+        RESPMessage::BulkString(value.into())
+    })
+}
+
+// Strings can't grow past proto-max-bulk-len, or a client couldn't be sent them back
+fn max_bulk_len(context: &Context) -> usize {
+    context.config.read().unwrap().limits.max_bulk_len
+}
+
+fn check_length(len: usize, max: usize) -> Result<()> {
+    if len > max {
+        return Err(Error::msg(
+            "string exceeds maximum allowed size (proto-max-bulk-len)",
+        ));
+    }
+    Ok(())
+}
+
+// APPEND key value
+// A missing key is created holding just the value
+pub fn append(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, value) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let max = max_bulk_len(context);
+
+    let mut cache = context.keyspace.lock(key);
+    let appended = cache.modify(key, |existing| {
+        let string = existing.as_string_mut()?;
+        check_length(string.len() + value.len(), max)?;
+        string.extend_from_slice(value);
+        Ok::<_, Error>(string.len())
+    });
+    let len = match appended {
+        Some(len) => len?,
+        None => {
+            cache.set_expiring_at(key.to_vec(), Value::String(value.to_vec()), None);
+            value.len()
+        }
+    };
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// STRLEN key
+pub fn strlen(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let len = match context.keyspace.lock(key).get(key) {
+        Some(value) => value.as_string()?.len(),
+        None => 0,
+    };
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// GETRANGE key start end
+// Negative offsets count back from the end of the string. Unlike LRANGE, an end that is
+// still negative after that is clamped to the first byte rather than giving nothing.
+pub fn getrange(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (start, end) = (parse_integer(&args[1])?, parse_integer(&args[2])?);
+
+    let mut cache = context.keyspace.lock(key);
+    let string: &[u8] = match cache.get(key) {
+        Some(value) => value.as_string()?,
+        None => &[],
+    };
+    let len = string.len() as i64;
+    if len == 0 || (start < 0 && end < 0 && start > end) {
+        return Ok(RESPMessage::BulkString(vec![].into()));
+    }
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 {
+        (len + end).max(0)
+    } else {
+        end.min(len - 1)
+    };
+    if start > end {
+        return Ok(RESPMessage::BulkString(vec![].into()));
+    }
+    Ok(RESPMessage::BulkString(
+        string[start as usize..=end as usize].to_vec().into(),
+    ))
+}
+
+// SETRANGE key offset value
+// The string is padded with zero bytes to reach the offset
+pub fn setrange(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let offset = parse_integer(&args[1])?;
+    let value = args[2].pack_bytes()?;
+    if offset < 0 {
+        return Err(Error::msg("offset is out of range"));
+    }
+    let (offset, max) = (offset as usize, max_bulk_len(context));
+
+    let mut cache = context.keyspace.lock(key);
+    let written = cache.modify(key, |existing| {
+        let string = existing.as_string_mut()?;
+        if value.is_empty() {
+            return Ok(string.len());
+        }
+        let end = offset.saturating_add(value.len());
+        check_length(end, max)?;
+        if string.len() < end {
+            string.resize(end, 0);
+        }
+        string[offset..end].copy_from_slice(value);
+        Ok::<_, Error>(string.len())
+    });
+    let len = match written {
+        Some(len) => len?,
+        // Writing nothing doesn't create the key
+        None if value.is_empty() => 0,
+        None => {
+            let end = offset.saturating_add(value.len());
+            check_length(end, max)?;
+            let mut string = vec![0; offset];
+            string.extend_from_slice(value);
+            cache.set_expiring_at(key.to_vec(), Value::String(string), None);
+            end
+        }
+    };
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// GETSET key value
+// Like SET, this drops any expiry the key had
+pub fn getset(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let value = Value::String(args[1].pack_bytes()?.to_vec());
+    if !context.keyspace.make_room_for([(key, &value)]) {
+        return Ok(out_of_memory());
+    }
+
+    let mut cache = context.keyspace.lock(key);
+    let old = cache
+        .get(key)
+        .map(|old| old.as_string().cloned())
+        .transpose()?;
+    cache.set_expiring_at(key.to_vec(), value, None);
+    Ok(bulk_or_null(old))
+}
+
+// GETDEL key
+pub fn getdel(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let mut cache = context.keyspace.lock(key);
+    let old = cache
+        .get(key)
+        .map(|old| old.as_string().cloned())
+        .transpose()?;
+    if old.is_some() {
+        cache.delete(key);
+    }
+    Ok(bulk_or_null(old))
+}
+
+// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+//     PXAT unix-time-milliseconds | PERSIST]
+pub fn getex(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+
+    // None leaves the expiry alone, Some(None) removes it
+    let mut expiry = None;
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let option = option
+            .pack_string()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match option.as_ref() {
+            "persist" if expiry.is_none() => expiry = Some(None),
+            "ex" | "px" | "exat" | "pxat" if expiry.is_none() => {
+                let time = options.next().ok_or_else(syntax_error)?;
+                expiry = Some(Some(expire_time(&option, time, "getex")?));
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let mut cache = context.keyspace.lock(key);
+    let Some(value) = cache.get(key) else {
+        return Ok(RESPMessage::Null);
+    };
+    let value = value.as_string()?.clone();
+    match expiry {
+        Some(Some(expires_at)) if expires_at <= now() => {
+            cache.delete(key);
+        }
+        Some(expires_at) => {
+            cache.set_expires_at(key, expires_at);
+        }
+        None => {}
+    }
+    Ok(RESPMessage::BulkString(value.into()))
+}
+
+// MGET key [key ...]
+// Keys that are missing or don't hold a string come back as nil
+pub fn mget(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let keys = args
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut shards = context.keyspace.lock_all(keys.iter().copied());
+    let values = keys
+        .iter()
+        .map(|key| match shards.shard(key).get(key) {
+            Some(Value::String(value)) => RESPMessage::BulkString(value.clone().into()),
+            _ => RESPMessage::Null,
+        })
+        .collect();
+    Ok(RESPMessage::Array(values))
+}
+
+// Parses MSET and MSETNX's key value pairs, which have already been checked to pair up
+fn pairs(args: &[RESPMessage]) -> Result<Vec<(&[u8], Value)>> {
+    args.chunks(2)
+        .map(|pair| {
+            let value = Value::String(pair[1].pack_bytes()?.to_vec());
+            Ok((pair[0].pack_bytes()?, value))
+        })
+        .collect()
+}
+
+// MSET key value [key value ...]
+// Every shard involved is locked up front, so no one sees some of the keys set and not others
+pub fn mset(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    if !args.len().is_multiple_of(2) {
+        return Ok(wrong_arity("mset"));
+    }
+    let pairs = pairs(args)?;
+    if !context
+        .keyspace
+        .make_room_for(pairs.iter().map(|(key, value)| (*key, value)))
+    {
+        return Ok(out_of_memory());
+    }
+
+    let mut shards = context.keyspace.lock_all(pairs.iter().map(|(key, _)| *key));
+    for (key, value) in pairs {
+        shards.shard(key).set_expiring_at(key.to_vec(), value, None);
+    }
+    Ok(RESPMessage::SimpleString("OK".to_string()))
+}
+
+// MSETNX key value [key value ...]
+// Sets nothing at all if any of the keys exists
+pub fn msetnx(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    if !args.len().is_multiple_of(2) {
+        return Ok(wrong_arity("msetnx"));
+    }
+    let pairs = pairs(args)?;
+    if !context
+        .keyspace
+        .make_room_for(pairs.iter().map(|(key, value)| (*key, value)))
+    {
+        return Ok(out_of_memory());
+    }
+
+    let mut shards = context.keyspace.lock_all(pairs.iter().map(|(key, _)| *key));
+    if pairs.iter().any(|(key, _)| shards.shard(key).contains(key)) {
+        return Ok(RESPMessage::Integer(0));
+    }
+    for (key, value) in pairs {
+        shards.shard(key).set_expiring_at(key.to_vec(), value, None);
+    }
+    Ok(RESPMessage::Integer(1))
+}
+
+// SETNX key value
+pub fn setnx(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let value = Value::String(args[1].pack_bytes()?.to_vec());
+    if !context.keyspace.make_room_for([(key, &value)]) {
+        return Ok(out_of_memory());
+    }
+
+    let mut cache = context.keyspace.lock(key);
+    if cache.contains(key) {
+        return Ok(RESPMessage::Integer(0));
+    }
+    cache.set_expiring_at(key.to_vec(), value, None);
+    Ok(RESPMessage::Integer(1))
+}
+
+// SETEX key seconds value
+pub fn setex(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    set_expiring(context, args, "setex", "ex")
+}
+
+// PSETEX key milliseconds value
+pub fn psetex(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    set_expiring(context, args, "psetex", "px")
+}
+
+fn set_expiring(
+    context: &mut Context,
+    args: &[RESPMessage],
+    name: &str,
+    option: &str,
+) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let expires_at = expire_time(option, &args[1], name)?;
+    let value = Value::String(args[2].pack_bytes()?.to_vec());
+    if !context.keyspace.make_room_for([(key, &value)]) {
+        return Ok(out_of_memory());
+    }
+
+    context
+        .keyspace
+        .lock(key)
+        .set_expiring_at(key.to_vec(), value, Some(expires_at));
+    Ok(RESPMessage::SimpleString("OK".to_string()))
+}
@@ -0,0 +1,253 @@
+use crate::{
+    config::Config, hash::Hash, list::List, resp::format_double, set::Set, stream::Stream,
+    zset::SortedSet,
+};
+use std::fmt;
+
+// What a key holds
+#[derive(Debug, Clone)]
+pub enum Value {
+    String(Vec<u8>),
+    List(List),
+    Hash(Hash),
+    Set(Set),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+// How many elements the compact encoding of a collection may hold and how long each of them
+// may be, past which it is converted to its general encoding
+#[derive(Clone, Copy)]
+pub struct ListpackLimits {
+    pub entries: usize,
+    pub value: usize,
+}
+
+// Returned when a command is used on a key holding a different type of value than it
+// works on, which the client sees as a WRONGTYPE error rather than an ERR one
+#[derive(Debug)]
+pub struct WrongType;
+
+impl fmt::Display for WrongType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "WRONGTYPE Operation against a key holding the wrong kind of value"
+        )
+    }
+}
+
+impl std::error::Error for WrongType {}
+
+impl Value {
+    // The name TYPE replies with
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::ZSet(_) => "zset",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    // The name OBJECT ENCODING replies with
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Self::String(string) if string.len() <= 20 && parse_i64(string).is_some() => "int",
+            // Redis keeps strings up to 44 bytes in the same allocation as their object
+            Self::String(string) if string.len() <= 44 => "embstr",
+            Self::String(_) => "raw",
+            Self::List(_) => "quicklist",
+            Self::Hash(hash) => hash.encoding(),
+            Self::Set(set) => set.encoding(),
+            Self::ZSet(_) => "skiplist",
+            Self::Stream(_) => "stream",
+        }
+    }
+
+    // Roughly how many bytes the value takes up, counting a pointer and length for every
+    // element of a collection. Collections keep their own count up to date as they change,
+    // so this is cheap enough to call on every write.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::String(string) => string.len(),
+            Self::List(list) => list.size(),
+            Self::Hash(hash) => hash.size(),
+            Self::Set(set) => set.size(),
+            Self::ZSet(zset) => zset.size(),
+            Self::Stream(stream) => stream.size(),
+        }
+    }
+
+    // Whether the value is a collection with nothing left in it. Like Redis, such keys are
+    // removed rather than kept around empty.
+    pub fn is_empty_collection(&self) -> bool {
+        match self {
+            Self::String(_) => false,
+            Self::List(list) => list.is_empty(),
+            Self::Hash(hash) => hash.len() == 0,
+            Self::Set(set) => set.len() == 0,
+            Self::ZSet(zset) => zset.len() == 0,
+            // Streams stay around once their entries are trimmed, along with their groups
+            Self::Stream(_) => false,
+        }
+    }
+
+    // The value as its type name and its elements, the way SYNC sends it: a string is a
+    // single element, a list its elements in order, a hash each field followed by its value
+    // a set its members, a sorted set each member followed by its score and a stream what
+    // Stream::dump returns
+    pub fn dump(&self) -> (&'static str, Vec<Vec<u8>>) {
+        let elements = match self {
+            Self::String(string) => vec![string.clone()],
+            Self::List(list) => list.iter().cloned().collect(),
+            Self::Hash(hash) => hash
+                .iter()
+                .flat_map(|(field, value)| [field.clone(), value.clone()])
+                .collect(),
+            Self::Set(set) => set.members(),
+            Self::ZSet(zset) => zset
+                .iter()
+                .flat_map(|(member, score)| [member.clone(), format_double(score).into_bytes()])
+                .collect(),
+            Self::Stream(stream) => stream.dump(),
+        };
+        (self.type_name(), elements)
+    }
+
+    // Builds the value back from what dump returned. None if the type is unknown or the
+    // elements don't fit it. The config decides how the value is encoded.
+    pub fn restore(type_name: &[u8], elements: Vec<Vec<u8>>, config: &Config) -> Option<Self> {
+        match type_name {
+            b"string" => {
+                let [string] = <[Vec<u8>; 1]>::try_from(elements).ok()?;
+                Some(Self::String(string))
+            }
+            b"list" => Some(Self::List(elements.into())),
+            b"hash" if elements.len().is_multiple_of(2) => {
+                let mut hash = Hash::default();
+                let mut elements = elements.into_iter();
+                while let (Some(field), Some(value)) = (elements.next(), elements.next()) {
+                    hash.insert(field, value, config.hash_max_listpack);
+                }
+                Some(Self::Hash(hash))
+            }
+            b"set" => Some(Self::Set(Set::from_members(
+                elements,
+                config.set_max_intset_entries,
+            ))),
+            b"zset" if elements.len().is_multiple_of(2) => {
+                let mut zset = SortedSet::default();
+                let mut elements = elements.into_iter();
+                while let (Some(member), Some(score)) = (elements.next(), elements.next()) {
+                    zset.insert(member, parse_float(&score)?);
+                }
+                Some(Self::ZSet(zset))
+            }
+            b"stream" => Stream::restore(elements).map(Self::Stream),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Result<&Vec<u8>, WrongType> {
+        match self {
+            Self::String(string) => Ok(string),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_string_mut(&mut self) -> Result<&mut Vec<u8>, WrongType> {
+        match self {
+            Self::String(string) => Ok(string),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_list(&self) -> Result<&List, WrongType> {
+        match self {
+            Self::List(list) => Ok(list),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_list_mut(&mut self) -> Result<&mut List, WrongType> {
+        match self {
+            Self::List(list) => Ok(list),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_hash(&self) -> Result<&Hash, WrongType> {
+        match self {
+            Self::Hash(hash) => Ok(hash),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_hash_mut(&mut self) -> Result<&mut Hash, WrongType> {
+        match self {
+            Self::Hash(hash) => Ok(hash),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_set(&self) -> Result<&Set, WrongType> {
+        match self {
+            Self::Set(set) => Ok(set),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_set_mut(&mut self) -> Result<&mut Set, WrongType> {
+        match self {
+            Self::Set(set) => Ok(set),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_zset(&self) -> Result<&SortedSet, WrongType> {
+        match self {
+            Self::ZSet(zset) => Ok(zset),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_zset_mut(&mut self) -> Result<&mut SortedSet, WrongType> {
+        match self {
+            Self::ZSet(zset) => Ok(zset),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_stream(&self) -> Result<&Stream, WrongType> {
+        match self {
+            Self::Stream(stream) => Ok(stream),
+            _ => Err(WrongType),
+        }
+    }
+
+    pub fn as_stream_mut(&mut self) -> Result<&mut Stream, WrongType> {
+        match self {
+            Self::Stream(stream) => Ok(stream),
+            _ => Err(WrongType),
+        }
+    }
+}
+
+// Parses bytes that are exactly how an integer is written, so without a sign in front of
+// positive numbers, leading zeros or spaces, the way Redis decides a string is an integer
+pub fn parse_i64(bytes: &[u8]) -> Option<i64> {
+    let n: i64 = std::str::from_utf8(bytes).ok()?.parse().ok()?;
+    (n.to_string().as_bytes() == bytes).then_some(n)
+}
+
+// Parses a float, including inf and -inf, but not NaN
+pub fn parse_float(bytes: &[u8]) -> Option<f64> {
+    std::str::from_utf8(bytes)
+        .ok()?
+        .parse()
+        .ok()
+        .filter(|float: &f64| !float.is_nan())
+}
@@ -0,0 +1,977 @@
+use crate::{
+    command::{parse_integer, syntax_error, wrong_arity, Context},
+    list,
+    resp::{RESPMessage, RESPVersion},
+    value::{parse_float, Value, WrongType},
+};
+use anyhow::{Error, Result};
+use rand::Rng;
+use slab::Slab;
+use std::{cmp::Ordering, collections::HashMap, mem::size_of, ops::Range};
+
+// A score, ordered by f64::total_cmp. NaN is never stored, and -0 is stored as 0 so the two
+// compare equal like they do in Redis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Score(f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+// Members ordered by score, and members with the same score by their bytes. Like Redis, they
+// are kept in a skiplist whose links also count how many members they skip, so a member's
+// rank and the member at a rank are both found in O(log n) by walking down its levels, and
+// a map from member to score answers lookups.
+#[derive(Debug, Clone, Default)]
+pub struct SortedSet {
+    list: SkipList,
+    scores: HashMap<Vec<u8>, f64>,
+    // How many bytes the members take up, kept up to date as they are added and removed
+    size: usize,
+}
+
+// Every member is kept twice, in the skiplist and the map, each with its score
+fn member_size(member: &[u8]) -> usize {
+    2 * (member.len() + size_of::<Vec<u8>>() + size_of::<f64>())
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    // Adds the member or moves it to its new score
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) {
+        let score = score + 0.0;
+        match self.scores.insert(member.clone(), score) {
+            Some(previous) => {
+                self.list.remove(Score(previous), &member);
+            }
+            None => self.size += member_size(&member),
+        }
+        self.list.insert(Score(score), member);
+    }
+
+    // Returns whether the member was there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.size -= member_size(member);
+                self.list.remove(Score(score), member)
+            }
+            None => false,
+        }
+    }
+
+    // The member's position counting from the lowest score
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.list.rank(Score(score), member))
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        self.range(0..self.len())
+    }
+
+    // The members whose ranks are in range, counting from the lowest score
+    pub fn range(&self, range: Range<usize>) -> Iter<'_> {
+        let range = range.start..range.end.min(self.len());
+        if range.is_empty() {
+            return Iter {
+                list: &self.list,
+                front: None,
+                back: None,
+                remaining: 0,
+            };
+        }
+        Iter {
+            list: &self.list,
+            front: self.list.at(range.start),
+            back: self.list.at(range.end - 1),
+            remaining: range.len(),
+        }
+    }
+
+    // How many members from the lowest score on f holds for, which must be all of them up to
+    // some member and none after it
+    fn count_while(&self, f: impl Fn(&[u8], f64) -> bool) -> usize {
+        self.list.count_while(f)
+    }
+
+    fn pop(&mut self, end: End) -> Option<(Vec<u8>, f64)> {
+        let node = match end {
+            End::Min => self.list.nodes[HEAD].levels[0].forward?,
+            End::Max => self.list.tail?,
+        };
+        let (score, member) = (
+            self.list.nodes[node].score,
+            self.list.nodes[node].member.clone(),
+        );
+        self.remove(&member);
+        Some((member, score.0))
+    }
+}
+
+// Redis' ZSKIPLIST_MAXLEVEL and ZSKIPLIST_P: a node has one more level than the last with a
+// chance of 1 in 4, up to 32 of them
+const MAX_LEVEL: usize = 32;
+
+// Where the head of the skiplist is in its slab. It holds no member but has every level.
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    // How many members the link to forward skips over, counting forward itself. With no
+    // forward, it's how many members come after on the lowest level.
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    score: Score,
+    member: Vec<u8>,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+// The nodes live in a slab and point to each other by where they are in it
+#[derive(Debug, Clone)]
+struct SkipList {
+    nodes: Slab<Node>,
+    tail: Option<usize>,
+    // How many levels the tallest node has
+    level: usize,
+    len: usize,
+}
+
+impl Default for SkipList {
+    fn default() -> Self {
+        let mut nodes = Slab::new();
+        nodes.insert(Node {
+            score: Score(0.0),
+            member: vec![],
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0
+                };
+                MAX_LEVEL
+            ],
+        });
+        Self {
+            nodes,
+            tail: None,
+            level: 1,
+            len: 0,
+        }
+    }
+}
+
+impl SkipList {
+    fn key(&self, node: usize) -> (Score, &[u8]) {
+        (self.nodes[node].score, &self.nodes[node].member)
+    }
+
+    // The last node on each level before where (score, member) goes, along with the rank of
+    // each of those nodes counting the head as 0
+    fn before(&self, score: Score, member: &[u8]) -> ([usize; MAX_LEVEL], [usize; MAX_LEVEL]) {
+        let (mut update, mut rank) = ([HEAD; MAX_LEVEL], [0; MAX_LEVEL]);
+        let mut node = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = rank.get(i + 1).copied().unwrap_or(0);
+            while let Some(next) = self.nodes[node].levels[i].forward {
+                if self.key(next) >= (score, member) {
+                    break;
+                }
+                rank[i] += self.nodes[node].levels[i].span;
+                node = next;
+            }
+            update[i] = node;
+        }
+        (update, rank)
+    }
+
+    // Adds a member that isn't in the list yet
+    fn insert(&mut self, score: Score, member: Vec<u8>) {
+        let (mut update, mut rank) = self.before(score, &member);
+        let mut rng = rand::thread_rng();
+        let mut level = 1;
+        while level < MAX_LEVEL && rng.gen_ratio(1, 4) {
+            level += 1;
+        }
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.len;
+            }
+            self.level = level;
+        }
+
+        let node = self.nodes.insert(Node {
+            score,
+            member,
+            backward: (update[0] != HEAD).then_some(update[0]),
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0
+                };
+                level
+            ],
+        });
+        for i in 0..level {
+            let previous = self.nodes[update[i]].levels[i];
+            self.nodes[node].levels[i] = Level {
+                forward: previous.forward,
+                span: previous.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Level {
+                forward: Some(node),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        for (i, previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*previous].levels[i].span += 1;
+        }
+        match self.nodes[node].levels[0].forward {
+            Some(next) => self.nodes[next].backward = Some(node),
+            None => self.tail = Some(node),
+        }
+        self.len += 1;
+    }
+
+    // Returns whether the member was there
+    fn remove(&mut self, score: Score, member: &[u8]) -> bool {
+        let (update, _) = self.before(score, member);
+        let Some(node) = self.nodes[update[0]].levels[0].forward else {
+            return false;
+        };
+        if self.key(node) != (score, member) {
+            return false;
+        }
+
+        for (i, previous) in update.iter().enumerate().take(self.level) {
+            if self.nodes[*previous].levels[i].forward == Some(node) {
+                let removed = self.nodes[node].levels[i];
+                let link = &mut self.nodes[*previous].levels[i];
+                link.forward = removed.forward;
+                link.span = link.span + removed.span - 1;
+            } else {
+                self.nodes[*previous].levels[i].span -= 1;
+            }
+        }
+        let backward = self.nodes[node].backward;
+        match self.nodes[node].levels[0].forward {
+            Some(next) => self.nodes[next].backward = backward,
+            None => self.tail = backward,
+        }
+        while self.level > 1 && self.nodes[HEAD].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+        self.nodes.remove(node);
+        self.len -= 1;
+        true
+    }
+
+    // The rank of a member that is in the list
+    fn rank(&self, score: Score, member: &[u8]) -> usize {
+        let (_, rank) = self.before(score, member);
+        rank[0]
+    }
+
+    // The node at rank, counting from 0
+    fn at(&self, rank: usize) -> Option<usize> {
+        let (mut node, mut traversed) = (HEAD, 0);
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[node].levels[i].forward {
+                if traversed + self.nodes[node].levels[i].span > rank + 1 {
+                    break;
+                }
+                traversed += self.nodes[node].levels[i].span;
+                node = next;
+            }
+            if traversed == rank + 1 {
+                return Some(node);
+            }
+        }
+        None
+    }
+
+    fn count_while(&self, f: impl Fn(&[u8], f64) -> bool) -> usize {
+        let (mut node, mut count) = (HEAD, 0);
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[node].levels[i].forward {
+                if !f(&self.nodes[next].member, self.nodes[next].score.0) {
+                    break;
+                }
+                count += self.nodes[node].levels[i].span;
+                node = next;
+            }
+        }
+        count
+    }
+}
+
+// Members and their scores from a range of ranks, from either end
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    front: Option<usize>,
+    back: Option<usize>,
+    remaining: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a Vec<u8>, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.front?];
+        self.front = node.levels[0].forward;
+        self.remaining -= 1;
+        Some((&node.member, node.score.0))
+    }
+}
+
+impl DoubleEndedIterator for Iter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let node = &self.list.nodes[self.back?];
+        self.back = node.backward;
+        self.remaining -= 1;
+        Some((&node.member, node.score.0))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum End {
+    Min,
+    Max,
+}
+
+fn bulk(member: Vec<u8>) -> RESPMessage {
+    RESPMessage::BulkString(member.into())
+}
+
+fn parse_score(arg: &RESPMessage, error: &str) -> Result<f64> {
+    arg.pack_bytes()
+        .ok()
+        .and_then(parse_float)
+        .ok_or_else(|| Error::msg(error.to_string()))
+}
+
+// Members and their scores. RESP2 clients get them one after the other with the scores as
+// strings, and RESP3 ones get a [member, score] pair for each when nested is set.
+fn with_scores(pairs: Vec<(Vec<u8>, f64)>, nested: bool) -> RESPMessage {
+    let pairs = pairs
+        .into_iter()
+        .map(|(member, score)| [bulk(member), RESPMessage::Double(score)]);
+    RESPMessage::Array(if nested {
+        pairs.map(|pair| RESPMessage::Array(pair.into())).collect()
+    } else {
+        pairs.flatten().collect()
+    })
+}
+
+fn is_resp3(context: &Context) -> bool {
+    context.client.protocol == RESPVersion::RESP3
+}
+
+// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+// Replies with how many members were added, or also changed with CH. With INCR, replies
+// with the member's new score, or nil if one of the other options prevented the change.
+pub fn zadd(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    let mut rest = &args[1..];
+    while let Some(option) = rest.first() {
+        match option.pack_string()?.to_ascii_lowercase().as_ref() {
+            "nx" => nx = true,
+            "xx" => xx = true,
+            "gt" => gt = true,
+            "lt" => lt = true,
+            "ch" => ch = true,
+            "incr" => incr = true,
+            _ => break,
+        }
+        rest = &rest[1..];
+    }
+    if rest.is_empty() || !rest.len().is_multiple_of(2) {
+        return Err(syntax_error());
+    }
+    if nx && xx {
+        return Err(Error::msg(
+            "XX and NX options at the same time are not compatible",
+        ));
+    }
+    if (gt && lt) || (nx && (gt || lt)) {
+        return Err(Error::msg(
+            "GT, LT, and/or NX options at the same time are not compatible",
+        ));
+    }
+    if incr && rest.len() > 2 {
+        return Err(Error::msg(
+            "INCR option supports a single increment-element pair",
+        ));
+    }
+    let pairs = rest
+        .chunks(2)
+        .map(|pair| {
+            let score = parse_score(&pair[0], "value is not a valid float")?;
+            Ok((score, pair[1].pack_bytes()?.to_vec()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        if xx {
+            return Ok(if incr {
+                RESPMessage::Null
+            } else {
+                RESPMessage::Integer(0)
+            });
+        }
+        cache.set_expiring_at(key.to_vec(), Value::ZSet(SortedSet::default()), None);
+    }
+    let (added, changed, incremented) = cache
+        .modify(key, |value| {
+            let zset = value.as_zset_mut()?;
+            let (mut added, mut changed, mut incremented) = (0, 0, None);
+            for (score, member) in pairs {
+                let current = zset.score(&member);
+                let score = match current {
+                    Some(_) if nx => continue,
+                    None if xx => continue,
+                    Some(current) if incr => current + score,
+                    _ => score,
+                };
+                if score.is_nan() {
+                    return Err(Error::msg("resulting score is not a number (NaN)"));
+                }
+                match current {
+                    Some(current) if (gt && score <= current) || (lt && score >= current) => {
+                        continue
+                    }
+                    Some(current) if current == score => {}
+                    Some(_) => changed += 1,
+                    None => added += 1,
+                }
+                zset.insert(member, score);
+                incremented = Some(score);
+            }
+            Ok((added, changed, incremented))
+        })
+        .expect("the sorted set exists")?;
+
+    Ok(if incr {
+        incremented.map_or(RESPMessage::Null, RESPMessage::Double)
+    } else {
+        RESPMessage::Integer(added + if ch { changed } else { 0 })
+    })
+}
+
+// ZINCRBY key increment member
+// A missing member counts as 0
+pub fn zincrby(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let increment = parse_score(&args[1], "value is not a valid float")?;
+    let member = args[2].pack_bytes()?;
+
+    let mut cache = context.keyspace.lock(key);
+    if !cache.contains(key) {
+        cache.set_expiring_at(key.to_vec(), Value::ZSet(SortedSet::default()), None);
+    }
+    let score = cache
+        .modify(key, |value| {
+            let zset = value.as_zset_mut()?;
+            let score = zset.score(member).unwrap_or(0.0) + increment;
+            if score.is_nan() {
+                return Err(Error::msg("resulting score is not a number (NaN)"));
+            }
+            zset.insert(member.to_vec(), score);
+            Ok(score)
+        })
+        .expect("the sorted set exists")?;
+    Ok(RESPMessage::Double(score))
+}
+
+// ZREM key member [member ...]
+// Deletes the sorted set once its last member is removed
+pub fn zrem(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let members = args[1..]
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+
+    let removed = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let zset = value.as_zset_mut()?;
+            Ok::<_, WrongType>(members.iter().filter(|member| zset.remove(member)).count())
+        })
+        .transpose()?;
+    Ok(RESPMessage::Integer(removed.unwrap_or(0) as i64))
+}
+
+// ZSCORE key member
+pub fn zscore(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, member) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let mut cache = context.keyspace.lock(key);
+    let score = match cache.get(key) {
+        Some(value) => value.as_zset()?.score(member),
+        None => None,
+    };
+    Ok(score.map_or(RESPMessage::Null, RESPMessage::Double))
+}
+
+// ZCARD key
+pub fn zcard(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let len = match context.keyspace.lock(key).get(key) {
+        Some(value) => value.as_zset()?.len(),
+        None => 0,
+    };
+    Ok(RESPMessage::Integer(len as i64))
+}
+
+// ZRANK key member [WITHSCORE]
+pub fn zrank(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let (key, member) = (args[0].pack_bytes()?, args[1].pack_bytes()?);
+    let withscore = match args.get(2..) {
+        Some([]) | None => false,
+        Some([option]) if option.pack_string()?.eq_ignore_ascii_case("withscore") => true,
+        _ => return Err(syntax_error()),
+    };
+
+    let mut cache = context.keyspace.lock(key);
+    let found = match cache.get(key) {
+        Some(value) => {
+            let zset = value.as_zset()?;
+            zset.rank(member).zip(zset.score(member))
+        }
+        None => None,
+    };
+    Ok(match found {
+        Some((rank, score)) if withscore => RESPMessage::Array(vec![
+            RESPMessage::Integer(rank as i64),
+            RESPMessage::Double(score),
+        ]),
+        Some((rank, _)) => RESPMessage::Integer(rank as i64),
+        None if withscore => RESPMessage::NullArray,
+        None => RESPMessage::Null,
+    })
+}
+
+// One end of a BYSCORE range: a score, which leaves the score itself out when exclusive
+#[derive(Clone, Copy)]
+struct ScoreBound {
+    score: f64,
+    exclusive: bool,
+}
+
+impl ScoreBound {
+    // A score such as 1.5, -inf or +inf, preceded by ( to make it exclusive
+    fn parse(arg: &RESPMessage) -> Result<Self> {
+        let arg = arg.pack_bytes()?;
+        let (exclusive, score) = match arg.strip_prefix(b"(") {
+            Some(score) => (true, score),
+            None => (false, arg),
+        };
+        let score = parse_float(score).ok_or_else(|| Error::msg("min or max is not a float"))?;
+        Ok(Self { score, exclusive })
+    }
+
+    fn allows_min(self, score: f64) -> bool {
+        score > self.score || !self.exclusive && score == self.score
+    }
+
+    fn allows_max(self, score: f64) -> bool {
+        score < self.score || !self.exclusive && score == self.score
+    }
+}
+
+// One end of a BYLEX range
+enum LexBound {
+    // - and +, which sort before and after every member
+    Lowest,
+    Highest,
+    // [member and (member
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+}
+
+impl LexBound {
+    fn parse(arg: &RESPMessage) -> Result<Self> {
+        match arg.pack_bytes()? {
+            b"-" => Ok(Self::Lowest),
+            b"+" => Ok(Self::Highest),
+            [b'[', member @ ..] => Ok(Self::Inclusive(member.to_vec())),
+            [b'(', member @ ..] => Ok(Self::Exclusive(member.to_vec())),
+            _ => Err(Error::msg("min or max not valid string range item")),
+        }
+    }
+
+    fn allows_min(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Lowest => true,
+            Self::Highest => false,
+            Self::Inclusive(min) => member >= min.as_slice(),
+            Self::Exclusive(min) => member > min.as_slice(),
+        }
+    }
+
+    fn allows_max(&self, member: &[u8]) -> bool {
+        match self {
+            Self::Lowest => false,
+            Self::Highest => true,
+            Self::Inclusive(max) => member <= max.as_slice(),
+            Self::Exclusive(max) => member < max.as_slice(),
+        }
+    }
+}
+
+// The ranks of the members between the bounds, which allows_min and allows_max tell whether
+// a member and its score are within. Members below the min all come before the rest and
+// those up to the max all come before those past it, so counting each from the lowest score
+// gives where the range starts and ends without walking along it.
+fn within(
+    zset: &SortedSet,
+    allows_min: impl Fn(&[u8], f64) -> bool,
+    allows_max: impl Fn(&[u8], f64) -> bool,
+) -> Range<usize> {
+    let start = zset.count_while(|member, score| !allows_min(member, score));
+    let end = zset.count_while(allows_max);
+    start..end.max(start)
+}
+
+// Skips offset ranks and keeps count of the rest, starting from the highest rank with rev
+fn apply_limit(ranks: Range<usize>, offset: usize, count: usize, rev: bool) -> Range<usize> {
+    let offset = offset.min(ranks.len());
+    let len = (ranks.len() - offset).min(count);
+    if rev {
+        ranks.end - offset - len..ranks.end - offset
+    } else {
+        ranks.start + offset..ranks.start + offset + len
+    }
+}
+
+// ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count] [WITHSCORES]
+// By default start and stop are ranks, which count back from the end when negative. With
+// BYSCORE or BYLEX they are the lowest and highest score or member instead, and with REV
+// the members come from the highest score down, in which case start is the highest.
+pub fn zrange(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    let key = args[0].pack_bytes()?;
+    let (mut by_score, mut by_lex, mut rev, mut withscores) = (false, false, false, false);
+    let mut limit = None;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match option.pack_string()?.to_ascii_lowercase().as_ref() {
+            "byscore" => by_score = true,
+            "bylex" => by_lex = true,
+            "rev" => rev = true,
+            "withscores" => withscores = true,
+            "limit" => {
+                let offset = parse_integer(options.next().ok_or_else(syntax_error)?)?;
+                let count = parse_integer(options.next().ok_or_else(syntax_error)?)?;
+                limit = Some((offset, count));
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+    if by_score && by_lex {
+        return Err(syntax_error());
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(Error::msg(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX",
+        ));
+    }
+    if withscores && by_lex {
+        return Err(Error::msg(
+            "syntax error, WITHSCORES not supported in combination with BYLEX",
+        ));
+    }
+    // A negative offset selects nothing and a negative count everything from the offset
+    let (skip, take) = match limit {
+        Some((offset, _)) if offset < 0 => (0, 0),
+        Some((offset, count)) => (
+            offset as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        ),
+        None => (0, usize::MAX),
+    };
+    let (low, high) = if rev {
+        (&args[2], &args[1])
+    } else {
+        (&args[1], &args[2])
+    };
+
+    let mut cache = context.keyspace.lock(key);
+    let Some(value) = cache.get(key) else {
+        return Ok(RESPMessage::Array(vec![]));
+    };
+    let zset = value.as_zset()?;
+
+    // The ranks of the selected members, counting from the lowest score
+    let ranks = if by_score {
+        let (min, max) = (ScoreBound::parse(low)?, ScoreBound::parse(high)?);
+        let ranks = within(
+            zset,
+            |_, score| min.allows_min(score),
+            |_, score| max.allows_max(score),
+        );
+        apply_limit(ranks, skip, take, rev)
+    } else if by_lex {
+        let (min, max) = (LexBound::parse(low)?, LexBound::parse(high)?);
+        let ranks = within(
+            zset,
+            |member, _| min.allows_min(member),
+            |member, _| max.allows_max(member),
+        );
+        apply_limit(ranks, skip, take, rev)
+    } else {
+        let range = list::range(
+            parse_integer(&args[1])?,
+            parse_integer(&args[2])?,
+            zset.len(),
+        );
+        if rev {
+            zset.len() - range.end..zset.len() - range.start
+        } else {
+            range
+        }
+    };
+    let selected: Vec<(&Vec<u8>, f64)> = if rev {
+        zset.range(ranks).rev().collect()
+    } else {
+        zset.range(ranks).collect()
+    };
+
+    let selected = selected
+        .into_iter()
+        .map(|(member, score)| (member.clone(), score));
+    Ok(if withscores {
+        with_scores(selected.collect(), is_resp3(context))
+    } else {
+        RESPMessage::Array(selected.map(|(member, _)| bulk(member)).collect())
+    })
+}
+
+// ZPOPMIN key [count]
+pub fn zpopmin(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    pop_command(context, args, "zpopmin", End::Min)
+}
+
+// ZPOPMAX key [count]
+pub fn zpopmax(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    pop_command(context, args, "zpopmax", End::Max)
+}
+
+// Replies with the popped members and their scores. Without a count a single member is
+// popped, and RESP3 clients get it as a flat pair rather than a list of them.
+fn pop_command(
+    context: &mut Context,
+    args: &[RESPMessage],
+    name: &str,
+    end: End,
+) -> Result<RESPMessage> {
+    if args.len() > 2 {
+        return Ok(wrong_arity(name));
+    }
+    let key = args[0].pack_bytes()?;
+    let count = match args.get(1) {
+        Some(count) => {
+            let count = parse_integer(count)?;
+            if count < 0 {
+                return Err(Error::msg("value is out of range, must be positive"));
+            }
+            Some(count as usize)
+        }
+        None => None,
+    };
+
+    let popped = context
+        .keyspace
+        .lock(key)
+        .modify(key, |value| {
+            let zset = value.as_zset_mut()?;
+            Ok::<_, WrongType>(
+                (0..count.unwrap_or(1))
+                    .map_while(|_| zset.pop(end))
+                    .collect::<Vec<_>>(),
+            )
+        })
+        .transpose()?;
+    let nested = count.is_some() && is_resp3(context);
+    Ok(with_scores(popped.unwrap_or_default(), nested))
+}
+
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf counts as 0, as in Redis
+            Self::Sum => Some(a + b).filter(|sum| !sum.is_nan()).unwrap_or(0.0),
+            Self::Min => a.min(b),
+            Self::Max => a.max(b),
+        }
+    }
+}
+
+// ZUNIONSTORE destination numkeys key [key ...] [WEIGHTS weight [weight ...]]
+//   [AGGREGATE SUM | MIN | MAX]
+pub fn zunionstore(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    store_command(context, args, "zunionstore", false)
+}
+
+// ZINTERSTORE destination numkeys key [key ...] [WEIGHTS weight [weight ...]]
+//   [AGGREGATE SUM | MIN | MAX]
+pub fn zinterstore(context: &mut Context, args: &[RESPMessage]) -> Result<RESPMessage> {
+    store_command(context, args, "zinterstore", true)
+}
+
+// Combines sorted sets, or plain sets whose members all count as scoring 1, multiplying each
+// score by the weight of its set. Replaces whatever the destination holds with the result,
+// or deletes it if the result is empty, and replies with the number of members in it.
+fn store_command(
+    context: &mut Context,
+    args: &[RESPMessage],
+    name: &str,
+    intersect: bool,
+) -> Result<RESPMessage> {
+    let destination = args[0].pack_bytes()?;
+    let numkeys = parse_integer(&args[1])?;
+    if numkeys < 1 {
+        return Err(Error::msg(format!(
+            "at least 1 input key is needed for '{}' command",
+            name
+        )));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys > args.len() - 2 {
+        return Err(syntax_error());
+    }
+    let sources = args[2..2 + numkeys]
+        .iter()
+        .map(RESPMessage::pack_bytes)
+        .collect::<Result<Vec<_>>>()?;
+    let mut weights = vec![1.0; numkeys];
+    let mut aggregate = Aggregate::Sum;
+    let mut options = args[2 + numkeys..].iter();
+    while let Some(option) = options.next() {
+        match option.pack_string()?.to_ascii_lowercase().as_ref() {
+            "weights" => {
+                for weight in weights.iter_mut() {
+                    *weight = parse_score(
+                        options.next().ok_or_else(syntax_error)?,
+                        "weight value is not a float",
+                    )?;
+                }
+            }
+            "aggregate" => {
+                let option = options.next().ok_or_else(syntax_error)?;
+                aggregate = match option.pack_string()?.to_ascii_lowercase().as_ref() {
+                    "sum" => Aggregate::Sum,
+                    "min" => Aggregate::Min,
+                    "max" => Aggregate::Max,
+                    _ => return Err(syntax_error()),
+                };
+            }
+            _ => return Err(syntax_error()),
+        }
+    }
+
+    let keyspace = context.keyspace;
+    let mut shards = keyspace.lock_all(sources.iter().copied().chain([destination]));
+    // The members of each source with their weighted scores, empty for a missing key
+    let mut inputs = vec![];
+    for (source, weight) in sources.iter().zip(weights) {
+        let members: Vec<(Vec<u8>, f64)> = match shards.shard(source).get(source) {
+            Some(Value::ZSet(zset)) => zset
+                .iter()
+                .map(|(member, score)| (member.clone(), score))
+                .collect(),
+            Some(Value::Set(set)) => set
+                .members()
+                .into_iter()
+                .map(|member| (member, 1.0))
+                .collect(),
+            Some(_) => return Err(WrongType.into()),
+            None => vec![],
+        };
+        // 0 * inf counts as 0, as in Redis
+        inputs.push(
+            members
+                .into_iter()
+                .map(|(member, score)| {
+                    let weighted = score * weight;
+                    (member, if weighted.is_nan() { 0.0 } else { weighted })
+                })
+                .collect::<HashMap<_, _>>(),
+        );
+    }
+
+    let mut inputs = inputs.into_iter();
+    let mut result = inputs.next().expect("there is at least one key");
+    for input in inputs {
+        if intersect {
+            result.retain(|member, _| input.contains_key(member));
+            for (member, score) in result.iter_mut() {
+                *score = aggregate.apply(*score, input[member]);
+            }
+        } else {
+            for (member, score) in input {
+                result
+                    .entry(member)
+                    .and_modify(|total| *total = aggregate.apply(*total, score))
+                    .or_insert(score);
+            }
+        }
+    }
+
+    let len = result.len();
+    let cache = shards.shard(destination);
+    if len == 0 {
+        cache.delete(destination);
+    } else {
+        let mut zset = SortedSet::default();
+        for (member, score) in result {
+            zset.insert(member, score);
+        }
+        cache.set_expiring_at(destination.to_vec(), Value::ZSet(zset), None);
+    }
+    Ok(RESPMessage::Integer(len as i64))
+}
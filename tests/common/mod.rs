@@ -0,0 +1,38 @@
+#![allow(dead_code)]
+
+use assert_cmd::prelude::CommandCargoExt;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    thread::sleep,
+    time::Duration,
+};
+
+static mut PROC: Option<std::process::Child> = None;
+
+#[ctor::ctor]
+fn start_server() {
+    let mut cmd = Command::cargo_bin("tinyredis").unwrap();
+    unsafe {
+        PROC = Some(cmd.stdout(Stdio::null()).spawn().unwrap());
+    }
+    sleep(Duration::from_millis(100));
+}
+
+#[ctor::dtor]
+fn stop_server() {
+    unsafe {
+        if let Some(ref mut proc) = PROC {
+            proc.kill().unwrap();
+        }
+    }
+}
+
+// Sends raw bytes over the connection and returns whatever the server replies with
+pub fn send_raw(stream: &mut TcpStream, bytes: &[u8]) -> Vec<u8> {
+    stream.write_all(bytes).unwrap();
+    let mut response = [0; 4096];
+    let bytes_read = stream.read(&mut response).unwrap();
+    response[..bytes_read].to_vec()
+}
@@ -0,0 +1,454 @@
+mod common;
+
+use redis::{Client, Connection, RedisResult};
+use std::sync::Mutex;
+
+// The eviction policy is shared by the whole server, so these tests take turns
+static SERVER: Mutex<()> = Mutex::new(());
+
+// Starts from an empty cache with room for 3 of the keys the tests use
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+    config_set(&mut con, "maxmemory", "0");
+    clear(&mut con);
+
+    set(&mut con, "k1", &[]).unwrap();
+    let size = memory(&mut con, "used_memory");
+    let _: i64 = redis::cmd("DEL").arg("k1").query(&mut con).unwrap();
+    config_set(&mut con, "maxmemory", &(3 * size + size / 2).to_string());
+    con
+}
+
+// Like connect, but with room for count keys named by many_key, so that every shard holds
+// several of them and which one is evicted depends on each shard's recency list as well as
+// on comparing the shards' picks
+fn connect_many(count: usize) -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+    config_set(&mut con, "maxmemory", "0");
+    clear(&mut con);
+
+    set(&mut con, &many_key(0), &[]).unwrap();
+    let size = memory(&mut con, "used_memory");
+    clear(&mut con);
+    config_set(&mut con, "maxmemory", &(count * size + size / 2).to_string());
+    set_policy(&mut con, "allkeys-lru");
+    con
+}
+
+// Keys that all take up the same room
+fn many_key(i: usize) -> String {
+    format!("many{:03}", i)
+}
+
+// Deletes every key the tests use
+fn clear(con: &mut Connection) {
+    let mut keys: Vec<String> = (1..=5).map(|i| format!("k{}", i)).collect();
+    keys.extend((0..200).map(many_key));
+    let _: i64 = redis::cmd("DEL").arg(&keys).query(con).unwrap();
+}
+
+// Whether the key exists, without that counting as a use of it like a GET would
+fn stored(con: &mut Connection, key: &str) -> bool {
+    let ttl: i64 = redis::cmd("PTTL").arg(key).query(con).unwrap();
+    ttl != -2
+}
+
+// Sets count new keys after next one at a time, checking that each evicts the least
+// recently used key there is, the first in order
+fn assert_evicts_in_order(con: &mut Connection, order: &mut Vec<usize>, next: usize, count: usize) {
+    for i in next..next + count {
+        set(con, &many_key(i), &[]).unwrap();
+        order.push(i);
+        let evicted = order.remove(0);
+        assert!(!stored(con, &many_key(evicted)), "{} was not evicted", evicted);
+        assert!(stored(con, &many_key(order[0])), "{} was evicted", order[0]);
+    }
+}
+
+// Reads a field from INFO's memory section
+fn memory(con: &mut Connection, field: &str) -> usize {
+    let info: String = redis::cmd("INFO").arg("memory").query(con).unwrap();
+    info.lines()
+        .find_map(|line| line.strip_prefix(&format!("{}:", field)))
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+fn config_set(con: &mut Connection, name: &str, value: &str) {
+    let _: String = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg(name)
+        .arg(value)
+        .query(con)
+        .unwrap();
+}
+
+fn set_policy(con: &mut Connection, policy: &str) {
+    config_set(con, "maxmemory-policy", policy);
+}
+
+fn set(con: &mut Connection, key: &str, args: &[&str]) -> RedisResult<String> {
+    redis::cmd("SET").arg(key).arg("value").arg(args).query(con)
+}
+
+fn frequency(con: &mut Connection, key: &str) -> RedisResult<Option<i64>> {
+    redis::cmd("OBJECT").arg("FREQ").arg(key).query(con)
+}
+
+fn exists(con: &mut Connection, key: &str) -> bool {
+    redis::cmd("GET")
+        .arg(key)
+        .query::<Option<String>>(con)
+        .unwrap()
+        .is_some()
+}
+
+#[test]
+fn it_evicts_the_least_recently_used_key_under_allkeys_lru() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect();
+    set_policy(&mut con, "allkeys-lru");
+    let maxmemory = memory(&mut con, "maxmemory");
+
+    for key in ["k1", "k2", "k3"] {
+        set(&mut con, key, &[]).unwrap();
+    }
+    assert!(exists(&mut con, "k1"));
+    set(&mut con, "k4", &[]).unwrap();
+    assert!(!exists(&mut con, "k2"));
+    assert!(exists(&mut con, "k1") && exists(&mut con, "k3") && exists(&mut con, "k4"));
+    assert!(memory(&mut con, "used_memory") <= maxmemory);
+
+    // A bigger value takes the room of more than one key
+    set(&mut con, "k2", &[]).unwrap();
+    let _: String = redis::cmd("SET")
+        .arg("k1")
+        .arg("v".repeat(maxmemory / 3))
+        .query(&mut con)
+        .unwrap();
+    assert!(exists(&mut con, "k1") && !exists(&mut con, "k3") && !exists(&mut con, "k4"));
+
+    // Lowering the limit evicts keys straight away
+    config_set(&mut con, "maxmemory", "1");
+    assert_eq!(memory(&mut con, "used_memory"), 0);
+    let info: String = redis::cmd("INFO").arg("memory").query(&mut con).unwrap();
+    assert!(info.contains("maxmemory_human:1B\r\n"));
+    assert!(info.contains("maxmemory_policy:allkeys-lru\r\n"));
+}
+
+#[test]
+fn it_can_handle_lru() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect();
+    set_policy(&mut con, "allkeys-lru");
+
+    for i in 1..=4 {
+        let _: String = redis::cmd("SET")
+            .arg(format!("k{}", i))
+            .arg(format!("value{}", i))
+            .query(&mut con)
+            .unwrap();
+    }
+
+    // k4 was set last and k1 first, with nothing read in between
+    let value: Option<String> = redis::cmd("GET").arg("k4").query(&mut con).unwrap();
+    assert_eq!(value.as_deref(), Some("value4"));
+    let value: Option<String> = redis::cmd("GET").arg("k1").query(&mut con).unwrap();
+    assert_eq!(value, None);
+}
+
+#[test]
+fn it_evicts_the_least_recently_touched_key_after_reads_writes_and_deletes() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect_many(40);
+
+    // From the least to the most recently used
+    let mut order: Vec<usize> = (0..40).collect();
+    for &i in &order {
+        set(&mut con, &many_key(i), &[]).unwrap();
+    }
+    // Read some keys, overwrite others, delete others and leave the rest alone
+    for i in 0..40 {
+        match i % 4 {
+            0 => assert!(exists(&mut con, &many_key(i))),
+            1 => set(&mut con, &many_key(i), &[]).map(|_| ()).unwrap(),
+            2 => {
+                let _: i64 = redis::cmd("DEL").arg(many_key(i)).query(&mut con).unwrap();
+            }
+            _ => continue,
+        }
+        order.retain(|&j| j != i);
+        if i % 4 != 2 {
+            order.push(i);
+        }
+    }
+    // Fill the room the deleted keys left, after which every new key evicts one
+    for i in 40..50 {
+        set(&mut con, &many_key(i), &[]).unwrap();
+        order.push(i);
+    }
+    assert_evicts_in_order(&mut con, &mut order, 50, 40);
+}
+
+#[test]
+fn it_reuses_the_slots_of_deleted_keys() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect_many(40);
+
+    let mut order: Vec<usize> = (0..40).collect();
+    for &i in &order {
+        set(&mut con, &many_key(i), &[]).unwrap();
+    }
+    // Deleting every other key frees slots all over the shards, which the next keys take,
+    // and deleting the oldest keys left empties the tails of the recency lists
+    let deleted: Vec<usize> = (0..40).step_by(2).chain([1, 3, 5, 7, 9]).collect();
+    for i in &deleted {
+        let _: i64 = redis::cmd("DEL").arg(many_key(*i)).query(&mut con).unwrap();
+    }
+    order.retain(|i| !deleted.contains(i));
+    for i in 40..65 {
+        set(&mut con, &many_key(i), &[]).unwrap();
+        order.push(i);
+    }
+    assert_evicts_in_order(&mut con, &mut order, 65, 40);
+}
+
+#[test]
+fn it_refuses_writes_at_capacity_under_noeviction() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect();
+    set_policy(&mut con, "noeviction");
+
+    for key in ["k1", "k2", "k3"] {
+        set(&mut con, key, &[]).unwrap();
+    }
+    let err = set(&mut con, "k4", &[]).unwrap_err();
+    assert_eq!(err.code(), Some("OOM"));
+    assert_eq!(
+        err.detail(),
+        Some("command not allowed when used memory > 'maxmemory'.")
+    );
+
+    // Existing keys can still be overwritten, as that takes no extra room
+    assert_eq!(set(&mut con, "k1", &[]).unwrap(), "OK");
+    assert!(exists(&mut con, "k1") && exists(&mut con, "k2") && exists(&mut con, "k3"));
+}
+
+#[test]
+fn it_only_evicts_keys_with_an_expiry_under_volatile_policies() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect();
+
+    set_policy(&mut con, "volatile-ttl");
+    set(&mut con, "k1", &["EX", "100"]).unwrap();
+    set(&mut con, "k2", &["EX", "10"]).unwrap();
+    set(&mut con, "k3", &[]).unwrap();
+    set(&mut con, "k4", &[]).unwrap();
+    assert!(!exists(&mut con, "k2"));
+
+    // k1 and k3 may be evicted, and k1 was used last
+    set_policy(&mut con, "volatile-lru");
+    set(&mut con, "k3", &["EX", "100"]).unwrap();
+    assert!(exists(&mut con, "k1"));
+    set(&mut con, "k2", &[]).unwrap();
+    assert!(!exists(&mut con, "k3"));
+    assert!(exists(&mut con, "k1") && exists(&mut con, "k4"));
+
+    // Nothing left that may be evicted
+    set_policy(&mut con, "volatile-random");
+    let _: i64 = redis::cmd("PERSIST").arg("k1").query(&mut con).unwrap();
+    assert_eq!(set(&mut con, "k3", &[]).unwrap_err().code(), Some("OOM"));
+}
+
+#[test]
+fn it_evicts_the_least_frequently_used_key_under_allkeys_lfu() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect();
+    set_policy(&mut con, "allkeys-lfu");
+    // Without a log factor every use counts
+    config_set(&mut con, "lfu-log-factor", "0");
+
+    for key in ["k1", "k2", "k3"] {
+        set(&mut con, key, &[]).unwrap();
+    }
+    assert_eq!(frequency(&mut con, "k1").unwrap(), Some(5));
+    for key in ["k1", "k1", "k2", "k3", "k3"] {
+        assert!(exists(&mut con, key));
+    }
+    assert_eq!(frequency(&mut con, "k1").unwrap(), Some(7));
+    assert_eq!(frequency(&mut con, "k2").unwrap(), Some(6));
+    assert_eq!(frequency(&mut con, "missing").unwrap(), None);
+
+    set(&mut con, "k4", &[]).unwrap();
+    assert!(!exists(&mut con, "k2"));
+
+    // With the default factor, uses become less and less likely to count
+    config_set(&mut con, "lfu-log-factor", "10");
+    for _ in 0..100 {
+        assert!(exists(&mut con, "k4"));
+    }
+    let k4 = frequency(&mut con, "k4").unwrap().unwrap();
+    assert!(k4 > 6 && k4 < 50, "{}", k4);
+
+    set_policy(&mut con, "allkeys-lru");
+    let err = frequency(&mut con, "k4").unwrap_err();
+    assert!(err.detail().unwrap().starts_with("An LFU maxmemory policy is not selected"));
+}
+
+#[test]
+fn it_can_get_and_set_config() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let mut con = connect();
+
+    set_policy(&mut con, "allkeys-random");
+    let config: Vec<String> = redis::cmd("CONFIG")
+        .arg("GET")
+        .arg("maxmemory-*")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(
+        config,
+        ["maxmemory-policy", "allkeys-random", "maxmemory-samples", "5"]
+    );
+
+    let err = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("hz")
+        .arg("20")
+        .arg("maxmemory-policy")
+        .arg("most-recent")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some(
+            "CONFIG SET failed (possibly related to argument 'maxmemory-policy') - argument(s) \
+             must be one of the following: noeviction, allkeys-lru, allkeys-lfu, allkeys-random, \
+             volatile-lru, volatile-lfu, volatile-random, volatile-ttl"
+        )
+    );
+    // Nothing changes unless every parameter is valid
+    let hz: Vec<String> = redis::cmd("CONFIG").arg("GET").arg("HZ").query(&mut con).unwrap();
+    assert_eq!(hz, ["hz", "10"]);
+
+    let err = redis::cmd("CONFIG")
+        .arg("SET")
+        .arg("no-such-option")
+        .arg("1")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("Unknown option or number of arguments for CONFIG SET - 'no-such-option'")
+    );
+
+    set_policy(&mut con, "allkeys-lru");
+}
+
+// Runs the command and returns its reply, for the commands whose reply doesn't matter
+fn run(con: &mut Connection, args: &[&str]) -> redis::Value {
+    redis::cmd(args[0]).arg(&args[1..]).query(con).unwrap()
+}
+
+#[test]
+fn it_keeps_count_of_the_memory_collections_take_up_as_they_change() {
+    let _server = SERVER.lock().unwrap_or_else(|e| e.into_inner());
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+    config_set(&mut con, "maxmemory", "0");
+    clear(&mut con);
+    let empty = memory(&mut con, "used_memory");
+
+    // A list changed at both ends and in the middle counts the same as one pushed afresh
+    run(&mut con, &["RPUSH", "k1", "a", "bb", "ccc", "dddd", "eeeee", "ffffff", "bb"]);
+    run(&mut con, &["LPUSH", "k1", "zz"]);
+    run(&mut con, &["LPOP", "k1"]);
+    run(&mut con, &["RPOP", "k1", "2"]);
+    run(&mut con, &["LSET", "k1", "0", "a much longer first element"]);
+    run(&mut con, &["LINSERT", "k1", "BEFORE", "ccc", "inserted"]);
+    run(&mut con, &["LREM", "k1", "1", "bb"]);
+    run(&mut con, &["LTRIM", "k1", "0", "2"]);
+    let elements: Vec<String> =
+        redis::cmd("LRANGE").arg("k1").arg(0).arg(-1).query(&mut con).unwrap();
+    let changed = memory(&mut con, "used_memory");
+    run(&mut con, &["DEL", "k1"]);
+    let _: i64 = redis::cmd("RPUSH").arg("k1").arg(&elements).query(&mut con).unwrap();
+    assert_eq!(memory(&mut con, "used_memory"), changed);
+
+    run(&mut con, &["DEL", "k1"]);
+    assert_eq!(memory(&mut con, "used_memory"), empty);
+
+    // So does a sorted set whose members moved, were removed and were popped
+    run(&mut con, &["ZADD", "k2", "1", "a", "2", "bb", "3", "ccc", "4", "dddd", "5", "eeeee"]);
+    run(&mut con, &["ZADD", "k2", "10", "a", "0", "ffffff"]);
+    run(&mut con, &["ZINCRBY", "k2", "2.5", "bb"]);
+    run(&mut con, &["ZREM", "k2", "ccc", "missing"]);
+    run(&mut con, &["ZPOPMIN", "k2"]);
+    let members: Vec<String> = redis::cmd("ZRANGE")
+        .arg("k2")
+        .arg(0)
+        .arg(-1)
+        .arg("WITHSCORES")
+        .query(&mut con)
+        .unwrap();
+    let changed = memory(&mut con, "used_memory");
+    run(&mut con, &["DEL", "k2"]);
+    let pairs: Vec<&String> = members.chunks(2).flat_map(|pair| [&pair[1], &pair[0]]).collect();
+    let _: i64 = redis::cmd("ZADD").arg("k2").arg(&pairs).query(&mut con).unwrap();
+    assert_eq!(memory(&mut con, "used_memory"), changed);
+
+    run(&mut con, &["DEL", "k2"]);
+    assert_eq!(memory(&mut con, "used_memory"), empty);
+
+    // And so does a hash, both as a listpack and once it is converted to a hash table
+    for long in ["short", &"x".repeat(100)] {
+        run(&mut con, &["HSET", "k3", "a", "1", "bb", "22", "ccc", "333"]);
+        run(&mut con, &["HSET", "k3", "a", "a longer value", "dddd", long]);
+        run(&mut con, &["HINCRBY", "k3", "bb", "1000"]);
+        run(&mut con, &["HDEL", "k3", "ccc", "missing"]);
+        let fields: Vec<String> = redis::cmd("HGETALL").arg("k3").query(&mut con).unwrap();
+        let changed = memory(&mut con, "used_memory");
+        run(&mut con, &["DEL", "k3"]);
+        let _: i64 = redis::cmd("HSET").arg("k3").arg(&fields).query(&mut con).unwrap();
+        assert_eq!(memory(&mut con, "used_memory"), changed);
+
+        run(&mut con, &["DEL", "k3"]);
+        assert_eq!(memory(&mut con, "used_memory"), empty);
+    }
+
+    // And a set kept as a hash table, whose members were added, removed and popped
+    run(&mut con, &["SADD", "k4", "a", "bb", "ccc", "dddd", "eeeee", "1", "22"]);
+    run(&mut con, &["SREM", "k4", "bb", "missing"]);
+    run(&mut con, &["SPOP", "k4", "2"]);
+    run(&mut con, &["SADD", "k4", "ffffff"]);
+    let members: Vec<String> = redis::cmd("SMEMBERS").arg("k4").query(&mut con).unwrap();
+    let changed = memory(&mut con, "used_memory");
+    run(&mut con, &["DEL", "k4"]);
+    let _: i64 = redis::cmd("SADD").arg("k4").arg(&members).query(&mut con).unwrap();
+    assert_eq!(memory(&mut con, "used_memory"), changed);
+
+    run(&mut con, &["DEL", "k4"]);
+    assert_eq!(memory(&mut con, "used_memory"), empty);
+
+    // And a stream that was trimmed, with a group whose consumers came and went
+    run(&mut con, &["XADD", "k5", "1-1", "a", "1"]);
+    run(&mut con, &["XADD", "k5", "1-2", "bb", "22", "ccc", "333"]);
+    run(&mut con, &["XADD", "k5", "MAXLEN", "2", "1-3", "dddd", "4444"]);
+    run(&mut con, &["XGROUP", "CREATE", "k5", "g", "0"]);
+    run(&mut con, &["XGROUP", "CREATECONSUMER", "k5", "g", "c1"]);
+    run(&mut con, &["XGROUP", "CREATECONSUMER", "k5", "g", "consumer2"]);
+    run(&mut con, &["XGROUP", "DELCONSUMER", "k5", "g", "c1"]);
+    let changed = memory(&mut con, "used_memory");
+    run(&mut con, &["DEL", "k5"]);
+    run(&mut con, &["XADD", "k5", "1-2", "bb", "22", "ccc", "333"]);
+    run(&mut con, &["XADD", "k5", "1-3", "dddd", "4444"]);
+    run(&mut con, &["XGROUP", "CREATE", "k5", "g", "0"]);
+    run(&mut con, &["XGROUP", "CREATECONSUMER", "k5", "g", "consumer2"]);
+    assert_eq!(memory(&mut con, "used_memory"), changed);
+
+    run(&mut con, &["DEL", "k5"]);
+    assert_eq!(memory(&mut con, "used_memory"), empty);
+}
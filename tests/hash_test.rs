@@ -0,0 +1,328 @@
+mod common;
+
+use common::send_raw;
+use redis::{Client, Connection, RedisResult};
+use std::{collections::HashSet, net::TcpStream};
+
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    client.get_connection().unwrap()
+}
+
+fn hset(con: &mut Connection, key: &str, pairs: &[(&str, &str)]) -> i64 {
+    redis::cmd("HSET").arg(key).arg(pairs).query(con).unwrap()
+}
+
+fn encoding(con: &mut Connection, key: &str) -> String {
+    redis::cmd("OBJECT")
+        .arg("ENCODING")
+        .arg(key)
+        .query(con)
+        .unwrap()
+}
+
+#[test]
+fn it_can_set_get_and_delete_fields() {
+    let mut con = connect();
+
+    assert_eq!(
+        hset(&mut con, "hash-user", &[("name", "ada"), ("lang", "en")]),
+        2
+    );
+    assert_eq!(
+        hset(&mut con, "hash-user", &[("lang", "fr"), ("age", "36")]),
+        1
+    );
+
+    let name: Option<String> = redis::cmd("HGET")
+        .arg("hash-user")
+        .arg("name")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(name.as_deref(), Some("ada"));
+    let values: Vec<Option<String>> = redis::cmd("HMGET")
+        .arg("hash-user")
+        .arg(&["lang", "missing", "age"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(
+        values,
+        [Some("fr".to_string()), None, Some("36".to_string())]
+    );
+
+    let all: Vec<(String, String)> = redis::cmd("HGETALL")
+        .arg("hash-user")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(
+        all,
+        [
+            ("name".to_string(), "ada".to_string()),
+            ("lang".to_string(), "fr".to_string()),
+            ("age".to_string(), "36".to_string()),
+        ]
+    );
+    let keys: Vec<String> = redis::cmd("HKEYS")
+        .arg("hash-user")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(keys, ["name", "lang", "age"]);
+    let values: Vec<String> = redis::cmd("HVALS")
+        .arg("hash-user")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(values, ["ada", "fr", "36"]);
+
+    let exists: Vec<i64> = ["name", "missing"]
+        .iter()
+        .map(|field| {
+            redis::cmd("HEXISTS")
+                .arg("hash-user")
+                .arg(field)
+                .query(&mut con)
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(exists, [1, 0]);
+
+    let removed: i64 = redis::cmd("HDEL")
+        .arg("hash-user")
+        .arg(&["name", "missing", "lang"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(removed, 2);
+    let len: i64 = redis::cmd("HLEN").arg("hash-user").query(&mut con).unwrap();
+    assert_eq!(len, 1);
+
+    // Removing the last field removes the key
+    let _: i64 = redis::cmd("HDEL")
+        .arg("hash-user")
+        .arg("age")
+        .query(&mut con)
+        .unwrap();
+    let kind: String = redis::cmd("TYPE").arg("hash-user").query(&mut con).unwrap();
+    assert_eq!(kind, "none");
+
+    let err = redis::cmd("HSET")
+        .arg("hash-user")
+        .arg(&["name", "ada", "lang"])
+        .query::<i64>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("wrong number of arguments for 'hset' command")
+    );
+}
+
+#[test]
+fn it_can_increment_fields() {
+    let mut con = connect();
+    hset(
+        &mut con,
+        "hash-counters",
+        &[("text", "abc"), ("max", "9223372036854775807")],
+    );
+
+    let incrby = |con: &mut Connection, field: &str, increment: i64| -> RedisResult<i64> {
+        redis::cmd("HINCRBY")
+            .arg("hash-counters")
+            .arg(field)
+            .arg(increment)
+            .query(con)
+    };
+    assert_eq!(incrby(&mut con, "visits", 5).unwrap(), 5);
+    assert_eq!(incrby(&mut con, "visits", -7).unwrap(), -2);
+    assert_eq!(
+        incrby(&mut con, "text", 1).unwrap_err().detail(),
+        Some("hash value is not an integer")
+    );
+    assert_eq!(
+        incrby(&mut con, "max", 1).unwrap_err().detail(),
+        Some("increment or decrement would overflow")
+    );
+
+    let incrbyfloat = |con: &mut Connection, field: &str, increment: &str| -> RedisResult<String> {
+        redis::cmd("HINCRBYFLOAT")
+            .arg("hash-counters")
+            .arg(field)
+            .arg(increment)
+            .query(con)
+    };
+    assert_eq!(incrbyfloat(&mut con, "price", "10.5").unwrap(), "10.5");
+    assert_eq!(incrbyfloat(&mut con, "price", "0.1").unwrap(), "10.6");
+    assert_eq!(incrbyfloat(&mut con, "visits", "2").unwrap(), "0");
+    assert_eq!(incrbyfloat(&mut con, "price", "5.0e3").unwrap(), "5010.6");
+    // Very large and very small results are written with an exponent, and read back as one
+    assert_eq!(incrbyfloat(&mut con, "huge", "1e21").unwrap(), "1e+21");
+    assert_eq!(incrbyfloat(&mut con, "huge", "1e21").unwrap(), "2e+21");
+    assert_eq!(incrbyfloat(&mut con, "tiny", "1e-7").unwrap(), "1e-07");
+    let tiny: String = redis::cmd("HGET")
+        .arg("hash-counters")
+        .arg("tiny")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(tiny, "1e-07");
+    assert_eq!(
+        incrbyfloat(&mut con, "text", "1").unwrap_err().detail(),
+        Some("hash value is not a float")
+    );
+    assert_eq!(
+        incrbyfloat(&mut con, "price", "x").unwrap_err().detail(),
+        Some("value is not a valid float")
+    );
+    assert_eq!(
+        incrbyfloat(&mut con, "price", "inf").unwrap_err().detail(),
+        Some("increment would produce NaN or Infinity")
+    );
+}
+
+#[test]
+fn it_converts_large_hashes_to_hash_tables() {
+    let mut con = connect();
+
+    hset(&mut con, "hash-small", &[("a", "1")]);
+    assert_eq!(encoding(&mut con, "hash-small"), "listpack");
+
+    // Too many fields
+    let fields: Vec<(String, String)> = (0..129)
+        .map(|i| (format!("f{}", i), i.to_string()))
+        .collect();
+    let added: i64 = redis::cmd("HSET")
+        .arg("hash-many")
+        .arg(&fields)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(added, 129);
+    assert_eq!(encoding(&mut con, "hash-many"), "hashtable");
+    let value: String = redis::cmd("HGET")
+        .arg("hash-many")
+        .arg("f128")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(value, "128");
+
+    // Too long a value
+    hset(&mut con, "hash-long", &[("a", "1")]);
+    hset(&mut con, "hash-long", &[("b", &"x".repeat(65))]);
+    assert_eq!(encoding(&mut con, "hash-long"), "hashtable");
+    let len: i64 = redis::cmd("HLEN").arg("hash-long").query(&mut con).unwrap();
+    assert_eq!(len, 2);
+}
+
+#[test]
+fn it_can_scan_every_field() {
+    let mut con = connect();
+    let fields: Vec<(String, String)> = (0..300)
+        .map(|i| (format!("f{}", i), i.to_string()))
+        .collect();
+    let _: i64 = redis::cmd("HSET")
+        .arg("hash-scan")
+        .arg(&fields)
+        .query(&mut con)
+        .unwrap();
+
+    let mut seen = HashSet::new();
+    let mut cursor = "0".to_string();
+    let mut calls = 0;
+    loop {
+        let (next, pairs): (String, Vec<(String, String)>) = redis::cmd("HSCAN")
+            .arg("hash-scan")
+            .arg(&cursor)
+            .arg("COUNT")
+            .arg(50)
+            .query(&mut con)
+            .unwrap();
+        // Each call only returns about COUNT fields rather than the rest of the hash
+        assert!(pairs.len() <= 50);
+        for (field, value) in pairs {
+            assert_eq!(field, format!("f{}", value));
+            assert!(seen.insert(field));
+        }
+        calls += 1;
+        // Fields removed during the scan don't stop the rest from being returned
+        if calls == 2 {
+            let _: i64 = redis::cmd("HDEL")
+                .arg("hash-scan")
+                .arg("f299")
+                .query(&mut con)
+                .unwrap();
+        }
+        if next == "0" {
+            break;
+        }
+        cursor = next;
+    }
+    assert!(calls >= 6);
+    assert!((0..299).all(|i| seen.contains(&format!("f{}", i))));
+
+    // A listpack is returned whole, and MATCH and NOVALUES apply to it too
+    hset(
+        &mut con,
+        "hash-scan-small",
+        &[("name", "ada"), ("lang", "en"), ("nick", "a")],
+    );
+    let (next, fields): (String, Vec<String>) = redis::cmd("HSCAN")
+        .arg("hash-scan-small")
+        .arg(0)
+        .arg("MATCH")
+        .arg("n*")
+        .arg("NOVALUES")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(next, "0");
+    assert_eq!(fields, ["name", "nick"]);
+
+    let err = redis::cmd("HSCAN")
+        .arg("hash-scan")
+        .arg("x")
+        .query::<(String, Vec<String>)>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("invalid cursor"));
+}
+
+#[test]
+fn it_sends_hgetall_as_a_map_to_resp3_clients() {
+    let mut con = connect();
+    hset(&mut con, "hash-resp3", &[("a", "1")]);
+
+    let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+    send_raw(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n");
+    let response = send_raw(&mut stream, b"*2\r\n$7\r\nHGETALL\r\n$10\r\nhash-resp3\r\n");
+    assert_eq!(response, b"%1\r\n$1\r\na\r\n$1\r\n1\r\n");
+}
+
+#[test]
+fn it_refuses_hash_commands_on_other_types() {
+    let mut con = connect();
+    let _: String = redis::cmd("SET")
+        .arg("hash-string")
+        .arg("value")
+        .query(&mut con)
+        .unwrap();
+    hset(&mut con, "hash-typed", &[("a", "1")]);
+
+    let err = redis::cmd("HSET")
+        .arg("hash-string")
+        .arg(&["a", "1"])
+        .query::<i64>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+    let err = redis::cmd("HGET")
+        .arg("hash-string")
+        .arg("a")
+        .query::<Option<String>>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+    let err = redis::cmd("LPUSH")
+        .arg("hash-typed")
+        .arg("a")
+        .query::<i64>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+
+    let kind: String = redis::cmd("TYPE")
+        .arg("hash-typed")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "hash");
+}
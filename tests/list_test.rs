@@ -0,0 +1,396 @@
+mod common;
+
+use redis::{Client, Connection, RedisResult};
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    client.get_connection().unwrap()
+}
+
+fn push(con: &mut Connection, command: &str, key: &str, elements: &[&str]) -> i64 {
+    redis::cmd(command)
+        .arg(key)
+        .arg(elements)
+        .query(con)
+        .unwrap()
+}
+
+fn range(con: &mut Connection, key: &str, start: i64, stop: i64) -> Vec<String> {
+    redis::cmd("LRANGE")
+        .arg(key)
+        .arg(start)
+        .arg(stop)
+        .query(con)
+        .unwrap()
+}
+
+#[test]
+fn it_can_push_pop_and_read_lists() {
+    let mut con = connect();
+
+    assert_eq!(push(&mut con, "RPUSH", "list-basic", &["b", "c"]), 2);
+    assert_eq!(push(&mut con, "LPUSH", "list-basic", &["a", "z"]), 4);
+    assert_eq!(range(&mut con, "list-basic", 0, -1), ["z", "a", "b", "c"]);
+    assert_eq!(range(&mut con, "list-basic", -3, 1), ["a"]);
+    assert_eq!(range(&mut con, "list-basic", 2, 100), ["b", "c"]);
+    assert!(range(&mut con, "list-basic", 3, 1).is_empty());
+
+    let len: i64 = redis::cmd("LLEN")
+        .arg("list-basic")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(len, 4);
+    let last: Option<String> = redis::cmd("LINDEX")
+        .arg("list-basic")
+        .arg(-1)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(last.as_deref(), Some("c"));
+    let missing: Option<String> = redis::cmd("LINDEX")
+        .arg("list-basic")
+        .arg(4)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(missing, None);
+
+    let first: String = redis::cmd("LPOP")
+        .arg("list-basic")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(first, "z");
+    let last: Vec<String> = redis::cmd("RPOP")
+        .arg("list-basic")
+        .arg(2)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(last, ["c", "b"]);
+
+    // Popping the last element removes the key
+    let popped: Vec<String> = redis::cmd("LPOP")
+        .arg("list-basic")
+        .arg(5)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(popped, ["a"]);
+    let kind: String = redis::cmd("TYPE")
+        .arg("list-basic")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "none");
+    let popped: Option<String> = redis::cmd("LPOP")
+        .arg("list-basic")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(popped, None);
+    let popped: Option<Vec<String>> = redis::cmd("LPOP")
+        .arg("list-basic")
+        .arg(1)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(popped, None);
+
+    let err = redis::cmd("LPOP")
+        .arg("list-basic")
+        .arg(-1)
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("value is out of range, must be positive")
+    );
+}
+
+#[test]
+fn it_can_change_lists_in_place() {
+    let mut con = connect();
+    push(
+        &mut con,
+        "RPUSH",
+        "list-edit",
+        &["a", "x", "b", "x", "c", "x"],
+    );
+
+    let removed: i64 = redis::cmd("LREM")
+        .arg("list-edit")
+        .arg(-2)
+        .arg("x")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(removed, 2);
+    assert_eq!(range(&mut con, "list-edit", 0, -1), ["a", "x", "b", "c"]);
+
+    let _: String = redis::cmd("LSET")
+        .arg("list-edit")
+        .arg(-1)
+        .arg("d")
+        .query(&mut con)
+        .unwrap();
+    let err = redis::cmd("LSET")
+        .arg("list-edit")
+        .arg(10)
+        .arg("d")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("index out of range"));
+    let err = redis::cmd("LSET")
+        .arg("list-missing")
+        .arg(0)
+        .arg("d")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("no such key"));
+
+    let insert = |con: &mut Connection, place: &str, pivot: &str| -> i64 {
+        redis::cmd("LINSERT")
+            .arg("list-edit")
+            .arg(place)
+            .arg(pivot)
+            .arg("new")
+            .query(con)
+            .unwrap()
+    };
+    assert_eq!(insert(&mut con, "BEFORE", "x"), 5);
+    assert_eq!(insert(&mut con, "after", "d"), 6);
+    assert_eq!(insert(&mut con, "AFTER", "missing"), -1);
+    assert_eq!(
+        range(&mut con, "list-edit", 0, -1),
+        ["a", "new", "x", "b", "d", "new"]
+    );
+
+    let _: String = redis::cmd("LTRIM")
+        .arg("list-edit")
+        .arg(1)
+        .arg(-2)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(range(&mut con, "list-edit", 0, -1), ["new", "x", "b", "d"]);
+    let _: String = redis::cmd("LTRIM")
+        .arg("list-edit")
+        .arg(5)
+        .arg(10)
+        .query(&mut con)
+        .unwrap();
+    let len: i64 = redis::cmd("LLEN").arg("list-edit").query(&mut con).unwrap();
+    assert_eq!(len, 0);
+}
+
+#[test]
+fn it_can_move_elements_between_lists() {
+    let mut con = connect();
+    push(&mut con, "RPUSH", "list-queue", &["job1", "job2", "job3"]);
+
+    let lmove = |con: &mut Connection, source: &str, destination: &str, from: &str, to: &str| {
+        redis::cmd("LMOVE")
+            .arg(source)
+            .arg(destination)
+            .arg(from)
+            .arg(to)
+            .query::<Option<String>>(con)
+    };
+    assert_eq!(
+        lmove(&mut con, "list-queue", "list-processing", "LEFT", "RIGHT").unwrap(),
+        Some("job1".to_string())
+    );
+    assert_eq!(
+        lmove(&mut con, "list-queue", "list-processing", "LEFT", "RIGHT").unwrap(),
+        Some("job2".to_string())
+    );
+    assert_eq!(range(&mut con, "list-processing", 0, -1), ["job1", "job2"]);
+
+    // The same list rotates
+    assert_eq!(
+        lmove(
+            &mut con,
+            "list-processing",
+            "list-processing",
+            "RIGHT",
+            "LEFT"
+        )
+        .unwrap(),
+        Some("job2".to_string())
+    );
+    assert_eq!(range(&mut con, "list-processing", 0, -1), ["job2", "job1"]);
+
+    assert_eq!(
+        lmove(&mut con, "list-none", "list-queue", "LEFT", "LEFT").unwrap(),
+        None
+    );
+    let err = lmove(&mut con, "list-queue", "list-queue", "UP", "LEFT").unwrap_err();
+    assert_eq!(err.detail(), Some("syntax error"));
+}
+
+#[test]
+fn it_refuses_commands_on_the_wrong_type() {
+    let mut con = connect();
+    let _: String = redis::cmd("SET")
+        .arg("list-string")
+        .arg("value")
+        .query(&mut con)
+        .unwrap();
+    push(&mut con, "RPUSH", "list-wrong", &["a"]);
+
+    let wrong_type = |result: RedisResult<Option<String>>| {
+        let err = result.unwrap_err();
+        assert_eq!(err.code(), Some("WRONGTYPE"));
+        assert_eq!(
+            err.detail(),
+            Some("Operation against a key holding the wrong kind of value")
+        );
+    };
+    wrong_type(redis::cmd("GET").arg("list-wrong").query(&mut con));
+    wrong_type(
+        redis::cmd("SET")
+            .arg("list-wrong")
+            .arg("v")
+            .arg("GET")
+            .query(&mut con),
+    );
+    wrong_type(
+        redis::cmd("LPUSH")
+            .arg("list-string")
+            .arg("a")
+            .query(&mut con),
+    );
+    wrong_type(
+        redis::cmd("LRANGE")
+            .arg("list-string")
+            .arg(0)
+            .arg(-1)
+            .query(&mut con),
+    );
+    wrong_type(
+        redis::cmd("LMOVE")
+            .arg("list-wrong")
+            .arg("list-string")
+            .arg("LEFT")
+            .arg("LEFT")
+            .query(&mut con),
+    );
+    // Nothing moved
+    assert_eq!(range(&mut con, "list-wrong", 0, -1), ["a"]);
+
+    let kinds: Vec<String> = ["list-string", "list-wrong"]
+        .iter()
+        .map(|key| redis::cmd("TYPE").arg(key).query(&mut con).unwrap())
+        .collect();
+    assert_eq!(kinds, ["string", "list"]);
+
+    // SET replaces a value of any type
+    let _: String = redis::cmd("SET")
+        .arg("list-wrong")
+        .arg("v")
+        .query(&mut con)
+        .unwrap();
+    let value: String = redis::cmd("GET").arg("list-wrong").query(&mut con).unwrap();
+    assert_eq!(value, "v");
+}
+
+#[test]
+fn it_pops_straight_away_when_a_list_has_elements() {
+    let mut con = connect();
+    push(&mut con, "RPUSH", "list-ready", &["a", "b"]);
+
+    let popped: (String, String) = redis::cmd("BRPOP")
+        .arg("list-empty")
+        .arg("list-ready")
+        .arg(0)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(popped, ("list-ready".to_string(), "b".to_string()));
+    let moved: String = redis::cmd("BLMOVE")
+        .arg("list-ready")
+        .arg("list-ready-done")
+        .arg("LEFT")
+        .arg("RIGHT")
+        .arg(0)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(moved, "a");
+}
+
+#[test]
+fn it_times_out_when_nothing_is_pushed() {
+    let mut con = connect();
+
+    let start = Instant::now();
+    let popped: Option<(String, String)> = redis::cmd("BLPOP")
+        .arg("list-timeout")
+        .arg(0.2)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(popped, None);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    let moved: Option<String> = redis::cmd("BLMOVE")
+        .arg("list-timeout")
+        .arg("list-timeout-done")
+        .arg("LEFT")
+        .arg("LEFT")
+        .arg(0.1)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(moved, None);
+
+    let err = redis::cmd("BLPOP")
+        .arg("list-timeout")
+        .arg(-1)
+        .query::<Option<String>>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("timeout is negative"));
+    let err = redis::cmd("BLPOP")
+        .arg("list-timeout")
+        .arg("soon")
+        .query::<Option<String>>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("timeout is not a float or out of range"));
+}
+
+#[test]
+fn it_wakes_blocked_clients_in_the_order_they_blocked() {
+    let blocked_pop = |delay: u64| {
+        thread::sleep(Duration::from_millis(delay));
+        thread::spawn(|| {
+            let mut con = connect();
+            redis::cmd("BLPOP")
+                .arg("list-wake")
+                .arg(5)
+                .query::<(String, String)>(&mut con)
+                .unwrap()
+                .1
+        })
+    };
+    let first = blocked_pop(0);
+    let second = blocked_pop(100);
+    thread::sleep(Duration::from_millis(100));
+
+    let mut con = connect();
+    push(&mut con, "RPUSH", "list-wake", &["a", "b"]);
+    assert_eq!(first.join().unwrap(), "a");
+    assert_eq!(second.join().unwrap(), "b");
+}
+
+#[test]
+fn it_moves_an_element_pushed_while_blocked() {
+    let blocked = thread::spawn(|| {
+        let mut con = connect();
+        redis::cmd("BLMOVE")
+            .arg("list-blmove")
+            .arg("list-blmove-done")
+            .arg("RIGHT")
+            .arg("LEFT")
+            .arg(0)
+            .query::<String>(&mut con)
+            .unwrap()
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    let mut con = connect();
+    push(&mut con, "LPUSH", "list-blmove", &["job"]);
+    assert_eq!(blocked.join().unwrap(), "job");
+    assert_eq!(range(&mut con, "list-blmove-done", 0, -1), ["job"]);
+    assert!(range(&mut con, "list-blmove", 0, -1).is_empty());
+}
@@ -0,0 +1,209 @@
+mod common;
+
+use common::send_raw;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+// Inputs that can never become a valid message, along with the error they must produce
+const MALFORMED: &[(&[u8], &str)] = &[
+    (b"*abc\r\n", "invalid multibulk length"),
+    (b"*-5\r\n", "invalid multibulk length"),
+    (b"*1\r\n$abc\r\n", "invalid bulk length"),
+    (b"*1\r\n$99999999999999999999999\r\n", "invalid bulk length"),
+    (b"*1\r\n$3\r\nfoobar\r\n", "expected CRLF after bulk string"),
+    (b"*1\r\n:12a\r\n", "invalid integer"),
+    (b"*1\r\n#x\r\n", "invalid boolean"),
+    (b"*1\r\n,one\r\n", "invalid double"),
+    (b"*1\r\n=2\r\nab\r\n", "invalid verbatim string"),
+    (b"*1\r\n+\xff\xfe\r\n", "invalid UTF-8 in simple string"),
+    (b"*1\r\n!3\r\nerr\r\n", "unknown message type '!'"),
+];
+
+const VALID: &[&[u8]] = &[
+    b"*1\r\n$4\r\nPING\r\n",
+    b"*2\r\n$4\r\nECHO\r\n$5\r\nhello\r\n",
+    b"*2\r\n$3\r\nGET\r\n$4\r\nfuzz\r\n",
+    b"*3\r\n$3\r\nSET\r\n$4\r\nfuzz\r\n$5\r\nvalue\r\n",
+];
+
+fn connect() -> TcpStream {
+    let stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    stream
+}
+
+// Reads until the server hangs up, returning false if it kept the connection open
+fn is_closed(stream: &mut TcpStream) -> bool {
+    let mut buffer = [0; 4096];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return true,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::ConnectionReset => return true,
+            Err(_) => return false,
+        }
+    }
+}
+
+fn assert_alive() {
+    let mut stream = connect();
+    assert_eq!(send_raw(&mut stream, b"*1\r\n$4\r\nPING\r\n"), b"+PONG\r\n");
+}
+
+#[test]
+fn it_reports_malformed_input_and_closes_the_connection() {
+    for (input, error) in MALFORMED {
+        let mut stream = connect();
+        let response = send_raw(&mut stream, input);
+
+        assert_eq!(
+            String::from_utf8_lossy(&response),
+            format!("-ERR Protocol error: {}\r\n", error)
+        );
+        assert!(is_closed(&mut stream));
+    }
+    assert_alive();
+}
+
+#[test]
+fn it_enforces_protocol_limits() {
+    let deeply_nested = b"*1\r\n".repeat(100);
+    // Just over the limit, so the server has read all of it by the time it hangs up
+    let long_inline = b"a".repeat(64 * 1024 + 1);
+    let long_header = [&b"*"[..], &b"1".repeat(64 * 1024 + 1)].concat();
+    let inputs: [(&[u8], &str); 5] = [
+        (b"*1\r\n$1000000000000\r\n", "bulk length exceeds proto-max-bulk-len"),
+        (b"*100000000\r\n", "multibulk length exceeds proto-max-multibulk-len"),
+        (&deeply_nested, "nesting exceeds proto-max-nesting-depth"),
+        (&long_inline, "line exceeds proto-max-inline-len"),
+        (&long_header, "line exceeds proto-max-inline-len"),
+    ];
+
+    for (input, error) in inputs {
+        let mut stream = connect();
+        // The server may reject the input before all of it has been written
+        let _ = stream.write_all(input);
+        let mut response = vec![];
+        let _ = stream.read_to_end(&mut response);
+
+        assert_eq!(
+            String::from_utf8_lossy(&response),
+            format!("-ERR Protocol error: {}\r\n", error)
+        );
+    }
+    assert_alive();
+}
+
+#[test]
+fn it_accepts_nesting_up_to_the_limit() {
+    let mut stream = connect();
+    // ECHO's argument sits one level down, so this reaches the default depth of 32
+    let nested = [b"*1\r\n".repeat(31), b":7\r\n".to_vec()].concat();
+    let input = [&b"*2\r\n$4\r\nECHO\r\n"[..], &nested].concat();
+
+    assert_eq!(send_raw(&mut stream, &input), nested);
+}
+
+#[test]
+fn it_answers_commands_sent_before_malformed_input() {
+    let mut stream = connect();
+    let response = send_raw(&mut stream, b"*1\r\n$4\r\nPING\r\n*abc\r\n");
+
+    assert_eq!(
+        response,
+        b"+PONG\r\n-ERR Protocol error: invalid multibulk length\r\n"
+    );
+    assert!(is_closed(&mut stream));
+}
+
+#[test]
+fn it_accepts_inline_commands() {
+    let mut stream = connect();
+
+    assert_eq!(send_raw(&mut stream, b"PING\r\n"), b"+PONG\r\n");
+    assert_eq!(send_raw(&mut stream, b"ping\n"), b"+PONG\r\n");
+    assert_eq!(
+        send_raw(&mut stream, b"SET inline \"hello \\\"world\\\"\\x21\"\r\n"),
+        b"+OK\r\n"
+    );
+    assert_eq!(
+        send_raw(&mut stream, b"  GET   'inline'\r\n"),
+        b"$14\r\nhello \"world\"!\r\n"
+    );
+    assert_eq!(
+        send_raw(&mut stream, b"\r\nECHO 'it\\'s'\r\n"),
+        b"$4\r\nit's\r\n"
+    );
+}
+
+#[test]
+fn it_rejects_inline_commands_with_unbalanced_quotes() {
+    for input in [&b"SET \"foo bar\r\n"[..], b"ECHO 'foo\r\n", b"ECHO \"foo\"bar\r\n"] {
+        let mut stream = connect();
+        assert_eq!(
+            send_raw(&mut stream, input),
+            b"-ERR Protocol error: unbalanced quotes in request\r\n"
+        );
+        assert!(is_closed(&mut stream));
+    }
+}
+
+#[test]
+fn it_round_trips_negative_integers_and_nulls() {
+    let mut stream = connect();
+
+    assert_eq!(
+        send_raw(&mut stream, b"*2\r\n$4\r\nECHO\r\n:-42\r\n"),
+        b":-42\r\n"
+    );
+    assert_eq!(
+        send_raw(&mut stream, b"*2\r\n$4\r\nECHO\r\n$-1\r\n"),
+        b"$-1\r\n"
+    );
+    assert_eq!(
+        send_raw(&mut stream, b"*2\r\n$4\r\nECHO\r\n*-1\r\n"),
+        b"*-1\r\n"
+    );
+
+    // Null requests are ignored without a reply
+    assert_eq!(
+        send_raw(&mut stream, b"*-1\r\n*0\r\n*1\r\n$4\r\nPING\r\n"),
+        b"+PONG\r\n"
+    );
+
+    // RESP3 sends both kinds of null as the Null type
+    send_raw(&mut stream, b"HELLO 3\r\n");
+    assert_eq!(
+        send_raw(&mut stream, b"*2\r\n$4\r\nECHO\r\n*-1\r\n"),
+        b"_\r\n"
+    );
+}
+
+#[test]
+fn it_survives_mutated_commands() {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    for _ in 0..100 {
+        let mut input = VALID[rng.gen_range(0..VALID.len())].to_vec();
+        for _ in 0..rng.gen_range(1..4) {
+            let position = rng.gen_range(0..input.len());
+            match rng.gen_range(0..3) {
+                0 => input[position] = rng.gen(),
+                1 => input.insert(position, rng.gen()),
+                _ => input.truncate(position.max(1)),
+            }
+        }
+
+        // The server may hang up before all of the input has been written
+        let mut stream = connect();
+        let _ = stream.write_all(&input);
+        is_closed(&mut stream);
+    }
+    assert_alive();
+}
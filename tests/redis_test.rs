@@ -1,31 +1,14 @@
-use assert_cmd::prelude::CommandCargoExt;
+mod common;
+
+use common::send_raw;
 use redis::Client;
 use std::{
-    process::{Command, Stdio},
+    io::{Read, Write},
+    net::TcpStream,
     thread::sleep,
     time::Duration,
 };
 
-static mut PROC: Option<std::process::Child> = None;
-
-#[ctor::ctor]
-fn start_server() {
-    let mut cmd = Command::cargo_bin("tinyredis").unwrap();
-    unsafe {
-        PROC = Some(cmd.stdout(Stdio::null()).spawn().unwrap());
-    }
-    sleep(Duration::from_millis(100));
-}
-
-#[ctor::dtor]
-fn stop_server() {
-    unsafe {
-        if let Some(ref mut proc) = PROC {
-            proc.kill().unwrap();
-        }
-    }
-}
-
 #[test]
 fn it_can_connect_to_redis() {
     let client = Client::open("redis://127.0.0.1/").unwrap();
@@ -143,9 +126,6 @@ fn it_can_get_and_set_with_lru() {
 
     let value: String = redis::cmd("GET").arg("key").query(&mut con2).unwrap();
     assert_eq!(value, "value");
-
-    //it_can_handle_lru();
-    it_can_handle_lru();
 }
 #[test]
 fn it_can_set_with_ttl() {
@@ -176,64 +156,318 @@ fn it_can_set_with_ttl() {
 }
 
 #[test]
-fn it_can_handle_del() {
+fn it_can_set_with_options() {
     let client = Client::open("redis://127.0.0.1/").unwrap();
     let mut con = client.get_connection().unwrap();
 
+    // SET NX PX is how distributed locks are taken
+    let set = |con: &mut redis::Connection, args: &[&str]| {
+        redis::cmd("SET").arg("lock").arg(args).query::<Option<String>>(con)
+    };
+    assert_eq!(set(&mut con, &["a", "NX", "PX", "30000"]).unwrap(), Some("OK".to_string()));
+    assert_eq!(set(&mut con, &["b", "NX", "PX", "30000"]).unwrap(), None);
+
+    assert_eq!(set(&mut con, &["c", "XX", "GET", "KEEPTTL"]).unwrap(), Some("a".to_string()));
+    assert_eq!(set(&mut con, &["d", "GET"]).unwrap(), Some("c".to_string()));
+    assert_eq!(set(&mut con, &["e", "EX", "1"]).unwrap(), Some("OK".to_string()));
+    sleep(Duration::from_millis(1100));
+    assert_eq!(set(&mut con, &["f", "XX"]).unwrap(), None);
+
+    for (args, error) in [
+        (&["v", "NX", "XX"][..], "syntax error"),
+        (&["v", "EX", "1", "PX", "1"], "syntax error"),
+        (&["v", "KEEPTTL", "EX", "1"], "syntax error"),
+        (&["v", "EX"], "syntax error"),
+        (&["v", "EX", "soon"], "value is not an integer or out of range"),
+        (&["v", "PX", "0"], "invalid expire time in 'set' command"),
+        (&["v", "EX", "9223372036854775807"], "invalid expire time in 'set' command"),
+    ] {
+        let err = set(&mut con, args).unwrap_err();
+        assert_eq!(err.detail(), Some(error), "SET lock {:?}", args);
+    }
+}
+
+#[test]
+fn it_can_change_and_inspect_expiry() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
     let _ = redis::cmd("SET")
-        .arg("del2")
-        .arg("del2")
+        .arg("session")
+        .arg("data")
         .query::<String>(&mut con)
         .unwrap();
+    let mut query = |args: &[&str]| -> redis::RedisResult<i64> {
+        redis::cmd(args[0]).arg(&args[1..]).query(&mut con)
+    };
+    assert_eq!(query(&["TTL", "session"]).unwrap(), -1);
+    assert_eq!(query(&["EXPIRE", "session", "100", "XX"]).unwrap(), 0);
+    assert_eq!(query(&["EXPIRE", "session", "100", "GT"]).unwrap(), 0);
+    assert_eq!(query(&["EXPIRE", "session", "100", "NX"]).unwrap(), 1);
+    assert_eq!(query(&["TTL", "session"]).unwrap(), 100);
+
+    // Refreshing only ever pushes the expiry further out with GT
+    assert_eq!(query(&["EXPIRE", "session", "200", "GT"]).unwrap(), 1);
+    assert_eq!(query(&["EXPIRE", "session", "50", "GT"]).unwrap(), 0);
+    assert_eq!(query(&["PEXPIRE", "session", "50000", "LT"]).unwrap(), 1);
+    let pttl = query(&["PTTL", "session"]).unwrap();
+    assert!(pttl > 49000 && pttl <= 50000);
+
+    let at = query(&["EXPIRETIME", "session"]).unwrap();
+    assert_eq!(query(&["PEXPIREAT", "session", &(at * 1000 + 5000).to_string()]).unwrap(), 1);
+    assert_eq!(query(&["PEXPIRETIME", "session"]).unwrap(), at * 1000 + 5000);
+
+    assert_eq!(query(&["PERSIST", "session"]).unwrap(), 1);
+    assert_eq!(query(&["PERSIST", "session"]).unwrap(), 0);
+    assert_eq!(query(&["EXPIRETIME", "session"]).unwrap(), -1);
+
+    // An expiry in the past deletes the key
+    assert_eq!(query(&["EXPIREAT", "session", "1"]).unwrap(), 1);
+    assert_eq!(query(&["TTL", "session"]).unwrap(), -2);
+    assert_eq!(query(&["EXPIRE", "session", "100"]).unwrap(), 0);
+
+    for (args, error) in [
+        (
+            &["EXPIRE", "session", "1", "NX", "GT"][..],
+            "NX and XX, GT or LT options at the same time are not compatible",
+        ),
+        (
+            &["EXPIRE", "session", "1", "GT", "LT"],
+            "GT and LT options at the same time are not compatible",
+        ),
+        (&["EXPIRE", "session", "1", "SOON"], "Unsupported option SOON"),
+        (&["EXPIRE", "session", "9223372036854775807"], "invalid expire time in 'expire' command"),
+        (&["PEXPIRE", "session", "later"], "value is not an integer or out of range"),
+    ] {
+        let err = query(args).unwrap_err();
+        assert_eq!(err.detail(), Some(error), "{:?}", args);
+    }
+}
 
-    let value: String = redis::cmd("GET").arg("del2").query(&mut con).unwrap();
-    assert_eq!(value, "del2");
+#[test]
+fn it_can_expire_keys_in_the_background() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+    let expired_keys = |con: &mut redis::Connection| {
+        let info: String = redis::cmd("INFO").arg("stats").query(con).unwrap();
+        assert!(info.starts_with("# Stats\r\n"));
+        info.lines()
+            .find_map(|line| line.strip_prefix("expired_keys:"))
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+    };
+
+    // The key is never looked up again, so only the expiry cycle can remove it.
+    // Other tests may evict it from the tiny cache first, so allow a few attempts.
+    let expired = (0..5).any(|_| {
+        let before = expired_keys(&mut con);
+        let _ = redis::cmd("SET")
+            .arg("write-once")
+            .arg("value")
+            .arg("PX")
+            .arg(50)
+            .query::<String>(&mut con)
+            .unwrap();
+        sleep(Duration::from_millis(400));
+        expired_keys(&mut con) > before
+    });
+    assert!(expired);
+}
 
-    let del: String = redis::cmd("DEL").arg("del2 del3").query(&mut con).unwrap();
-    assert_eq!(del, "1");
+#[test]
+fn it_can_handle_pipelined_commands() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+
+    let (ping, echo, echo2): (String, String, String) = redis::pipe()
+        .cmd("PING")
+        .cmd("ECHO")
+        .arg("first")
+        .cmd("ECHO")
+        .arg("second")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(ping, "PONG");
+    assert_eq!(echo, "first");
+    assert_eq!(echo2, "second");
 }
 
-fn it_can_handle_lru() {
+#[test]
+fn it_can_handle_values_larger_than_a_read() {
     let client = Client::open("redis://127.0.0.1/").unwrap();
     let mut con = client.get_connection().unwrap();
 
+    let large = "x".repeat(64 * 1024);
+    let echo: String = redis::cmd("ECHO").arg(&large).query(&mut con).unwrap();
+    assert_eq!(echo, large);
+}
+
+#[test]
+fn it_can_handle_commands_split_across_writes() {
+    let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+
+    stream.write_all(b"*2\r\n$4\r\nEC").unwrap();
+    stream.flush().unwrap();
+    sleep(Duration::from_millis(50));
+    stream.write_all(b"HO\r\n$5\r\nhello\r\n").unwrap();
+
+    let mut response = [0; 11];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(&response, b"$5\r\nhello\r\n");
+}
+
+#[test]
+fn it_can_store_binary_values() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+
+    let key: &[u8] = b"binary-\xff\xfe";
+    let binary: &[u8] = b"\x00\x9f\x92\x96\xff\r\n\xc3\x28";
     let _ = redis::cmd("SET")
-        .arg("over1")
-        .arg("value1")
+        .arg(key)
+        .arg(binary)
         .query::<String>(&mut con)
         .unwrap();
 
-    let _ = redis::cmd("SET")
-        .arg("over2")
-        .arg("value2")
-        .query::<String>(&mut con)
+    let value: Vec<u8> = redis::cmd("GET").arg(key).query(&mut con).unwrap();
+    assert_eq!(value, binary);
+}
+
+#[test]
+fn it_can_switch_protocol_with_hello() {
+    let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+
+    let response = send_raw(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n");
+    assert!(response.starts_with(b"%7\r\n$6\r\nserver\r\n$9\r\ntinyredis\r\n"));
+    let proto = b"$5\r\nproto\r\n:3\r\n";
+    assert!(response.windows(proto.len()).any(|w| w == proto));
+
+    let response = send_raw(&mut stream, b"*2\r\n$3\r\nGET\r\n$13\r\nmissing-resp3\r\n");
+    assert_eq!(response, b"_\r\n");
+
+    let response = send_raw(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n2\r\n");
+    assert!(response.starts_with(b"*14\r\n"));
+
+    let response = send_raw(&mut stream, b"*2\r\n$3\r\nGET\r\n$13\r\nmissing-resp3\r\n");
+    assert_eq!(response, b"$-1\r\n");
+
+    let response = send_raw(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n4\r\n");
+    assert_eq!(response, b"-NOPROTO unsupported protocol version\r\n");
+}
+
+#[test]
+fn it_reports_unknown_commands_and_wrong_arity() {
+    let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+
+    let response = send_raw(&mut stream, b"*3\r\n$4\r\nNOPE\r\n$1\r\na\r\n$1\r\nb\r\n");
+    assert_eq!(
+        response,
+        b"-ERR unknown command 'NOPE', with args beginning with: 'a' 'b' \r\n"
+    );
+
+    let response = send_raw(&mut stream, b"*1\r\n$4\r\nECHO\r\n");
+    assert_eq!(
+        response,
+        b"-ERR wrong number of arguments for 'echo' command\r\n"
+    );
+
+    let response = send_raw(&mut stream, b"*2\r\n$3\r\nset\r\n$1\r\nk\r\n");
+    assert_eq!(
+        response,
+        b"-ERR wrong number of arguments for 'set' command\r\n"
+    );
+
+    // The connection stays usable after either error
+    assert_eq!(send_raw(&mut stream, b"PING\r\n"), b"+PONG\r\n");
+}
+
+#[test]
+fn it_can_describe_commands() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
+
+    let count: i64 = redis::cmd("COMMAND").arg("COUNT").query(&mut con).unwrap();
+    let all: Vec<redis::Value> = redis::cmd("COMMAND").query(&mut con).unwrap();
+    assert_eq!(all.len() as i64, count);
+
+    let info: Vec<redis::Value> = redis::cmd("COMMAND")
+        .arg("INFO")
+        .arg("get")
+        .arg("no-such-command")
+        .query(&mut con)
         .unwrap();
+    assert_eq!(info[1], redis::Value::Nil);
+    let redis::Value::Bulk(fields) = &info[0] else {
+        panic!("unexpected COMMAND INFO entry {:?}", info[0]);
+    };
+    let (name, arity, flags, first_key, last_key, step): (String, i64, Vec<String>, i64, i64, i64) =
+        redis::from_redis_value(&redis::Value::Bulk(fields[..6].to_vec())).unwrap();
+    assert_eq!(name, "get");
+    assert_eq!(arity, 2);
+    assert_eq!(flags, ["readonly", "fast"]);
+    assert_eq!((first_key, last_key, step), (1, 1, 1));
+}
+
+#[test]
+fn it_can_handle_del() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let mut con = client.get_connection().unwrap();
 
     let _ = redis::cmd("SET")
-        .arg("over3")
-        .arg("value3")
+        .arg("del2")
+        .arg("del2")
         .query::<String>(&mut con)
         .unwrap();
 
-    let _ = redis::cmd("SET")
-        .arg("over4")
-        .arg("value4")
-        .query::<String>(&mut con)
+    let value: String = redis::cmd("GET").arg("del2").query(&mut con).unwrap();
+    assert_eq!(value, "del2");
+
+    let del: i64 = redis::cmd("DEL").arg(&["del2", "del3"]).query(&mut con).unwrap();
+    assert_eq!(del, 1);
+
+    // Every argument is a single key, whatever bytes it holds
+    for key in ["del 4", " del5"] {
+        let _: String = redis::cmd("SET").arg(key).arg("v").query(&mut con).unwrap();
+    }
+    let del: i64 = redis::cmd("DEL")
+        .arg(&["del 4", "del5", " del5"])
+        .query(&mut con)
         .unwrap();
+    assert_eq!(del, 2);
+}
 
-    // over4 have smallest aging, over1 have largest againg, LRU
-    let value: String = redis::cmd("GET").arg("over4").query(&mut con).unwrap();
-    assert_eq!(value, "value4");
+#[test]
+fn it_can_delete_keys_held_by_different_shards_at_once() {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    let keys: Vec<String> = (0..32).map(|i| format!("multi{}", i)).collect();
+
+    // Each connection deletes the same keys in a different order while the others write
+    // them, which would deadlock if the shards were not always locked in the same order
+    let handles: Vec<_> = (0..4)
+        .map(|i| {
+            let mut con = client.get_connection().unwrap();
+            let mut keys = keys.clone();
+            keys.rotate_left(i * 8);
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    for key in &keys[..4] {
+                        let _: String = redis::cmd("SET").arg(key).arg("v").query(&mut con).unwrap();
+                    }
+                    let _: i64 = redis::cmd("DEL").arg(&keys).query(&mut con).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
 
-    // LRU Policy remove over1
-    let err = redis::cmd("GET")
-        .arg("over1")
-        .query::<String>(&mut con)
-        .unwrap_err();
-    assert_eq!(
-        err.detail().unwrap(),
-        "\"Response type not string compatible.\" (response was nil)"
-    );
+    let mut con = client.get_connection().unwrap();
+    for key in &keys {
+        let _: String = redis::cmd("SET").arg(key).arg("v").query(&mut con).unwrap();
+    }
+    let del: i64 = redis::cmd("DEL").arg(&keys).query(&mut con).unwrap();
+    assert_eq!(del, 32);
 }
 
 #[test]
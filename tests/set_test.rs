@@ -0,0 +1,292 @@
+mod common;
+
+use redis::{Client, Connection};
+use std::collections::HashSet;
+
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    client.get_connection().unwrap()
+}
+
+fn sadd(con: &mut Connection, key: &str, members: &[&str]) -> i64 {
+    redis::cmd("SADD").arg(key).arg(members).query(con).unwrap()
+}
+
+fn members(con: &mut Connection, key: &str) -> HashSet<String> {
+    redis::cmd("SMEMBERS").arg(key).query(con).unwrap()
+}
+
+fn set_of(members: &[&str]) -> HashSet<String> {
+    members.iter().map(|member| member.to_string()).collect()
+}
+
+fn encoding(con: &mut Connection, key: &str) -> String {
+    redis::cmd("OBJECT")
+        .arg("ENCODING")
+        .arg(key)
+        .query(con)
+        .unwrap()
+}
+
+#[test]
+fn it_can_add_check_and_remove_members() {
+    let mut con = connect();
+
+    assert_eq!(sadd(&mut con, "set-flags", &["beta", "dark", "beta"]), 2);
+    assert_eq!(sadd(&mut con, "set-flags", &["dark", "new"]), 1);
+    assert_eq!(
+        members(&mut con, "set-flags"),
+        set_of(&["beta", "dark", "new"])
+    );
+
+    let card: i64 = redis::cmd("SCARD")
+        .arg("set-flags")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(card, 3);
+    let found: Vec<i64> = ["dark", "old"]
+        .iter()
+        .map(|member| {
+            redis::cmd("SISMEMBER")
+                .arg("set-flags")
+                .arg(member)
+                .query(&mut con)
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(found, [1, 0]);
+
+    let removed: i64 = redis::cmd("SREM")
+        .arg("set-flags")
+        .arg(&["beta", "old", "dark"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(removed, 2);
+    let _: i64 = redis::cmd("SREM")
+        .arg("set-flags")
+        .arg("new")
+        .query(&mut con)
+        .unwrap();
+    // Removing the last member removes the key
+    let kind: String = redis::cmd("TYPE").arg("set-flags").query(&mut con).unwrap();
+    assert_eq!(kind, "none");
+    let card: i64 = redis::cmd("SCARD")
+        .arg("set-flags")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(card, 0);
+}
+
+#[test]
+fn it_keeps_integers_in_an_intset() {
+    let mut con = connect();
+
+    sadd(&mut con, "set-ints", &["3", "-1", "2", "3"]);
+    assert_eq!(encoding(&mut con, "set-ints"), "intset");
+    // An intset is sorted
+    let ordered: Vec<String> = redis::cmd("SMEMBERS")
+        .arg("set-ints")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(ordered, ["-1", "2", "3"]);
+    let found: i64 = redis::cmd("SISMEMBER")
+        .arg("set-ints")
+        .arg("02")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(found, 0);
+
+    // A member that isn't written as an integer converts it
+    sadd(&mut con, "set-ints", &["02"]);
+    assert_eq!(encoding(&mut con, "set-ints"), "hashtable");
+    assert_eq!(
+        members(&mut con, "set-ints"),
+        set_of(&["-1", "2", "3", "02"])
+    );
+
+    // So do too many integers
+    let many: Vec<String> = (0..513).map(|i| i.to_string()).collect();
+    let added: i64 = redis::cmd("SADD")
+        .arg("set-many")
+        .arg(&many)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(added, 513);
+    assert_eq!(encoding(&mut con, "set-many"), "hashtable");
+}
+
+#[test]
+fn it_can_combine_sets() {
+    let mut con = connect();
+    sadd(&mut con, "{set}a", &["1", "2", "3", "x"]);
+    sadd(&mut con, "{set}b", &["2", "3", "4"]);
+    sadd(&mut con, "{set}c", &["3", "x", "y"]);
+
+    let combine = |con: &mut Connection, command: &str, keys: &[&str]| -> HashSet<String> {
+        redis::cmd(command).arg(keys).query(con).unwrap()
+    };
+    assert_eq!(
+        combine(&mut con, "SINTER", &["{set}a", "{set}b", "{set}c"]),
+        set_of(&["3"])
+    );
+    assert_eq!(
+        combine(&mut con, "SINTER", &["{set}a", "{set}missing"]),
+        set_of(&[])
+    );
+    assert_eq!(
+        combine(&mut con, "SUNION", &["{set}b", "{set}c", "{set}missing"]),
+        set_of(&["2", "3", "4", "x", "y"])
+    );
+    assert_eq!(
+        combine(&mut con, "SDIFF", &["{set}a", "{set}b", "{set}c"]),
+        set_of(&["1"])
+    );
+    assert_eq!(
+        combine(&mut con, "SDIFF", &["{set}missing", "{set}a"]),
+        set_of(&[])
+    );
+
+    let store = |con: &mut Connection, command: &str, keys: &[&str]| -> i64 {
+        redis::cmd(command).arg(keys).query(con).unwrap()
+    };
+    assert_eq!(
+        store(&mut con, "SUNIONSTORE", &["{set}out", "{set}a", "{set}b"]),
+        5
+    );
+    assert_eq!(
+        members(&mut con, "{set}out"),
+        set_of(&["1", "2", "3", "4", "x"])
+    );
+    assert_eq!(
+        store(&mut con, "SINTERSTORE", &["{set}out", "{set}a", "{set}b"]),
+        2
+    );
+    assert_eq!(members(&mut con, "{set}out"), set_of(&["2", "3"]));
+    assert_eq!(encoding(&mut con, "{set}out"), "intset");
+
+    // The destination may be one of the sources, and is replaced whatever it held
+    let _: String = redis::cmd("SET")
+        .arg("{set}string")
+        .arg("v")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(
+        store(&mut con, "SDIFFSTORE", &["{set}string", "{set}a", "{set}b"]),
+        2
+    );
+    assert_eq!(members(&mut con, "{set}string"), set_of(&["1", "x"]));
+    assert_eq!(
+        store(&mut con, "SDIFFSTORE", &["{set}a", "{set}a", "{set}b"]),
+        2
+    );
+    assert_eq!(members(&mut con, "{set}a"), set_of(&["1", "x"]));
+
+    // An empty result deletes the destination
+    assert_eq!(
+        store(
+            &mut con,
+            "SINTERSTORE",
+            &["{set}out", "{set}a", "{set}missing"]
+        ),
+        0
+    );
+    let kind: String = redis::cmd("TYPE").arg("{set}out").query(&mut con).unwrap();
+    assert_eq!(kind, "none");
+
+    let _: String = redis::cmd("SET")
+        .arg("{set}string")
+        .arg("v")
+        .query(&mut con)
+        .unwrap();
+    let err = redis::cmd("SUNION")
+        .arg(&["{set}b", "{set}string"])
+        .query::<HashSet<String>>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn it_can_pick_random_members() {
+    let mut con = connect();
+    let all = ["a", "b", "c", "d", "e"];
+    sadd(&mut con, "set-random", &all);
+
+    let one: String = redis::cmd("SRANDMEMBER")
+        .arg("set-random")
+        .query(&mut con)
+        .unwrap();
+    assert!(all.contains(&one.as_str()));
+    let distinct: Vec<String> = redis::cmd("SRANDMEMBER")
+        .arg("set-random")
+        .arg(3)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(distinct.len(), 3);
+    assert_eq!(distinct.iter().collect::<HashSet<_>>().len(), 3);
+    let everything: HashSet<String> = redis::cmd("SRANDMEMBER")
+        .arg("set-random")
+        .arg(10)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(everything, set_of(&all));
+    let repeated: Vec<String> = redis::cmd("SRANDMEMBER")
+        .arg("set-random")
+        .arg(-20)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(repeated.len(), 20);
+    assert!(repeated.iter().all(|member| all.contains(&member.as_str())));
+    let missing: Option<String> = redis::cmd("SRANDMEMBER")
+        .arg("set-none")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(missing, None);
+
+    let popped: String = redis::cmd("SPOP")
+        .arg("set-random")
+        .query(&mut con)
+        .unwrap();
+    let popped_more: HashSet<String> = redis::cmd("SPOP")
+        .arg("set-random")
+        .arg(2)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(popped_more.len(), 2);
+    assert!(!popped_more.contains(&popped));
+    let left = members(&mut con, "set-random");
+    assert_eq!(left.len(), 2);
+    assert!(!left.contains(&popped) && left.is_disjoint(&popped_more));
+
+    // Popping every member removes the key
+    let rest: HashSet<String> = redis::cmd("SPOP")
+        .arg("set-random")
+        .arg(5)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(rest, left);
+    let kind: String = redis::cmd("TYPE")
+        .arg("set-random")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "none");
+
+    let err = redis::cmd("SPOP")
+        .arg("set-random")
+        .arg(-1)
+        .query::<Vec<String>>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("value is out of range, must be positive")
+    );
+    // A count too large to pick that many members is refused up front
+    sadd(&mut con, "set-random", &["a"]);
+    for count in [-9223372036854775807, i64::MIN, i64::MAX] {
+        let err = redis::cmd("SRANDMEMBER")
+            .arg("set-random")
+            .arg(count)
+            .query::<Vec<String>>(&mut con)
+            .unwrap_err();
+        assert_eq!(err.detail(), Some("value is out of range"));
+    }
+}
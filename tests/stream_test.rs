@@ -0,0 +1,660 @@
+mod common;
+
+use common::send_raw;
+use redis::{Client, Connection, RedisResult};
+use std::{
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+// An entry's ID and its fields and values one after the other
+type Entry = (String, Vec<String>);
+
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    client.get_connection().unwrap()
+}
+
+fn xadd(con: &mut Connection, key: &str, args: &[&str]) -> RedisResult<String> {
+    redis::cmd("XADD").arg(key).arg(args).query(con)
+}
+
+fn xrange(con: &mut Connection, key: &str, args: &[&str]) -> Vec<Entry> {
+    entries(redis::cmd("XRANGE").arg(key).arg(args).query(con).unwrap())
+}
+
+// redis-rs reads a list of pairs as one flat list, so entries are read one at a time
+fn entries(reply: Vec<redis::Value>) -> Vec<Entry> {
+    reply
+        .iter()
+        .map(|entry| redis::from_redis_value(entry).unwrap())
+        .collect()
+}
+
+// The pending entries XPENDING lists with a range, as their ID, consumer and deliveries
+fn pending(con: &mut Connection, args: &[&str]) -> Vec<(String, String, i64)> {
+    let reply: Vec<redis::Value> = redis::cmd("XPENDING").arg(args).query(con).unwrap();
+    reply
+        .iter()
+        .map(|pending| {
+            let (id, consumer, _, deliveries): (String, String, i64, i64) =
+                redis::from_redis_value(pending).unwrap();
+            (id, consumer, deliveries)
+        })
+        .collect()
+}
+
+// The entries XREAD or XREADGROUP read from each stream, or None for nil
+fn streams(reply: redis::Value) -> Option<Vec<(String, Vec<Entry>)>> {
+    let redis::Value::Bulk(streams) = reply else {
+        return None;
+    };
+    let streams = streams.iter().map(|stream| {
+        let (key, read): (String, Vec<redis::Value>) = redis::from_redis_value(stream).unwrap();
+        (key, entries(read))
+    });
+    Some(streams.collect())
+}
+
+fn ids(entries: &[Entry]) -> Vec<&str> {
+    entries.iter().map(|(id, _)| id.as_str()).collect()
+}
+
+fn entry(id: &str, fields: &[&str]) -> Entry {
+    (
+        id.to_string(),
+        fields.iter().map(|field| field.to_string()).collect(),
+    )
+}
+
+#[test]
+fn it_can_add_and_read_entries() {
+    let mut con = connect();
+
+    let id = xadd(&mut con, "stream-log", &["*", "level", "info"]).unwrap();
+    let (ms, seq) = id.split_once('-').unwrap();
+    assert!(ms.parse::<u64>().unwrap() > 0);
+    assert_eq!(seq, "0");
+    let next = xadd(&mut con, "stream-log", &["*", "level", "warn"]).unwrap();
+    assert!(xrange(&mut con, "stream-log", &["-", "+"])
+        .iter()
+        .map(|(id, _)| id)
+        .eq([&id, &next]));
+
+    assert_eq!(
+        xadd(&mut con, "stream-ids", &["5-1", "a", "1"]).unwrap(),
+        "5-1"
+    );
+    assert_eq!(
+        xadd(&mut con, "stream-ids", &["5-*", "b", "2"]).unwrap(),
+        "5-2"
+    );
+    assert_eq!(
+        xadd(&mut con, "stream-ids", &["7-*", "c", "3"]).unwrap(),
+        "7-0"
+    );
+    assert_eq!(
+        xadd(&mut con, "stream-ids", &["9", "d", "4"]).unwrap(),
+        "9-0"
+    );
+    let len: i64 = redis::cmd("XLEN")
+        .arg("stream-ids")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(len, 4);
+
+    assert_eq!(
+        xrange(&mut con, "stream-ids", &["-", "+"]),
+        [
+            entry("5-1", &["a", "1"]),
+            entry("5-2", &["b", "2"]),
+            entry("7-0", &["c", "3"]),
+            entry("9-0", &["d", "4"]),
+        ]
+    );
+    // Without a sequence number a start means the first entry of the millisecond, and an
+    // end the last one
+    assert_eq!(
+        ids(&xrange(&mut con, "stream-ids", &["5", "5"])),
+        ["5-1", "5-2"]
+    );
+    assert_eq!(
+        ids(&xrange(&mut con, "stream-ids", &["(5-1", "(9-0"])),
+        ["5-2", "7-0"]
+    );
+    assert_eq!(
+        ids(&xrange(&mut con, "stream-ids", &["-", "+", "COUNT", "2"])),
+        ["5-1", "5-2"]
+    );
+    assert!(xrange(&mut con, "stream-ids", &["9", "5"]).is_empty());
+    assert!(xrange(&mut con, "stream-missing", &["-", "+"]).is_empty());
+    let reversed = entries(
+        redis::cmd("XREVRANGE")
+            .arg("stream-ids")
+            .arg(&["+", "(5-1", "COUNT", "2"])
+            .query(&mut con)
+            .unwrap(),
+    );
+    assert_eq!(ids(&reversed), ["9-0", "7-0"]);
+
+    let error = |result: RedisResult<String>| result.unwrap_err().detail().map(String::from);
+    assert_eq!(
+        error(xadd(&mut con, "stream-ids", &["9-0", "e", "5"])).as_deref(),
+        Some("The ID specified in XADD is equal or smaller than the target stream top item")
+    );
+    assert_eq!(
+        error(xadd(&mut con, "stream-ids", &["8-*", "e", "5"])).as_deref(),
+        Some("The ID specified in XADD is equal or smaller than the target stream top item")
+    );
+    assert_eq!(
+        error(xadd(&mut con, "stream-new", &["0-0", "e", "5"])).as_deref(),
+        Some("The ID specified in XADD must be greater than 0-0")
+    );
+    assert_eq!(
+        error(xadd(&mut con, "stream-new", &["x-1", "e", "5"])).as_deref(),
+        Some("Invalid stream ID specified as stream command argument")
+    );
+    assert_eq!(
+        error(xadd(&mut con, "stream-new", &["*", "e", "5", "f"])).as_deref(),
+        Some("wrong number of arguments for 'xadd' command")
+    );
+    let kind: String = redis::cmd("TYPE")
+        .arg("stream-new")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "none");
+}
+
+#[test]
+fn it_can_trim_streams() {
+    let mut con = connect();
+    for i in 1..=10 {
+        xadd(&mut con, "stream-trim", &[&format!("{}-0", i), "n", "v"]).unwrap();
+    }
+
+    xadd(&mut con, "stream-trim", &["MAXLEN", "8", "11-0", "n", "v"]).unwrap();
+    assert_eq!(
+        ids(&xrange(&mut con, "stream-trim", &["-", "+", "COUNT", "1"])),
+        ["4-0"]
+    );
+    let xtrim = |con: &mut Connection, args: &[&str]| -> RedisResult<i64> {
+        redis::cmd("XTRIM").arg("stream-trim").arg(args).query(con)
+    };
+    assert_eq!(xtrim(&mut con, &["MINID", "6"]).unwrap(), 2);
+    assert_eq!(
+        xtrim(&mut con, &["MAXLEN", "~", "2", "LIMIT", "3"]).unwrap(),
+        3
+    );
+    assert_eq!(xtrim(&mut con, &["MAXLEN", "=", "2"]).unwrap(), 1);
+    assert_eq!(
+        ids(&xrange(&mut con, "stream-trim", &["-", "+"])),
+        ["10-0", "11-0"]
+    );
+    // A stream trimmed down to nothing stays around, and still only takes later IDs
+    assert_eq!(xtrim(&mut con, &["MAXLEN", "0"]).unwrap(), 2);
+    let len: i64 = redis::cmd("XLEN")
+        .arg("stream-trim")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(len, 0);
+    let kind: String = redis::cmd("TYPE")
+        .arg("stream-trim")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "stream");
+    assert!(xadd(&mut con, "stream-trim", &["11-0", "n", "v"]).is_err());
+
+    assert_eq!(
+        xtrim(&mut con, &["MAXLEN", "2", "LIMIT", "1"])
+            .unwrap_err()
+            .detail(),
+        Some("syntax error, LIMIT cannot be used without the special ~ option")
+    );
+    assert_eq!(
+        xtrim(&mut con, &["MAXLEN", "-1"]).unwrap_err().detail(),
+        Some("The MAXLEN argument must be >= 0.")
+    );
+    assert_eq!(
+        xtrim(&mut con, &["LENGTH", "1"]).unwrap_err().detail(),
+        Some("syntax error")
+    );
+
+    let added: Option<String> = redis::cmd("XADD")
+        .arg("stream-nomk")
+        .arg(&["NOMKSTREAM", "*", "n", "v"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(added, None);
+    let len: i64 = redis::cmd("XLEN")
+        .arg("stream-nomk")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(len, 0);
+}
+
+#[test]
+fn it_can_read_several_streams() {
+    let mut con = connect();
+    xadd(&mut con, "{stream}a", &["1-0", "n", "1"]).unwrap();
+    xadd(&mut con, "{stream}a", &["2-0", "n", "2"]).unwrap();
+    xadd(&mut con, "{stream}b", &["3-0", "n", "3"]).unwrap();
+
+    let read = streams(
+        redis::cmd("XREAD")
+            .arg(&[
+                "STREAMS",
+                "{stream}a",
+                "{stream}b",
+                "{stream}c",
+                "1",
+                "0",
+                "0",
+            ])
+            .query(&mut con)
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        read,
+        [
+            ("{stream}a".to_string(), vec![entry("2-0", &["n", "2"])]),
+            ("{stream}b".to_string(), vec![entry("3-0", &["n", "3"])]),
+        ]
+    );
+    let read = streams(
+        redis::cmd("XREAD")
+            .arg(&["COUNT", "1", "STREAMS", "{stream}a", "0-0"])
+            .query(&mut con)
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(ids(&read[0].1), ["1-0"]);
+    let read = streams(
+        redis::cmd("XREAD")
+            .arg(&["STREAMS", "{stream}a", "{stream}b", "$", "$"])
+            .query(&mut con)
+            .unwrap(),
+    );
+    assert_eq!(read, None);
+
+    let start = Instant::now();
+    let read = streams(
+        redis::cmd("XREAD")
+            .arg(&["BLOCK", "100", "STREAMS", "{stream}a", "$"])
+            .query(&mut con)
+            .unwrap(),
+    );
+    assert_eq!(read, None);
+    assert!(start.elapsed() >= Duration::from_millis(100));
+
+    let error = |con: &mut Connection, args: &[&str]| {
+        redis::cmd("XREAD")
+            .arg(args)
+            .query::<redis::Value>(con)
+            .unwrap_err()
+            .detail()
+            .map(String::from)
+    };
+    assert_eq!(
+        error(&mut con, &["STREAMS", "{stream}a", "{stream}b", "0"]).as_deref(),
+        Some(
+            "Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+        )
+    );
+    assert_eq!(
+        error(&mut con, &["BLOCK", "-1", "STREAMS", "{stream}a", "0"]).as_deref(),
+        Some("timeout is negative")
+    );
+    assert_eq!(
+        error(&mut con, &["COUNT", "1", "{stream}a", "0"]).as_deref(),
+        Some("syntax error")
+    );
+}
+
+#[test]
+fn it_wakes_every_blocked_reader_with_the_entries_added_after_it_blocked() {
+    let mut con = connect();
+    xadd(&mut con, "stream-wake", &["*", "old", "entry"]).unwrap();
+
+    let blocked_read = || {
+        thread::spawn(|| {
+            let mut con = connect();
+            redis::cmd("XREAD")
+                .arg(&["BLOCK", "5000", "STREAMS", "stream-wake", "$"])
+                .query(&mut con)
+                .map(streams)
+                .unwrap()
+                .unwrap()
+        })
+    };
+    let first = blocked_read();
+    let second = blocked_read();
+    thread::sleep(Duration::from_millis(100));
+
+    let id = xadd(&mut con, "stream-wake", &["*", "new", "entry"]).unwrap();
+    for reader in [first, second] {
+        let read = reader.join().unwrap();
+        assert_eq!(
+            read,
+            [(
+                "stream-wake".to_string(),
+                vec![entry(&id, &["new", "entry"])]
+            )]
+        );
+    }
+}
+
+#[test]
+fn it_delivers_entries_to_consumer_groups() {
+    let mut con = connect();
+    for i in 1..=4 {
+        xadd(
+            &mut con,
+            "stream-jobs",
+            &[&format!("{}-0", i), "job", &i.to_string()],
+        )
+        .unwrap();
+    }
+
+    let xgroup = |con: &mut Connection, args: &[&str]| -> RedisResult<redis::Value> {
+        redis::cmd("XGROUP").arg(args).query(con)
+    };
+    xgroup(&mut con, &["CREATE", "stream-jobs", "workers", "0"]).unwrap();
+    assert_eq!(
+        xgroup(&mut con, &["CREATE", "stream-jobs", "workers", "$"])
+            .unwrap_err()
+            .code(),
+        Some("BUSYGROUP")
+    );
+    assert_eq!(
+        xgroup(&mut con, &["CREATE", "stream-none", "workers", "$"])
+            .unwrap_err()
+            .detail(),
+        Some(
+            "The XGROUP subcommand requires the key to exist. Note that for CREATE you may want \
+             to use the MKSTREAM option to create an empty stream automatically."
+        )
+    );
+    xgroup(
+        &mut con,
+        &["CREATE", "stream-made", "workers", "$", "MKSTREAM"],
+    )
+    .unwrap();
+    let kind: String = redis::cmd("TYPE")
+        .arg("stream-made")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "stream");
+
+    let read_group = |con: &mut Connection, consumer: &str, args: &[&str]| {
+        redis::cmd("XREADGROUP")
+            .arg(&["GROUP", "workers", consumer])
+            .arg(args)
+            .query(con)
+            .map(streams)
+    };
+    // Each new entry goes to a single consumer
+    let read = read_group(
+        &mut con,
+        "alice",
+        &["COUNT", "2", "STREAMS", "stream-jobs", ">"],
+    );
+    assert_eq!(ids(&read.unwrap().unwrap()[0].1), ["1-0", "2-0"]);
+    let read = read_group(
+        &mut con,
+        "bob",
+        &["COUNT", "1", "STREAMS", "stream-jobs", ">"],
+    );
+    assert_eq!(ids(&read.unwrap().unwrap()[0].1), ["3-0"]);
+    let read = read_group(&mut con, "bob", &["NOACK", "STREAMS", "stream-jobs", ">"]);
+    assert_eq!(ids(&read.unwrap().unwrap()[0].1), ["4-0"]);
+    let read = read_group(&mut con, "bob", &["STREAMS", "stream-jobs", ">"]);
+    assert_eq!(read.unwrap(), None);
+
+    // Pending entries are read again from an ID, even when there are none
+    let read = read_group(&mut con, "alice", &["STREAMS", "stream-jobs", "0"]);
+    assert_eq!(
+        read.unwrap().unwrap(),
+        [(
+            "stream-jobs".to_string(),
+            vec![entry("1-0", &["job", "1"]), entry("2-0", &["job", "2"])]
+        )]
+    );
+    let read = read_group(&mut con, "carol", &["STREAMS", "stream-jobs", "0"]);
+    assert_eq!(
+        read.unwrap().unwrap(),
+        [("stream-jobs".to_string(), vec![])]
+    );
+
+    let (count, first, last, consumers): (i64, String, String, Vec<redis::Value>) =
+        redis::cmd("XPENDING")
+            .arg(&["stream-jobs", "workers"])
+            .query(&mut con)
+            .unwrap();
+    assert_eq!((count, first.as_str(), last.as_str()), (3, "1-0", "3-0"));
+    assert_eq!(
+        consumers
+            .iter()
+            .map(|consumer| redis::from_redis_value(consumer).unwrap())
+            .collect::<Vec<(String, String)>>(),
+        [
+            ("alice".to_string(), "2".to_string()),
+            ("bob".to_string(), "1".to_string())
+        ]
+    );
+    assert_eq!(
+        pending(
+            &mut con,
+            &["stream-jobs", "workers", "-", "+", "10", "alice"]
+        )
+        .iter()
+        .map(|(id, consumer, deliveries)| (id.as_str(), consumer.as_str(), *deliveries))
+        .collect::<Vec<_>>(),
+        [("1-0", "alice", 2), ("2-0", "alice", 2)]
+    );
+
+    let acknowledged: i64 = redis::cmd("XACK")
+        .arg(&["stream-jobs", "workers", "1-0", "3-0", "9-0"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(acknowledged, 2);
+    let removed = xgroup(
+        &mut con,
+        &["DELCONSUMER", "stream-jobs", "workers", "alice"],
+    )
+    .unwrap();
+    assert_eq!(removed, redis::Value::Int(1));
+    let (count, ..): (i64, Option<String>, Option<String>, Option<Vec<String>>) =
+        redis::cmd("XPENDING")
+            .arg(&["stream-jobs", "workers"])
+            .query(&mut con)
+            .unwrap();
+    assert_eq!(count, 0);
+
+    // SETID moves the group back, so the entries are delivered again
+    xgroup(&mut con, &["SETID", "stream-jobs", "workers", "2-0"]).unwrap();
+    let read = read_group(&mut con, "bob", &["STREAMS", "stream-jobs", ">"]);
+    assert_eq!(ids(&read.unwrap().unwrap()[0].1), ["3-0", "4-0"]);
+
+    // Nothing is read unless every stream has the group
+    let err = read_group(
+        &mut con,
+        "bob",
+        &["STREAMS", "stream-made", "stream-none", ">", ">"],
+    )
+    .unwrap_err();
+    assert_eq!(err.code(), Some("NOGROUP"));
+    assert_eq!(
+        xgroup(&mut con, &["DESTROY", "stream-jobs", "workers"]).unwrap(),
+        redis::Value::Int(1)
+    );
+    let err = read_group(&mut con, "bob", &["STREAMS", "stream-jobs", ">"]).unwrap_err();
+    assert_eq!(err.code(), Some("NOGROUP"));
+}
+
+#[test]
+fn it_can_claim_pending_entries() {
+    let mut con = connect();
+    for i in 1..=3 {
+        xadd(
+            &mut con,
+            "stream-claim",
+            &[&format!("{}-0", i), "job", &i.to_string()],
+        )
+        .unwrap();
+    }
+    let _: String = redis::cmd("XGROUP")
+        .arg(&["CREATE", "stream-claim", "workers", "0"])
+        .query(&mut con)
+        .unwrap();
+    let _: redis::Value = redis::cmd("XREADGROUP")
+        .arg(&["GROUP", "workers", "alice", "STREAMS", "stream-claim", ">"])
+        .query(&mut con)
+        .unwrap();
+
+    // Nothing has been pending for an hour
+    let claimed: Vec<String> = redis::cmd("XCLAIM")
+        .arg(&["stream-claim", "workers", "bob", "3600000", "1-0"])
+        .query(&mut con)
+        .unwrap();
+    assert!(claimed.is_empty());
+    let claimed = entries(
+        redis::cmd("XCLAIM")
+            .arg(&["stream-claim", "workers", "bob", "0", "1-0", "9-0"])
+            .query(&mut con)
+            .unwrap(),
+    );
+    assert_eq!(claimed, [entry("1-0", &["job", "1"])]);
+    let claimed: Vec<String> = redis::cmd("XCLAIM")
+        .arg(&[
+            "stream-claim",
+            "workers",
+            "bob",
+            "0",
+            "2-0",
+            "RETRYCOUNT",
+            "7",
+            "JUSTID",
+        ])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(claimed, ["2-0"]);
+    assert_eq!(
+        pending(&mut con, &["stream-claim", "workers", "-", "+", "10"])
+            .iter()
+            .map(|(id, consumer, deliveries)| (id.as_str(), consumer.as_str(), *deliveries))
+            .collect::<Vec<_>>(),
+        [("1-0", "bob", 2), ("2-0", "bob", 7), ("3-0", "alice", 1)]
+    );
+
+    // Entries trimmed away are reported as deleted and are no longer pending
+    let _: i64 = redis::cmd("XTRIM")
+        .arg(&["stream-claim", "MINID", "2"])
+        .query(&mut con)
+        .unwrap();
+    let (cursor, claimed, deleted): (String, Vec<redis::Value>, Vec<String>) =
+        redis::cmd("XAUTOCLAIM")
+            .arg(&["stream-claim", "workers", "carol", "0", "-", "COUNT", "2"])
+            .query(&mut con)
+            .unwrap();
+    assert_eq!(cursor, "3-0");
+    assert_eq!(entries(claimed), [entry("2-0", &["job", "2"])]);
+    assert_eq!(deleted, ["1-0"]);
+    let (cursor, claimed, deleted): (String, Vec<String>, Vec<String>) = redis::cmd("XAUTOCLAIM")
+        .arg(&["stream-claim", "workers", "carol", "0", &cursor, "JUSTID"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(
+        (cursor.as_str(), claimed, deleted),
+        ("0-0", vec!["3-0".to_string()], vec![])
+    );
+    let (count, ..): (i64, String, String, Vec<redis::Value>) = redis::cmd("XPENDING")
+        .arg(&["stream-claim", "workers"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(count, 2);
+
+    let err = redis::cmd("XAUTOCLAIM")
+        .arg(&["stream-claim", "workers", "carol", "0", "-", "COUNT", "0"])
+        .query::<redis::Value>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("COUNT must be > 0"));
+    let err = redis::cmd("XCLAIM")
+        .arg(&["stream-claim", "nobody", "carol", "0", "2-0"])
+        .query::<redis::Value>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("NOGROUP"));
+}
+
+#[test]
+fn it_wakes_a_consumer_blocked_on_a_group() {
+    let mut con = connect();
+    let _: String = redis::cmd("XGROUP")
+        .arg(&["CREATE", "stream-group-wake", "workers", "$", "MKSTREAM"])
+        .query(&mut con)
+        .unwrap();
+
+    let blocked = thread::spawn(|| {
+        let mut con = connect();
+        redis::cmd("XREADGROUP")
+            .arg(&["GROUP", "workers", "alice", "BLOCK", "0"])
+            .arg(&["STREAMS", "stream-group-wake", ">"])
+            .query(&mut con)
+            .map(streams)
+            .unwrap()
+            .unwrap()
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    xadd(&mut con, "stream-group-wake", &["1-0", "job", "1"]).unwrap();
+    let read = blocked.join().unwrap();
+    assert_eq!(ids(&read[0].1), ["1-0"]);
+}
+
+#[test]
+fn it_sends_xread_as_a_map_to_resp3_clients() {
+    let mut con = connect();
+    xadd(&mut con, "stream-resp3", &["1-0", "a", "1"]).unwrap();
+
+    let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+    send_raw(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n");
+    let response = send_raw(
+        &mut stream,
+        b"*4\r\n$5\r\nXREAD\r\n$7\r\nSTREAMS\r\n$12\r\nstream-resp3\r\n$1\r\n0\r\n",
+    );
+    assert_eq!(
+        response,
+        b"%1\r\n$12\r\nstream-resp3\r\n*1\r\n*2\r\n$3\r\n1-0\r\n*2\r\n$1\r\na\r\n$1\r\n1\r\n"
+    );
+}
+
+#[test]
+fn it_refuses_stream_commands_on_other_types() {
+    let mut con = connect();
+    let _: String = redis::cmd("SET")
+        .arg("stream-string")
+        .arg("value")
+        .query(&mut con)
+        .unwrap();
+    xadd(&mut con, "stream-typed", &["*", "a", "1"]).unwrap();
+
+    let err = xadd(&mut con, "stream-string", &["*", "a", "1"]).unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+    let err = redis::cmd("XREAD")
+        .arg(&["STREAMS", "stream-string", "0"])
+        .query::<redis::Value>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+    let err = redis::cmd("LPUSH")
+        .arg("stream-typed")
+        .arg("a")
+        .query::<i64>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+    let encoding: String = redis::cmd("OBJECT")
+        .arg(&["ENCODING", "stream-typed"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(encoding, "stream");
+}
@@ -0,0 +1,313 @@
+mod common;
+
+use redis::{Client, Connection};
+
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    client.get_connection().unwrap()
+}
+
+fn set(con: &mut Connection, key: &str, value: &str) {
+    let _: String = redis::cmd("SET").arg(key).arg(value).query(con).unwrap();
+}
+
+fn get(con: &mut Connection, key: &str) -> Option<String> {
+    redis::cmd("GET").arg(key).query(con).unwrap()
+}
+
+fn pttl(con: &mut Connection, key: &str) -> i64 {
+    redis::cmd("PTTL").arg(key).query(con).unwrap()
+}
+
+#[test]
+fn it_can_append_and_measure_strings() {
+    let mut con = connect();
+
+    let len: i64 = redis::cmd("APPEND")
+        .arg("string-append")
+        .arg("Hello")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(len, 5);
+    let len: i64 = redis::cmd("APPEND")
+        .arg("string-append")
+        .arg(" World")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(len, 11);
+    assert_eq!(
+        get(&mut con, "string-append").as_deref(),
+        Some("Hello World")
+    );
+
+    let strlen = |con: &mut Connection, key: &str| -> i64 {
+        redis::cmd("STRLEN").arg(key).query(con).unwrap()
+    };
+    assert_eq!(strlen(&mut con, "string-append"), 11);
+    assert_eq!(strlen(&mut con, "string-missing"), 0);
+
+    let _: i64 = redis::cmd("RPUSH")
+        .arg("string-list")
+        .arg("a")
+        .query(&mut con)
+        .unwrap();
+    let err = redis::cmd("APPEND")
+        .arg("string-list")
+        .arg("b")
+        .query::<i64>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.code(), Some("WRONGTYPE"));
+}
+
+#[test]
+fn it_can_read_and_write_ranges() {
+    let mut con = connect();
+    set(&mut con, "string-range", "This is a string");
+
+    let getrange = |con: &mut Connection, start: i64, end: i64| -> String {
+        redis::cmd("GETRANGE")
+            .arg("string-range")
+            .arg(start)
+            .arg(end)
+            .query(con)
+            .unwrap()
+    };
+    assert_eq!(getrange(&mut con, 0, 3), "This");
+    assert_eq!(getrange(&mut con, -3, -1), "ing");
+    assert_eq!(getrange(&mut con, 0, -1), "This is a string");
+    assert_eq!(getrange(&mut con, 10, 100), "string");
+    assert_eq!(getrange(&mut con, 5, 3), "");
+    // An end before the start of the string is clamped to the first byte
+    assert_eq!(getrange(&mut con, 0, -100), "T");
+    assert_eq!(getrange(&mut con, -1, -5), "");
+    let missing: String = redis::cmd("GETRANGE")
+        .arg("string-missing")
+        .arg(0)
+        .arg(-1)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(missing, "");
+
+    set(&mut con, "string-setrange", "Hello World");
+    let setrange = |con: &mut Connection, key: &str, offset: i64, value: &str| {
+        redis::cmd("SETRANGE")
+            .arg(key)
+            .arg(offset)
+            .arg(value)
+            .query::<i64>(con)
+    };
+    assert_eq!(setrange(&mut con, "string-setrange", 6, "Redis"), Ok(11));
+    assert_eq!(
+        get(&mut con, "string-setrange").as_deref(),
+        Some("Hello Redis")
+    );
+    assert_eq!(setrange(&mut con, "string-setrange", 11, "!"), Ok(12));
+    assert_eq!(
+        get(&mut con, "string-setrange").as_deref(),
+        Some("Hello Redis!")
+    );
+
+    // A missing key is padded with zero bytes, unless there's nothing to write
+    assert_eq!(setrange(&mut con, "string-padded", 3, "ab"), Ok(5));
+    let padded: Vec<u8> = redis::cmd("GET")
+        .arg("string-padded")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(padded, b"\0\0\0ab");
+    assert_eq!(setrange(&mut con, "string-empty", 3, ""), Ok(0));
+    let kind: String = redis::cmd("TYPE")
+        .arg("string-empty")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(kind, "none");
+
+    let err = setrange(&mut con, "string-setrange", -1, "x").unwrap_err();
+    assert_eq!(err.detail(), Some("offset is out of range"));
+    let err = setrange(&mut con, "string-setrange", 512 * 1024 * 1024, "x").unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("string exceeds maximum allowed size (proto-max-bulk-len)")
+    );
+}
+
+#[test]
+fn it_can_get_and_change_in_one_step() {
+    let mut con = connect();
+
+    let getset = |con: &mut Connection, value: &str| -> Option<String> {
+        redis::cmd("GETSET")
+            .arg("string-getset")
+            .arg(value)
+            .query(con)
+            .unwrap()
+    };
+    assert_eq!(getset(&mut con, "one"), None);
+    let _: i64 = redis::cmd("EXPIRE")
+        .arg("string-getset")
+        .arg(100)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(getset(&mut con, "two").as_deref(), Some("one"));
+    // Setting drops the expiry
+    assert_eq!(pttl(&mut con, "string-getset"), -1);
+
+    set(&mut con, "string-getdel", "gone");
+    let getdel = |con: &mut Connection| -> Option<String> {
+        redis::cmd("GETDEL")
+            .arg("string-getdel")
+            .query(con)
+            .unwrap()
+    };
+    assert_eq!(getdel(&mut con).as_deref(), Some("gone"));
+    assert_eq!(getdel(&mut con), None);
+
+    set(&mut con, "string-getex", "value");
+    let getex = |con: &mut Connection, options: &[&str]| {
+        redis::cmd("GETEX")
+            .arg("string-getex")
+            .arg(options)
+            .query::<Option<String>>(con)
+    };
+    assert_eq!(getex(&mut con, &[]), Ok(Some("value".to_string())));
+    assert_eq!(pttl(&mut con, "string-getex"), -1);
+    assert_eq!(
+        getex(&mut con, &["EX", "100"]),
+        Ok(Some("value".to_string()))
+    );
+    let ttl = pttl(&mut con, "string-getex");
+    assert!(ttl > 99_000 && ttl <= 100_000);
+    assert_eq!(getex(&mut con, &["PERSIST"]), Ok(Some("value".to_string())));
+    assert_eq!(pttl(&mut con, "string-getex"), -1);
+    let err = getex(&mut con, &["PX", "0"]).unwrap_err();
+    assert_eq!(err.detail(), Some("invalid expire time in 'getex' command"));
+    let err = getex(&mut con, &["EX", "1", "PERSIST"]).unwrap_err();
+    assert_eq!(err.detail(), Some("syntax error"));
+    // A time that has already passed deletes the key
+    assert_eq!(
+        getex(&mut con, &["PXAT", "1"]),
+        Ok(Some("value".to_string()))
+    );
+    assert_eq!(get(&mut con, "string-getex"), None);
+}
+
+#[test]
+fn it_can_get_and_set_many_keys() {
+    let mut con = connect();
+
+    let ok: String = redis::cmd("MSET")
+        .arg(&["{string}a", "1", "{string}b", "2", "{string}c", "3"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(ok, "OK");
+    let _: i64 = redis::cmd("RPUSH")
+        .arg("{string}list")
+        .arg("a")
+        .query(&mut con)
+        .unwrap();
+    // Keys that are missing or hold something else than a string come back as nil
+    let values: Vec<Option<String>> = redis::cmd("MGET")
+        .arg(&[
+            "{string}a",
+            "{string}missing",
+            "{string}c",
+            "{string}list",
+            "{string}b",
+        ])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(
+        values,
+        [
+            Some("1".to_string()),
+            None,
+            Some("3".to_string()),
+            None,
+            Some("2".to_string())
+        ]
+    );
+
+    let err = redis::cmd("MSET")
+        .arg(&["{string}a", "1", "{string}b"])
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("wrong number of arguments for 'mset' command")
+    );
+
+    let msetnx = |con: &mut Connection, args: &[&str]| -> i64 {
+        redis::cmd("MSETNX").arg(args).query(con).unwrap()
+    };
+    // Nothing is set when any of the keys exists
+    assert_eq!(msetnx(&mut con, &["{string}new", "x", "{string}a", "y"]), 0);
+    assert_eq!(get(&mut con, "{string}new"), None);
+    assert_eq!(get(&mut con, "{string}a").as_deref(), Some("1"));
+    assert_eq!(
+        msetnx(&mut con, &["{string}new", "x", "{string}other", "y"]),
+        1
+    );
+    assert_eq!(get(&mut con, "{string}new").as_deref(), Some("x"));
+    assert_eq!(get(&mut con, "{string}other").as_deref(), Some("y"));
+}
+
+#[test]
+fn it_can_set_conditionally_or_with_an_expiry() {
+    let mut con = connect();
+
+    let setnx = |con: &mut Connection, value: &str| -> i64 {
+        redis::cmd("SETNX")
+            .arg("string-setnx")
+            .arg(value)
+            .query(con)
+            .unwrap()
+    };
+    assert_eq!(setnx(&mut con, "first"), 1);
+    assert_eq!(setnx(&mut con, "second"), 0);
+    assert_eq!(get(&mut con, "string-setnx").as_deref(), Some("first"));
+
+    let ok: String = redis::cmd("SETEX")
+        .arg("string-setex")
+        .arg(100)
+        .arg("value")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(ok, "OK");
+    assert_eq!(get(&mut con, "string-setex").as_deref(), Some("value"));
+    let ttl = pttl(&mut con, "string-setex");
+    assert!(ttl > 99_000 && ttl <= 100_000);
+
+    let _: String = redis::cmd("PSETEX")
+        .arg("string-psetex")
+        .arg(100_000)
+        .arg("value")
+        .query(&mut con)
+        .unwrap();
+    let ttl = pttl(&mut con, "string-psetex");
+    assert!(ttl > 99_000 && ttl <= 100_000);
+
+    let err = redis::cmd("SETEX")
+        .arg("string-setex")
+        .arg(0)
+        .arg("value")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("invalid expire time in 'setex' command"));
+    let err = redis::cmd("PSETEX")
+        .arg("string-psetex")
+        .arg(-5)
+        .arg("value")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(
+        err.detail(),
+        Some("invalid expire time in 'psetex' command")
+    );
+    // Seconds that overflow once turned into milliseconds are refused the same way SET does
+    let err = redis::cmd("SETEX")
+        .arg("string-setex")
+        .arg(i64::MAX)
+        .arg("value")
+        .query::<String>(&mut con)
+        .unwrap_err();
+    assert_eq!(err.detail(), Some("invalid expire time in 'setex' command"));
+}
@@ -0,0 +1,544 @@
+mod common;
+
+use common::send_raw;
+use redis::{Client, Connection, FromRedisValue, RedisResult};
+use std::net::TcpStream;
+
+fn connect() -> Connection {
+    let client = Client::open("redis://127.0.0.1/").unwrap();
+    client.get_connection().unwrap()
+}
+
+fn zadd<T: FromRedisValue>(con: &mut Connection, key: &str, args: &[&str]) -> RedisResult<T> {
+    redis::cmd("ZADD").arg(key).arg(args).query(con)
+}
+
+fn zrange(con: &mut Connection, key: &str, args: &[&str]) -> Vec<String> {
+    redis::cmd("ZRANGE").arg(key).arg(args).query(con).unwrap()
+}
+
+#[test]
+fn it_can_add_and_update_members() {
+    let mut con = connect();
+
+    let added: i64 = zadd(
+        &mut con,
+        "zset-board",
+        &["10", "ann", "20", "bob", "15", "cat"],
+    )
+    .unwrap();
+    assert_eq!(added, 3);
+    // bob moves, dan is new
+    let added: i64 = zadd(&mut con, "zset-board", &["5", "bob", "30", "dan"]).unwrap();
+    assert_eq!(added, 1);
+    let changed: i64 = zadd(
+        &mut con,
+        "zset-board",
+        &["CH", "6", "bob", "30", "dan", "1", "eve"],
+    )
+    .unwrap();
+    assert_eq!(changed, 2);
+    assert_eq!(
+        zrange(&mut con, "zset-board", &["0", "-1"]),
+        ["eve", "bob", "ann", "cat", "dan"]
+    );
+
+    // NX only adds, XX only updates
+    zadd::<i64>(&mut con, "zset-board", &["NX", "100", "ann", "2", "fay"]).unwrap();
+    zadd::<i64>(&mut con, "zset-board", &["XX", "3", "eve", "100", "gus"]).unwrap();
+    // GT and LT only move members up or down
+    zadd::<i64>(&mut con, "zset-board", &["GT", "1", "cat", "40", "dan"]).unwrap();
+    zadd::<i64>(&mut con, "zset-board", &["LT", "50", "ann", "4", "eve"]).unwrap();
+    assert_eq!(
+        zrange(&mut con, "zset-board", &["0", "-1", "WITHSCORES"]),
+        ["fay", "2", "eve", "3", "bob", "6", "ann", "10", "cat", "15", "dan", "40"]
+    );
+
+    let score: Option<String> = zadd(&mut con, "zset-board", &["INCR", "2.5", "ann"]).unwrap();
+    assert_eq!(score.as_deref(), Some("12.5"));
+    let score: Option<String> = zadd(&mut con, "zset-board", &["NX", "INCR", "1", "ann"]).unwrap();
+    assert_eq!(score, None);
+    let score: String = redis::cmd("ZINCRBY")
+        .arg("zset-board")
+        .arg("-0.5")
+        .arg("ann")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(score, "12");
+    let score: String = redis::cmd("ZINCRBY")
+        .arg("zset-board")
+        .arg("+inf")
+        .arg("new")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(score, "inf");
+    // Very large and very small scores are written with an exponent, as %.17g would
+    zadd::<i64>(
+        &mut con,
+        "zset-exponents",
+        &[
+            "1e21", "big", "1e-7", "small", "0.0001", "tenth", "-1.5e300", "low",
+        ],
+    )
+    .unwrap();
+    assert_eq!(
+        zrange(&mut con, "zset-exponents", &["0", "-1", "WITHSCORES"]),
+        [
+            "low",
+            "-1.5e+300",
+            "small",
+            "1e-07",
+            "tenth",
+            "0.0001",
+            "big",
+            "1e+21"
+        ]
+    );
+    let score: String = redis::cmd("ZINCRBY")
+        .arg("zset-exponents")
+        .arg("1e21")
+        .arg("big")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(score, "2e+21");
+    let score: String = redis::cmd("ZSCORE")
+        .arg("zset-exponents")
+        .arg("small")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(score, "1e-07");
+    let score: Option<String> = redis::cmd("ZSCORE")
+        .arg("zset-board")
+        .arg("missing")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(score, None);
+
+    let error = |result: RedisResult<redis::Value>| result.unwrap_err().detail().map(String::from);
+    assert_eq!(
+        error(zadd(&mut con, "zset-board", &["NX", "XX", "1", "a"])).as_deref(),
+        Some("XX and NX options at the same time are not compatible")
+    );
+    assert_eq!(
+        error(zadd(&mut con, "zset-board", &["GT", "LT", "1", "a"])).as_deref(),
+        Some("GT, LT, and/or NX options at the same time are not compatible")
+    );
+    assert_eq!(
+        error(zadd(&mut con, "zset-board", &["INCR", "1", "a", "2", "b"])).as_deref(),
+        Some("INCR option supports a single increment-element pair")
+    );
+    assert_eq!(
+        error(zadd(&mut con, "zset-board", &["high", "a"])).as_deref(),
+        Some("value is not a valid float")
+    );
+    assert_eq!(
+        error(zadd(&mut con, "zset-board", &["INCR", "-inf", "new"])).as_deref(),
+        Some("resulting score is not a number (NaN)")
+    );
+    assert_eq!(
+        error(zadd(&mut con, "zset-board", &["1", "a", "2"])).as_deref(),
+        Some("syntax error")
+    );
+}
+
+#[test]
+fn it_can_read_ranges_by_rank_score_and_lex() {
+    let mut con = connect();
+    zadd::<i64>(
+        &mut con,
+        "zset-range",
+        &["1", "a", "2", "b", "2", "c", "3", "d", "4.5", "e"],
+    )
+    .unwrap();
+
+    assert_eq!(zrange(&mut con, "zset-range", &["1", "2"]), ["b", "c"]);
+    assert_eq!(zrange(&mut con, "zset-range", &["-2", "100"]), ["d", "e"]);
+    assert_eq!(
+        zrange(&mut con, "zset-range", &["0", "1", "REV"]),
+        ["e", "d"]
+    );
+    assert!(zrange(&mut con, "zset-range", &["3", "1"]).is_empty());
+
+    assert_eq!(
+        zrange(&mut con, "zset-range", &["2", "3", "BYSCORE"]),
+        ["b", "c", "d"]
+    );
+    assert_eq!(
+        zrange(
+            &mut con,
+            "zset-range",
+            &["(2", "+inf", "BYSCORE", "WITHSCORES"]
+        ),
+        ["d", "3", "e", "4.5"]
+    );
+    assert_eq!(
+        zrange(&mut con, "zset-range", &["(4.5", "-inf", "BYSCORE", "REV"]),
+        ["d", "c", "b", "a"]
+    );
+    assert_eq!(
+        zrange(
+            &mut con,
+            "zset-range",
+            &["-inf", "inf", "BYSCORE", "LIMIT", "1", "2"]
+        ),
+        ["b", "c"]
+    );
+    assert_eq!(
+        zrange(
+            &mut con,
+            "zset-range",
+            &["-inf", "inf", "BYSCORE", "LIMIT", "3", "-1"]
+        ),
+        ["d", "e"]
+    );
+    assert!(zrange(
+        &mut con,
+        "zset-range",
+        &["-inf", "inf", "BYSCORE", "LIMIT", "-1", "2"]
+    )
+    .is_empty());
+
+    zadd::<i64>(
+        &mut con,
+        "zset-lex",
+        &["0", "apple", "0", "banana", "0", "cherry", "0", "date"],
+    )
+    .unwrap();
+    assert_eq!(
+        zrange(&mut con, "zset-lex", &["[banana", "(date", "BYLEX"]),
+        ["banana", "cherry"]
+    );
+    assert_eq!(
+        zrange(&mut con, "zset-lex", &["+", "(banana", "BYLEX", "REV"]),
+        ["date", "cherry"]
+    );
+    assert_eq!(
+        zrange(
+            &mut con,
+            "zset-lex",
+            &["-", "+", "BYLEX", "LIMIT", "1", "1"]
+        ),
+        ["banana"]
+    );
+
+    let error = |con: &mut Connection, args: &[&str]| {
+        redis::cmd("ZRANGE")
+            .arg("zset-range")
+            .arg(args)
+            .query::<Vec<String>>(con)
+            .unwrap_err()
+            .detail()
+            .map(String::from)
+    };
+    assert_eq!(
+        error(&mut con, &["a", "2", "BYSCORE"]).as_deref(),
+        Some("min or max is not a float")
+    );
+    assert_eq!(
+        error(&mut con, &["a", "b", "BYLEX"]).as_deref(),
+        Some("min or max not valid string range item")
+    );
+    assert_eq!(
+        error(&mut con, &["0", "1", "LIMIT", "0", "1"]).as_deref(),
+        Some("syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX")
+    );
+    assert_eq!(
+        error(&mut con, &["-", "+", "BYLEX", "WITHSCORES"]).as_deref(),
+        Some("syntax error, WITHSCORES not supported in combination with BYLEX")
+    );
+}
+
+#[test]
+fn it_can_rank_remove_and_pop_members() {
+    let mut con = connect();
+    zadd::<i64>(
+        &mut con,
+        "zset-pop",
+        &["3", "c", "1", "a", "2", "b", "4", "d"],
+    )
+    .unwrap();
+
+    let rank: i64 = redis::cmd("ZRANK")
+        .arg("zset-pop")
+        .arg("c")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(rank, 2);
+    let (rank, score): (i64, String) = redis::cmd("ZRANK")
+        .arg("zset-pop")
+        .arg("d")
+        .arg("WITHSCORE")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!((rank, score.as_str()), (3, "4"));
+    let rank: Option<i64> = redis::cmd("ZRANK")
+        .arg("zset-pop")
+        .arg("z")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(rank, None);
+
+    let removed: i64 = redis::cmd("ZREM")
+        .arg("zset-pop")
+        .arg(&["b", "z"])
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(removed, 1);
+    let card: i64 = redis::cmd("ZCARD").arg("zset-pop").query(&mut con).unwrap();
+    assert_eq!(card, 3);
+
+    let lowest: Vec<String> = redis::cmd("ZPOPMIN")
+        .arg("zset-pop")
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(lowest, ["a", "1"]);
+    let highest: Vec<String> = redis::cmd("ZPOPMAX")
+        .arg("zset-pop")
+        .arg(5)
+        .query(&mut con)
+        .unwrap();
+    assert_eq!(highest, ["d", "4", "c", "3"]);
+    // Popping the last member removes the key
+    let kind: String = redis::cmd("TYPE").arg("zset-pop").query(&mut con).unwrap();
+    assert_eq!(kind, "none");
+    let nothing: Vec<String> = redis::cmd("ZPOPMIN")
+        .arg("zset-pop")
+        .query(&mut con)
+        .unwrap();
+    assert!(nothing.is_empty());
+}
+
+#[test]
+fn it_keeps_ranks_and_ranges_right_in_a_large_sorted_set() {
+    let mut con = connect();
+
+    // Scores repeat, so members with the same score are ordered by their bytes
+    let mut expected: Vec<(i64, String)> = (0..1000)
+        .map(|i| ((i * 7919) % 101, format!("m{:04}", i)))
+        .collect();
+    for chunk in expected.chunks(100) {
+        let args: Vec<String> = chunk
+            .iter()
+            .flat_map(|(score, member)| [score.to_string(), member.clone()])
+            .collect();
+        redis::cmd("ZADD")
+            .arg("zset-large")
+            .arg(&args)
+            .query::<i64>(&mut con)
+            .unwrap();
+    }
+    // Move some members and remove others, which relinks the levels around them
+    for (score, member) in expected.iter_mut().step_by(5) {
+        *score = -*score;
+        redis::cmd("ZADD")
+            .arg("zset-large")
+            .arg(*score)
+            .arg(&*member)
+            .query::<i64>(&mut con)
+            .unwrap();
+    }
+    let removed: Vec<String> = expected
+        .iter()
+        .skip(1)
+        .step_by(3)
+        .map(|(_, member)| member.clone())
+        .collect();
+    redis::cmd("ZREM")
+        .arg("zset-large")
+        .arg(&removed)
+        .query::<i64>(&mut con)
+        .unwrap();
+    expected.retain(|(_, member)| !removed.contains(member));
+    expected.sort();
+    let members: Vec<String> = expected.iter().map(|(_, member)| member.clone()).collect();
+
+    for (rank, member) in members.iter().enumerate() {
+        let found: i64 = redis::cmd("ZRANK")
+            .arg("zset-large")
+            .arg(member)
+            .query(&mut con)
+            .unwrap();
+        assert_eq!(found, rank as i64);
+    }
+    assert_eq!(zrange(&mut con, "zset-large", &["0", "-1"]), members);
+    assert_eq!(
+        zrange(&mut con, "zset-large", &["250", "259"]),
+        &members[250..260]
+    );
+    let mut reversed = members.clone();
+    reversed.reverse();
+    assert_eq!(
+        zrange(&mut con, "zset-large", &["10", "19", "REV"]),
+        &reversed[10..20]
+    );
+
+    // Scores from -10 to 10, with and without skipping some of them
+    let between: Vec<String> = expected
+        .iter()
+        .filter(|(score, _)| (-10..=10).contains(score))
+        .map(|(_, member)| member.clone())
+        .collect();
+    assert_eq!(
+        zrange(&mut con, "zset-large", &["-10", "10", "BYSCORE"]),
+        between
+    );
+    assert_eq!(
+        zrange(
+            &mut con,
+            "zset-large",
+            &["-10", "10", "BYSCORE", "LIMIT", "5", "7"]
+        ),
+        &between[5..12]
+    );
+    let below: Vec<String> = expected
+        .iter()
+        .rev()
+        .filter(|(score, _)| (-10..10).contains(score))
+        .map(|(_, member)| member.clone())
+        .collect();
+    assert_eq!(
+        zrange(
+            &mut con,
+            "zset-large",
+            &["(10", "-10", "BYSCORE", "REV", "LIMIT", "2", "4"]
+        ),
+        &below[2..6]
+    );
+}
+
+#[test]
+fn it_can_store_unions_and_intersections() {
+    let mut con = connect();
+    zadd::<i64>(&mut con, "{zset}a", &["1", "x", "2", "y", "3", "z"]).unwrap();
+    zadd::<i64>(&mut con, "{zset}b", &["10", "y", "20", "z", "30", "w"]).unwrap();
+    let _: i64 = redis::cmd("SADD")
+        .arg("{zset}set")
+        .arg(&["z", "v"])
+        .query(&mut con)
+        .unwrap();
+
+    let store = |con: &mut Connection, command: &str, args: &[&str]| -> i64 {
+        redis::cmd(command).arg(args).query(con).unwrap()
+    };
+    assert_eq!(
+        store(
+            &mut con,
+            "ZUNIONSTORE",
+            &["{zset}out", "2", "{zset}a", "{zset}b"]
+        ),
+        4
+    );
+    assert_eq!(
+        zrange(&mut con, "{zset}out", &["0", "-1", "WITHSCORES"]),
+        ["x", "1", "y", "12", "z", "23", "w", "30"]
+    );
+    assert_eq!(
+        store(
+            &mut con,
+            "ZUNIONSTORE",
+            &[
+                "{zset}out",
+                "2",
+                "{zset}a",
+                "{zset}b",
+                "WEIGHTS",
+                "2",
+                "0.5",
+                "AGGREGATE",
+                "MAX"
+            ]
+        ),
+        4
+    );
+    assert_eq!(
+        zrange(&mut con, "{zset}out", &["0", "-1", "WITHSCORES"]),
+        ["x", "2", "y", "5", "z", "10", "w", "15"]
+    );
+    assert_eq!(
+        store(
+            &mut con,
+            "ZINTERSTORE",
+            &[
+                "{zset}out",
+                "3",
+                "{zset}a",
+                "{zset}b",
+                "{zset}set",
+                "AGGREGATE",
+                "MIN"
+            ]
+        ),
+        1
+    );
+    assert_eq!(
+        zrange(&mut con, "{zset}out", &["0", "-1", "WITHSCORES"]),
+        ["z", "1"]
+    );
+    // The destination may be one of the sources
+    assert_eq!(
+        store(
+            &mut con,
+            "ZINTERSTORE",
+            &["{zset}a", "2", "{zset}a", "{zset}b"]
+        ),
+        2
+    );
+    assert_eq!(
+        zrange(&mut con, "{zset}a", &["0", "-1", "WITHSCORES"]),
+        ["y", "12", "z", "23"]
+    );
+    // An empty result deletes the destination
+    assert_eq!(
+        store(
+            &mut con,
+            "ZINTERSTORE",
+            &["{zset}out", "2", "{zset}a", "{zset}missing"]
+        ),
+        0
+    );
+    let kind: String = redis::cmd("TYPE").arg("{zset}out").query(&mut con).unwrap();
+    assert_eq!(kind, "none");
+
+    let error = |con: &mut Connection, args: &[&str]| {
+        redis::cmd("ZUNIONSTORE")
+            .arg(args)
+            .query::<i64>(con)
+            .unwrap_err()
+            .detail()
+            .map(String::from)
+    };
+    assert_eq!(
+        error(&mut con, &["{zset}out", "0", "{zset}a"]).as_deref(),
+        Some("at least 1 input key is needed for 'zunionstore' command")
+    );
+    assert_eq!(
+        error(&mut con, &["{zset}out", "3", "{zset}a", "{zset}b"]).as_deref(),
+        Some("syntax error")
+    );
+    assert_eq!(
+        error(&mut con, &["{zset}out", "1", "{zset}a", "WEIGHTS", "x"]).as_deref(),
+        Some("weight value is not a float")
+    );
+}
+
+#[test]
+fn it_sends_scores_as_doubles_to_resp3_clients() {
+    let mut con = connect();
+    zadd::<i64>(&mut con, "zset-resp3", &["1.5", "a", "2", "b"]).unwrap();
+
+    let mut stream = TcpStream::connect("127.0.0.1:6379").unwrap();
+    send_raw(&mut stream, b"*2\r\n$5\r\nHELLO\r\n$1\r\n3\r\n");
+    let response = send_raw(
+        &mut stream,
+        b"*5\r\n$6\r\nZRANGE\r\n$10\r\nzset-resp3\r\n$1\r\n0\r\n$2\r\n-1\r\n$10\r\nWITHSCORES\r\n",
+    );
+    assert_eq!(
+        response,
+        b"*2\r\n*2\r\n$1\r\na\r\n,1.5\r\n*2\r\n$1\r\nb\r\n,2\r\n"
+    );
+    let response = send_raw(
+        &mut stream,
+        b"*3\r\n$6\r\nZSCORE\r\n$10\r\nzset-resp3\r\n$1\r\nb\r\n",
+    );
+    assert_eq!(response, b",2\r\n");
+}